@@ -0,0 +1,62 @@
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+/// A marker in a WAV file, with a label for editors to show.
+pub struct Cue {
+    /// Sample frame the marker is at.
+    pub position: u32,
+    pub label: String,
+}
+
+/// Appends a `cue ` chunk and a `LIST` of `labl`s for `cues` to a finalized WAV file, and fixes
+/// up the size in its RIFF header.
+pub fn append_cues(path: &Path, cues: &[Cue]) -> anyhow::Result<()> {
+    if cues.is_empty() {
+        return Ok(());
+    }
+
+    let mut chunks = Vec::new();
+    chunks.extend(b"cue ");
+    chunks.write_u32::<LittleEndian>(4 + 24 * cues.len() as u32)?;
+    chunks.write_u32::<LittleEndian>(cues.len() as u32)?;
+    for (id, cue) in (1..).zip(cues) {
+        chunks.write_u32::<LittleEndian>(id)?;
+        chunks.write_u32::<LittleEndian>(cue.position)?;
+        chunks.extend(b"data");
+        // Chunk and block start, both zero for a plain data chunk
+        chunks.write_u32::<LittleEndian>(0)?;
+        chunks.write_u32::<LittleEndian>(0)?;
+        chunks.write_u32::<LittleEndian>(cue.position)?;
+    }
+
+    let mut labels = b"adtl".to_vec();
+    for (id, cue) in (1..).zip(cues) {
+        let text = cue.label.as_bytes();
+        labels.extend(b"labl");
+        labels.write_u32::<LittleEndian>(4 + text.len() as u32 + 1)?;
+        labels.write_u32::<LittleEndian>(id)?;
+        labels.extend(text);
+        labels.push(0);
+        // Chunks start on even offsets
+        if labels.len() % 2 == 1 {
+            labels.push(0);
+        }
+    }
+    chunks.extend(b"LIST");
+    chunks.write_u32::<LittleEndian>(labels.len() as u32)?;
+    chunks.extend(labels);
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let end = file.seek(SeekFrom::End(0))?;
+    if end % 2 == 1 {
+        file.write_all(&[0])?;
+    }
+    file.write_all(&chunks)?;
+    let length = file.stream_position()?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_u32::<LittleEndian>((length - 8) as u32)?;
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use crate::events::{EventBus, EventKind};
+
+use super::{AudioFrame, AudioSink};
+
+const ROWS: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const COLUMNS: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+
+/// Length of the blocks tones are looked for in. A key has to be held for two blocks in a row.
+const BLOCK_SECONDS: f32 = 0.02;
+
+/// Share of a block's energy each of the row and column tones must have, so that speech and noise
+/// don't pass for a key.
+const MIN_SHARE: f32 = 0.2;
+
+/// The strongest row and column tones must be this much above the next strongest of theirs.
+const MIN_RATIO: f32 = 4.0;
+
+/// Blocks quieter than this, in RMS, are taken for silence.
+const MIN_LEVEL: f32 = 100.0;
+
+/// Keys pressed with less than this in between make up one sequence, published when it ends.
+const SEQUENCE_GAP_SECONDS: f32 = 1.0;
+
+/// Power at `frequency` in `samples`, by the Goertzel algorithm, scaled so that a lone sine wave
+/// has as much as the whole block.
+fn goertzel(samples: &[f32], frequency: f32, sample_rate: f32) -> f32 {
+    let coefficient = 2.0 * (std::f32::consts::TAU * frequency / sample_rate).cos();
+    let (mut previous, mut before) = (0.0f32, 0.0f32);
+    for sample in samples {
+        let current = sample + coefficient * previous - before;
+        before = previous;
+        previous = current;
+    }
+    let power = previous * previous + before * before - coefficient * previous * before;
+    power * 2.0 / samples.len() as f32
+}
+
+/// Index of the strongest of `powers`, if it stands out from the rest.
+fn strongest(powers: &[f32; 4], energy: f32) -> Option<usize> {
+    let (index, &best) = powers
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    let others = powers
+        .iter()
+        .enumerate()
+        .filter(|(other, _)| *other != index)
+        .map(|(_, power)| *power)
+        .fold(0.0, f32::max);
+    (best >= MIN_SHARE * energy && best >= MIN_RATIO * others).then_some(index)
+}
+
+/// Decodes touch tones, publishing each sequence of keys, like `0412#`, as a `Decoded` event once
+/// the keys stop.
+pub struct DtmfDecoder {
+    name: String,
+    events: EventBus,
+    sample_rate: u32,
+    /// Samples left over from the last frame, short of a block.
+    pending: Vec<f32>,
+    /// Key heard in the last block.
+    last: Option<char>,
+    /// Key being held, already added to the sequence.
+    held: Option<char>,
+    sequence: String,
+    /// Blocks since the last key was let go.
+    quiet_blocks: usize,
+}
+
+impl DtmfDecoder {
+    pub fn new(name: String, events: EventBus) -> Self {
+        DtmfDecoder {
+            name,
+            events,
+            sample_rate: 0,
+            pending: Vec::new(),
+            last: None,
+            held: None,
+            sequence: String::new(),
+            quiet_blocks: 0,
+        }
+    }
+
+    fn key(&self, block: &[f32]) -> Option<char> {
+        let energy = block.iter().map(|sample| sample * sample).sum::<f32>();
+        if (energy / block.len() as f32).sqrt() < MIN_LEVEL {
+            return None;
+        }
+        let rate = self.sample_rate as f32;
+        let rows = ROWS.map(|frequency| goertzel(block, frequency, rate));
+        let columns = COLUMNS.map(|frequency| goertzel(block, frequency, rate));
+        Some(KEYS[strongest(&rows, energy)?][strongest(&columns, energy)?])
+    }
+
+    fn block(&mut self, block: &[f32]) {
+        let key = self.key(block);
+        if key.is_some() && key == self.last && key != self.held {
+            self.held = key;
+            self.sequence.extend(key);
+        } else if key.is_none() && self.last.is_none() {
+            self.held = None;
+        }
+        self.last = key;
+
+        if self.held.is_some() {
+            self.quiet_blocks = 0;
+            return;
+        }
+        self.quiet_blocks += 1;
+        if self.quiet_blocks as f32 * BLOCK_SECONDS >= SEQUENCE_GAP_SECONDS {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.sequence.is_empty() {
+            return;
+        }
+        let text = std::mem::take(&mut self.sequence);
+        log::info!("{}: DTMF {}", self.name, text);
+        self.events.publish(
+            &self.name,
+            EventKind::Decoded {
+                decoder: "Dtmf".to_string(),
+                text,
+            },
+        );
+    }
+}
+
+impl AudioSink for DtmfDecoder {
+    fn write(&mut self, frame: &AudioFrame) -> anyhow::Result<()> {
+        if frame.sample_rate != self.sample_rate {
+            self.sample_rate = frame.sample_rate;
+            self.pending.clear();
+        }
+        let block_length = (self.sample_rate as f32 * BLOCK_SECONDS) as usize;
+        if block_length == 0 {
+            return Ok(());
+        }
+
+        self.pending
+            .extend(frame.samples.iter().map(|&sample| sample as f32));
+        let mut blocks = std::mem::take(&mut self.pending);
+        let mut chunks = blocks.chunks_exact(block_length);
+        for block in &mut chunks {
+            self.block(block);
+        }
+        let remainder = chunks.remainder().len();
+        blocks.drain(..blocks.len() - remainder);
+        self.pending = blocks;
+        Ok(())
+    }
+
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.pending.clear();
+        self.last = None;
+        self.held = None;
+        self.quiet_blocks = 0;
+        self.flush();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::sdr::Tuning;
+
+    const RATE: u32 = 12000;
+
+    fn tone(key: char, seconds: f32) -> Vec<i16> {
+        let (row, column) = KEYS
+            .iter()
+            .enumerate()
+            .find_map(|(row, keys)| Some((row, keys.iter().position(|k| *k == key)?)))
+            .unwrap();
+        (0..(seconds * RATE as f32) as usize)
+            .map(|i| {
+                let t = i as f32 / RATE as f32;
+                let wave = (std::f32::consts::TAU * ROWS[row] * t).sin()
+                    + (std::f32::consts::TAU * COLUMNS[column] * t).sin();
+                (wave * 8000.0) as i16
+            })
+            .collect()
+    }
+
+    fn frame(samples: &[i16]) -> AudioFrame {
+        AudioFrame {
+            samples: Arc::from(samples),
+            sample_rate: RATE,
+            timestamp: Utc::now(),
+            tuning: Tuning::USB {
+                low_cut: 300,
+                high_cut: 2700,
+                frequency: 7_100_000.0,
+            },
+            rssi: None,
+        }
+    }
+
+    #[test]
+    fn decodes_a_sequence() {
+        let events = EventBus::new();
+        let mut receiver = events.subscribe();
+        let mut decoder = DtmfDecoder::new("test".to_string(), events.clone());
+
+        let mut audio = Vec::new();
+        for key in "0412#*".chars() {
+            audio.extend(tone(key, 0.08));
+            audio.extend(vec![0; (0.06 * RATE as f32) as usize]);
+        }
+        audio.extend(vec![0; (1.5 * RATE as f32) as usize]);
+        // Frames that don't line up with the blocks
+        for samples in audio.chunks(1000) {
+            decoder.write(&frame(samples)).unwrap();
+        }
+
+        match receiver.try_recv().unwrap().kind {
+            EventKind::Decoded { text, .. } => assert_eq!(text, "0412#*"),
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn ignores_single_tones_and_noise() {
+        let events = EventBus::new();
+        let mut receiver = events.subscribe();
+        let mut decoder = DtmfDecoder::new("test".to_string(), events.clone());
+
+        let single = (0..RATE)
+            .map(|i| {
+                ((std::f32::consts::TAU * 697.0 * i as f32 / RATE as f32).sin() * 8000.0) as i16
+            })
+            .collect::<Vec<i16>>();
+        let mut rng = StdRng::seed_from_u64(27);
+        let noise = (0..RATE)
+            .map(|_| rng.gen::<i16>() / 4)
+            .collect::<Vec<i16>>();
+        decoder.write(&frame(&single)).unwrap();
+        decoder.write(&frame(&noise)).unwrap();
+        decoder.close().unwrap();
+
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -1,85 +1,483 @@
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use byteorder::ReadBytesExt;
-use chrono::Utc;
-use std::boxed::Box;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 
+use crate::{
+    config::{SquelchConfig, SquelchMode},
+    events::{EventBus, EventKind},
+    sdr::Tuning,
+};
+
+mod cue;
+mod dtmf;
 pub mod ima_adpcm;
+mod pool;
+mod process;
+mod sink;
+mod squelch;
+mod stream;
+mod udp;
+mod vad;
+
+use cue::Cue;
+pub use dtmf::DtmfDecoder;
+pub use pool::{OverflowPolicy, WriterHandle, WriterPool};
+pub use sink::{build_sinks, AudioFrame, AudioSink};
+pub use squelch::{Gate, Squelch};
+pub use stream::{pcm_bytes, wav_stream_header, AudioStream};
+pub use udp::UdpSink;
+pub use vad::VoiceDetector;
+
+/// Expands a file name template. `{name}`, `{frequency}` (in kHz) and `{mode}` are replaced first, and
+/// the result is then run through `strftime` with the given time. A `%` in the substituted values is
+/// kept as it is.
+pub fn render_template(
+    template: &str,
+    name: &str,
+    tuning: &Tuning,
+    time: DateTime<Utc>,
+) -> anyhow::Result<String> {
+    use std::fmt::Write;
 
+    let escape = |value: &str| value.replace('%', "%%");
+    let template = template
+        .replace("{name}", &escape(name))
+        .replace(
+            "{frequency}",
+            &escape(&format!("{:.0}", tuning.frequency() / 1000.0)),
+        )
+        .replace("{mode}", &escape(tuning.mode()));
+
+    let mut rendered = String::new();
+    write!(rendered, "{}", time.format(&template))
+        .map_err(|_| anyhow::anyhow!("invalid file name template: {}", template))?;
+    Ok(rendered)
+}
+
+/// A transmission in a recording, as found by the squelch.
+#[derive(Debug, Serialize)]
+struct Segment {
+    start: DateTime<Utc>,
+    /// Unset if the file was closed while it went on.
+    end: Option<DateTime<Utc>>,
+    /// Where it is in the file, in seconds.
+    offset: f64,
+    duration: f64,
+    #[serde(skip)]
+    position: u32,
+}
+
+/// What goes into the `.json` next to a squelched recording.
+#[derive(Serialize)]
+struct SegmentIndex<'a> {
+    station: &'a str,
+    segments: &'a [Segment],
+}
+
+/// Records audio into WAV files, starting a new file every `rotate`. With a squelch,
+/// transmissions are listed in a `.json` next to each file and, depending on its mode, get
+/// files of their own or are marked with cues.
 pub struct Writer {
     name: String,
-    dir: std::path::PathBuf,
+    dir: PathBuf,
+    template: String,
+    rotate: Duration,
+    events: EventBus,
     wav_writer: Option<hound::WavWriter<std::io::BufWriter<std::fs::File>>>,
-    decoder: ima_adpcm::IMA_ADPCM_Decoder,
+    path: PathBuf,
     sample_rate: u32,
     start: Instant,
+    squelch: Option<Squelch>,
+    /// Samples written to the open file.
+    position: u32,
+    segments: Vec<Segment>,
+    /// Start of a transmission to add once the file it goes into is open, and how many of the
+    /// samples already written belong to it.
+    pending_segment: Option<(DateTime<Utc>, u32)>,
+    /// A transmission went on when the last file was closed, and carries over into the next.
+    carry_over: bool,
 }
 
 impl Writer {
-    pub fn new(name: String, dir: &std::path::Path) -> Self {
+    pub fn new(
+        name: String,
+        dir: &Path,
+        template: String,
+        rotate: Duration,
+        events: EventBus,
+    ) -> Self {
         Writer {
-            name: name,
+            name,
             dir: dir.to_path_buf(),
+            template,
+            rotate,
+            events,
             wav_writer: None,
+            path: PathBuf::new(),
             sample_rate: 12000,
-            decoder: ima_adpcm::IMA_ADPCM_Decoder::new(),
             start: Instant::now(),
+            squelch: None,
+            position: 0,
+            segments: Vec::new(),
+            pending_segment: None,
+            carry_over: false,
         }
     }
 
-    pub fn set_sample_rate(&mut self, sample_rate: u32) {
-        self.sample_rate = sample_rate;
+    pub fn with_squelch(mut self, config: Option<SquelchConfig>) -> Self {
+        self.squelch = config.map(Squelch::new);
+        self
     }
 
-    fn open(&mut self, path: &std::path::Path) {
+    fn open(&mut self, frame: &AudioFrame) -> anyhow::Result<()> {
+        let path = render_template(&self.template, &self.name, &frame.tuning, frame.timestamp)?;
+
         self.start = Instant::now();
-        let file = std::fs::File::create(self.dir.join(path)).unwrap();
-        self.wav_writer = Some(
-            hound::WavWriter::new(
-                std::io::BufWriter::new(file),
-                hound::WavSpec {
-                    channels: 1,
-                    sample_rate: self.sample_rate,
-                    bits_per_sample: 16,
-                    sample_format: hound::SampleFormat::Int,
-                },
-            )
-            .unwrap(),
+        self.sample_rate = frame.sample_rate;
+        self.position = 0;
+        self.path = self.dir.join(path);
+        let file = std::fs::File::create(&self.path)?;
+        self.wav_writer = Some(hound::WavWriter::new(
+            std::io::BufWriter::new(file),
+            hound::WavSpec {
+                channels: 1,
+                sample_rate: self.sample_rate,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            },
+        )?);
+
+        self.events.publish(
+            &self.name,
+            EventKind::FileOpened {
+                path: self.path.display().to_string(),
+            },
         );
-        self.decoder = ima_adpcm::IMA_ADPCM_Decoder::new();
+        Ok(())
     }
 
-    pub fn write_samples(&mut self, samples: &Vec<u8>) {
+    fn record(&mut self, frame: &AudioFrame) -> anyhow::Result<()> {
+        if self.wav_writer.is_some() && frame.sample_rate != self.sample_rate {
+            self.close()?;
+        }
         if self.wav_writer.is_none() {
-            self.open(std::path::Path::new(
-                format!("{}_{}.wav", self.name, Utc::now().format("%Y%m%d_%H%M%S")).as_str(),
-            ));
+            self.open(frame)?;
+        }
+        let carried = std::mem::take(&mut self.carry_over).then_some((frame.timestamp, 0));
+        if let Some((start, written)) = self.pending_segment.take().or(carried) {
+            self.segments.push(Segment {
+                start,
+                end: None,
+                offset: 0.0,
+                duration: 0.0,
+                position: self.position.saturating_sub(written),
+            });
         }
 
         let mut writer = self
             .wav_writer
             .as_mut()
             .unwrap()
-            .get_i16_writer((samples.len() as u32) * 2);
-        let mut cursor = std::io::Cursor::new(samples);
-        while let Ok(sample) = cursor.read_u8() {
-            let decoded = self.decoder.decode((sample & 0x0F) as u16);
-            writer.write_sample(decoded);
-            let decoded = self.decoder.decode((sample >> 4) as u16);
-            writer.write_sample(decoded);
+            .get_i16_writer(frame.samples.len() as u32);
+        for &sample in frame.samples.iter() {
+            writer.write_sample(sample);
         }
-        writer.flush().unwrap();
-        if self.start.elapsed().as_secs() > 1800 {
-            self.close();
+        writer.flush()?;
+        self.position += frame.samples.len() as u32;
+        if self.start.elapsed() > self.rotate {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    /// Fills in the offset and length of the last transmission, ending it at `end` if given.
+    fn end_segment(&mut self, end: Option<DateTime<Utc>>) {
+        let Some(segment) = self
+            .segments
+            .last_mut()
+            .filter(|segment| segment.end.is_none())
+        else {
+            return;
+        };
+        segment.end = end;
+        segment.offset = segment.position as f64 / self.sample_rate as f64;
+        segment.duration = (self.position - segment.position) as f64 / self.sample_rate as f64;
+    }
+
+    /// Writes the transmissions of the file just closed into a `.json` and, unless they have a
+    /// file each, cues.
+    fn write_segments(&mut self) -> anyhow::Result<()> {
+        let segments = std::mem::take(&mut self.segments);
+        let Some(squelch) = self.squelch.as_ref() else {
+            return Ok(());
+        };
+        if squelch.mode() != SquelchMode::Files {
+            let cues = segments
+                .iter()
+                .map(|segment| Cue {
+                    position: segment.position,
+                    label: segment.start.format("%H:%M:%S").to_string(),
+                })
+                .collect::<Vec<Cue>>();
+            cue::append_cues(&self.path, &cues)?;
         }
+
+        let index = SegmentIndex {
+            station: &self.name,
+            segments: &segments,
+        };
+        std::fs::write(
+            self.path.with_extension("json"),
+            serde_json::to_vec_pretty(&index)?,
+        )?;
+        Ok(())
     }
+}
 
-    pub fn close(&mut self) {
-        match self.wav_writer.take() {
-            Some(writer) => {
-                writer.finalize().unwrap();
+impl AudioSink for Writer {
+    fn write(&mut self, frame: &AudioFrame) -> anyhow::Result<()> {
+        let Some(squelch) = self.squelch.as_mut() else {
+            return self.record(frame);
+        };
+
+        let mode = squelch.mode();
+        match squelch.process(frame) {
+            Gate::Closed => match mode {
+                SquelchMode::Continuous => self.record(frame),
+                SquelchMode::Files | SquelchMode::Cues => Ok(()),
+            },
+            Gate::Opened(frames) => {
+                let (current, pre_roll) = frames.split_last().unwrap();
+                if mode == SquelchMode::Files {
+                    self.close()?;
+                }
+                if mode == SquelchMode::Continuous {
+                    // The pre-roll has been recorded already
+                    let written = pre_roll
+                        .iter()
+                        .map(|frame| frame.samples.len() as u32)
+                        .sum();
+                    self.pending_segment = Some((frames[0].timestamp, written));
+                } else {
+                    self.pending_segment = Some((frames[0].timestamp, 0));
+                    for frame in pre_roll {
+                        self.record(frame)?;
+                    }
+                }
+                self.record(current)
+            }
+            Gate::Open => self.record(frame),
+            Gate::Ended => {
+                self.end_segment(Some(frame.timestamp));
+                match mode {
+                    SquelchMode::Files => self.close(),
+                    SquelchMode::Cues => Ok(()),
+                    SquelchMode::Continuous => self.record(frame),
+                }
             }
-            None => {}
         }
     }
+
+    fn close(&mut self) -> anyhow::Result<()> {
+        if let Some(writer) = self.wav_writer.take() {
+            writer.finalize()?;
+            self.carry_over = self
+                .segments
+                .last()
+                .is_some_and(|segment| segment.end.is_none());
+            self.end_segment(None);
+            self.write_segments()?;
+            self.events.publish(
+                &self.name,
+                EventKind::FileClosed {
+                    path: self.path.display().to_string(),
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// A directory of its own for each test, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("sdr-scraper-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        /// Files with `extension`, by name.
+        fn files(&self, extension: &str) -> Vec<PathBuf> {
+            let mut files = std::fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+                .collect::<Vec<PathBuf>>();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn time(ms: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap() + chrono::Duration::milliseconds(ms)
+    }
+
+    /// A tenth of a second at 12 kHz, `ms` in, with the S-meter at `rssi`.
+    fn frame(ms: i64, rssi: f64) -> AudioFrame {
+        AudioFrame {
+            samples: Arc::from(vec![1000; 1200]),
+            sample_rate: 12000,
+            timestamp: time(ms),
+            tuning: Tuning::AM {
+                bandwidth: 9000,
+                frequency: 6_080_000.0,
+            },
+            rssi: Some(rssi),
+        }
+    }
+
+    /// Records two transmissions, 0.5-0.6 s and 3.0-3.1 s, the second still going when the
+    /// writer is closed. With 0.2 s of pre-roll and 1 s of hang, the first is recorded from 0.3 s
+    /// until it ends at 1.7 s, and the second from 2.8 s.
+    fn record(dir: &TempDir, mode: SquelchMode) {
+        let squelch = SquelchConfig {
+            threshold: Some(-100.0),
+            attack: Duration::ZERO,
+            hang: Duration::from_secs(1),
+            pre_roll: Duration::from_millis(200),
+            mode,
+            ..SquelchConfig::default()
+        };
+        let mut writer = Writer::new(
+            "test".to_string(),
+            &dir.0,
+            "{name}_%H%M%S_%3f.wav".to_string(),
+            Duration::from_secs(3600),
+            EventBus::new(),
+        )
+        .with_squelch(Some(squelch));
+
+        for ms in (0..3200).step_by(100) {
+            let on = (500..700).contains(&ms) || ms >= 3000;
+            writer
+                .write(&frame(ms, if on { -90.0 } else { -120.0 }))
+                .unwrap();
+        }
+        writer.close().unwrap();
+    }
+
+    fn segments(path: &Path) -> Vec<serde_json::Value> {
+        let index: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path.with_extension("json")).unwrap()).unwrap();
+        assert_eq!(index["station"], "test");
+        index["segments"].as_array().unwrap().clone()
+    }
+
+    fn assert_segment(
+        segment: &serde_json::Value,
+        start: i64,
+        end: Option<i64>,
+        offset: f64,
+        duration: f64,
+    ) {
+        assert_eq!(segment["start"], serde_json::to_value(time(start)).unwrap());
+        assert_eq!(segment["end"], serde_json::to_value(end.map(time)).unwrap());
+        assert!((segment["offset"].as_f64().unwrap() - offset).abs() < 1e-9);
+        assert!((segment["duration"].as_f64().unwrap() - duration).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cues_mark_transmissions_in_one_file() {
+        let dir = TempDir::new("cues");
+        record(&dir, SquelchMode::Cues);
+
+        let wavs = dir.files("wav");
+        assert_eq!(wavs.len(), 1);
+        let segments = segments(&wavs[0]);
+        assert_eq!(segments.len(), 2);
+        // 0.3 s to 1.7 s is 14 frames, then 2.8 s to 3.1 s
+        assert_segment(&segments[0], 300, Some(1700), 0.0, 1.4);
+        assert_segment(&segments[1], 2800, None, 1.4, 0.4);
+
+        let file = std::fs::read(&wavs[0]).unwrap();
+        let riff_size = u32::from_le_bytes(file[4..8].try_into().unwrap());
+        assert_eq!(riff_size as usize, file.len() - 8);
+        let cue = file.windows(4).position(|id| id == b"cue ").unwrap();
+        let count = u32::from_le_bytes(file[cue + 8..cue + 12].try_into().unwrap());
+        let positions = (0..count as usize)
+            .map(|i| {
+                let point = cue + 12 + 24 * i;
+                u32::from_le_bytes(file[point + 4..point + 8].try_into().unwrap())
+            })
+            .collect::<Vec<u32>>();
+        assert_eq!(positions, [0, 14 * 1200]);
+        assert!(file.windows(4).any(|id| id == b"labl"));
+
+        // Still readable, the cues after the samples
+        let reader = hound::WavReader::open(&wavs[0]).unwrap();
+        assert_eq!(reader.len(), 18 * 1200);
+    }
+
+    #[test]
+    fn files_give_each_transmission_its_own() {
+        let dir = TempDir::new("files");
+        record(&dir, SquelchMode::Files);
+
+        let wavs = dir.files("wav");
+        assert_eq!(
+            wavs.iter()
+                .map(|path| path.file_name().unwrap().to_str().unwrap())
+                .collect::<Vec<&str>>(),
+            ["test_120000_300.wav", "test_120002_800.wav"]
+        );
+        let first = segments(&wavs[0]);
+        assert_eq!(first.len(), 1);
+        assert_segment(&first[0], 300, Some(1700), 0.0, 1.4);
+        let second = segments(&wavs[1]);
+        assert_eq!(second.len(), 1);
+        assert_segment(&second[0], 2800, None, 0.0, 0.4);
+
+        for (wav, frames) in wavs.iter().zip([14, 4]) {
+            let file = std::fs::read(wav).unwrap();
+            assert!(!file.windows(4).any(|id| id == b"cue "));
+            assert_eq!(hound::WavReader::open(wav).unwrap().len(), frames * 1200);
+        }
+    }
+
+    #[test]
+    fn template_keeps_percent_signs_in_values() {
+        let tuning = Tuning::AM {
+            bandwidth: 9000,
+            frequency: 6_080_000.0,
+        };
+        let time = Utc.with_ymd_and_hms(2026, 10, 18, 19, 5, 0).unwrap();
+        let rendered = render_template(
+            "{name}_{frequency}_{mode}_%Y%m%d_%H%M",
+            "kiwi%d_100%",
+            &tuning,
+            time,
+        )
+        .unwrap();
+        assert_eq!(rendered, "kiwi%d_100%_6080_am_20261018_1905");
+    }
 }
@@ -0,0 +1,292 @@
+use std::collections::VecDeque;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Condvar, Mutex,
+};
+use std::thread::JoinHandle;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use super::{AudioFrame, AudioSink};
+
+/// What to do when a writer's queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum OverflowPolicy {
+    /// Wait for the writer thread to catch up, applying backpressure to the receiver.
+    #[default]
+    Block,
+    /// Discard the oldest queued frame to make room for the new one.
+    DropOldest,
+    /// Queue past the limit anyway, trading memory for not losing audio, up to the pool's
+    /// spill limit. Beyond that, the oldest queued frame is dropped as with `DropOldest`.
+    Spill,
+}
+
+pub enum WriterJob {
+    Frame(AudioFrame),
+    Close,
+}
+
+struct WriterSlot {
+    name: String,
+    sinks: Mutex<Vec<Box<dyn AudioSink>>>,
+    permits: Semaphore,
+    depth: AtomicUsize,
+    overflows: AtomicU64,
+    /// Frames queued past the limit by `OverflowPolicy::Spill`.
+    spilled: AtomicUsize,
+    /// Whether the spill limit has been reached, to log only when that changes.
+    spill_full: AtomicBool,
+}
+
+struct QueuedJob {
+    slot: Arc<WriterSlot>,
+    job: WriterJob,
+    /// Whether this job holds one of the slot's permits, which must be returned once it is dequeued.
+    permit: bool,
+}
+
+#[derive(Default)]
+struct QueueState {
+    jobs: VecDeque<QueuedJob>,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct WorkerQueue {
+    state: Mutex<QueueState>,
+    available: Condvar,
+}
+
+impl WorkerQueue {
+    fn push(&self, job: QueuedJob) {
+        job.slot.depth.fetch_add(1, Ordering::Relaxed);
+        self.state.lock().unwrap().jobs.push_back(job);
+        self.available.notify_one();
+    }
+
+    /// Replaces the oldest queued frame belonging to `slot` with `job`, handing over its permit.
+    /// Returns the job back if there was nothing of this slot's to drop.
+    fn replace_oldest(&self, slot: &Arc<WriterSlot>, job: WriterJob) -> Result<(), WriterJob> {
+        let mut state = self.state.lock().unwrap();
+        let position = state.jobs.iter().position(|queued| {
+            queued.permit
+                && Arc::ptr_eq(&queued.slot, slot)
+                && matches!(queued.job, WriterJob::Frame(_))
+        });
+
+        match position {
+            Some(position) => {
+                let queued = state.jobs.remove(position).unwrap();
+                state.jobs.push_back(QueuedJob {
+                    slot: queued.slot,
+                    job,
+                    permit: true,
+                });
+                Ok(())
+            }
+            None => Err(job),
+        }
+    }
+
+    fn pop(&self) -> Option<QueuedJob> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                return Some(job);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.available.notify_all();
+    }
+}
+
+/// A set of blocking threads that own the audio sinks, so that disk I/O and other slow consumers never run
+/// on the async runtime. Each station's sinks are pinned to one thread to keep its frames in order.
+pub struct WriterPool {
+    queues: Vec<Arc<WorkerQueue>>,
+    threads: Vec<JoinHandle<()>>,
+    next: AtomicUsize,
+    capacity: usize,
+    policy: OverflowPolicy,
+    spill_limit: usize,
+}
+
+impl WriterPool {
+    pub fn new(
+        threads: usize,
+        capacity: usize,
+        policy: OverflowPolicy,
+        spill_limit: usize,
+    ) -> Self {
+        let threads = threads.max(1);
+        let queues: Vec<Arc<WorkerQueue>> = (0..threads)
+            .map(|_| Arc::new(WorkerQueue::default()))
+            .collect();
+
+        let threads = queues
+            .iter()
+            .enumerate()
+            .map(|(i, queue)| {
+                let queue = queue.clone();
+                std::thread::Builder::new()
+                    .name(format!("writer-{}", i))
+                    .spawn(move || Self::run(queue))
+                    .expect("failed to spawn writer thread")
+            })
+            .collect();
+
+        WriterPool {
+            queues,
+            threads,
+            next: AtomicUsize::new(0),
+            capacity: capacity.max(1),
+            policy,
+            spill_limit,
+        }
+    }
+
+    /// Hands a station's sinks over to one of the pool's threads.
+    pub fn register(&self, name: &str, sinks: Vec<Box<dyn AudioSink>>) -> WriterHandle {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.queues.len();
+        WriterHandle {
+            slot: Arc::new(WriterSlot {
+                name: name.to_string(),
+                sinks: Mutex::new(sinks),
+                permits: Semaphore::new(self.capacity),
+                depth: AtomicUsize::new(0),
+                overflows: AtomicU64::new(0),
+                spilled: AtomicUsize::new(0),
+                spill_full: AtomicBool::new(false),
+            }),
+            queue: self.queues[index].clone(),
+            policy: self.policy,
+            spill_limit: self.spill_limit,
+        }
+    }
+
+    /// Drains every queue and waits for the writer threads to exit.
+    pub fn shutdown(self) {
+        for queue in &self.queues {
+            queue.close();
+        }
+        for thread in self.threads {
+            if thread.join().is_err() {
+                log::error!("writer thread panicked");
+            }
+        }
+    }
+
+    fn run(queue: Arc<WorkerQueue>) {
+        while let Some(queued) = queue.pop() {
+            let slot = queued.slot;
+            slot.depth.fetch_sub(1, Ordering::Relaxed);
+            if queued.permit {
+                slot.permits.add_permits(1);
+            } else if matches!(queued.job, WriterJob::Frame(_)) {
+                slot.spilled.fetch_sub(1, Ordering::Relaxed);
+            }
+
+            let mut sinks = slot.sinks.lock().unwrap();
+            for sink in sinks.iter_mut() {
+                let result = match &queued.job {
+                    WriterJob::Frame(frame) => sink.write(frame),
+                    WriterJob::Close => sink.close(),
+                };
+
+                if let Err(e) = result {
+                    log::error!("{}: error writing audio: {}", slot.name, e);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WriterHandle {
+    slot: Arc<WriterSlot>,
+    queue: Arc<WorkerQueue>,
+    policy: OverflowPolicy,
+    spill_limit: usize,
+}
+
+impl WriterHandle {
+    /// Queues a frame for every sink, applying the pool's overflow policy if the queue is full.
+    pub async fn write(&self, frame: AudioFrame) {
+        let job = WriterJob::Frame(frame);
+
+        if let Ok(permit) = self.slot.permits.try_acquire() {
+            permit.forget();
+            return self.enqueue(job, true);
+        }
+
+        self.slot.overflows.fetch_add(1, Ordering::Relaxed);
+        let policy = match self.policy {
+            OverflowPolicy::Spill if !self.spill() => OverflowPolicy::DropOldest,
+            policy => policy,
+        };
+        let job = match policy {
+            OverflowPolicy::Block => job,
+            OverflowPolicy::DropOldest => match self.queue.replace_oldest(&self.slot, job) {
+                Ok(()) => return,
+                // The writer thread is mid-way through returning a permit
+                Err(job) => job,
+            },
+            OverflowPolicy::Spill => {
+                self.slot.spilled.fetch_add(1, Ordering::Relaxed);
+                return self.enqueue(job, false);
+            }
+        };
+
+        // The semaphore is never closed
+        self.slot.permits.acquire().await.unwrap().forget();
+        self.enqueue(job, true);
+    }
+
+    pub fn close(&self) {
+        self.enqueue(WriterJob::Close, false);
+    }
+
+    /// Whether another frame may be spilled, logging when the limit is reached and cleared.
+    fn spill(&self) -> bool {
+        let full = self.slot.spilled.load(Ordering::Relaxed) >= self.spill_limit;
+        if full != self.slot.spill_full.swap(full, Ordering::Relaxed) {
+            if full {
+                log::warn!(
+                    "{}: {} frames spilled past the queue, dropping the oldest",
+                    self.slot.name,
+                    self.spill_limit
+                );
+            } else {
+                log::info!("{}: writer caught up, spilling again", self.slot.name);
+            }
+        }
+        !full
+    }
+
+    /// Number of jobs waiting for the writer thread.
+    pub fn queue_depth(&self) -> usize {
+        self.slot.depth.load(Ordering::Relaxed)
+    }
+
+    /// Number of frames that found the queue full.
+    pub fn overflows(&self) -> u64 {
+        self.slot.overflows.load(Ordering::Relaxed)
+    }
+
+    fn enqueue(&self, job: WriterJob, permit: bool) {
+        self.queue.push(QueuedJob {
+            slot: self.slot.clone(),
+            job,
+            permit,
+        });
+    }
+}
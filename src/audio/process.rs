@@ -0,0 +1,256 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+
+use crate::{
+    events::{EventBus, EventKind},
+    sdr::Tuning,
+};
+
+use super::{stream::pcm_bytes, AudioFrame, AudioSink};
+
+/// How long a process gets to exit on its own after its input is closed.
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+/// Frames waiting for a process to read them before new ones are dropped.
+const INPUT_FRAMES: usize = 64;
+
+struct Running {
+    child: Child,
+    /// Frames for the thread feeding the process's stdin, which closes it once this is dropped.
+    input: SyncSender<Vec<u8>>,
+    sample_rate: u32,
+    /// Frames dropped in a row because the process fell behind.
+    dropped: u64,
+}
+
+/// Pipes raw s16le PCM into an external command, e.g. a decoder like `multimon-ng`. The command is run
+/// through `sh -c` after substituting `{sample_rate}`, `{name}`, `{frequency}` (in kHz) and `{mode}`, and
+/// each line it prints is published as a station event.
+///
+/// The process is fed from a thread of its own, so one that stops reading loses frames rather
+/// than holding up the writer thread and every other station on it.
+pub struct ProcessSink {
+    name: String,
+    command: String,
+    restart_delay: Duration,
+    events: EventBus,
+    running: Option<Running>,
+    last_spawn: Option<Instant>,
+}
+
+impl ProcessSink {
+    pub fn new(name: String, command: String, restart_delay: Duration, events: EventBus) -> Self {
+        ProcessSink {
+            name,
+            command,
+            restart_delay,
+            events,
+            running: None,
+            last_spawn: None,
+        }
+    }
+
+    fn render_command(&self, sample_rate: u32, tuning: &Tuning) -> String {
+        self.command
+            .replace("{sample_rate}", &sample_rate.to_string())
+            .replace("{name}", &self.name)
+            .replace(
+                "{frequency}",
+                &format!("{:.0}", tuning.frequency() / 1000.0),
+            )
+            .replace("{mode}", tuning.mode())
+    }
+
+    fn spawn(&mut self, frame: &AudioFrame) -> anyhow::Result<()> {
+        self.last_spawn = Some(Instant::now());
+
+        let command = self.render_command(frame.sample_rate, &frame.tuning);
+        log::info!("{}: starting `{}`", self.name, command);
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let (input, frames) = mpsc::sync_channel::<Vec<u8>>(INPUT_FRAMES);
+        let name = self.name.clone();
+        std::thread::Builder::new()
+            .name(format!("{}-stdin", self.name))
+            .spawn(move || {
+                for bytes in frames {
+                    if let Err(e) = stdin.write_all(&bytes) {
+                        // The process closed its input, it gets restarted once the delay is up
+                        log::warn!("{}: error writing to process: {}", name, e);
+                        break;
+                    }
+                }
+            })?;
+
+        let stdout = child.stdout.take().unwrap();
+        let name = self.name.clone();
+        let events = self.events.clone();
+        std::thread::Builder::new()
+            .name(format!("{}-stdout", self.name))
+            .spawn(move || {
+                for line in BufReader::new(stdout).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    log::info!("{}: {}", name, line);
+                    events.publish(&name, EventKind::ProcessOutput { line });
+                }
+            })?;
+
+        self.running = Some(Running {
+            child,
+            input,
+            sample_rate: frame.sample_rate,
+            dropped: 0,
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        let Some(Running {
+            mut child, input, ..
+        }) = self.running.take()
+        else {
+            return Ok(());
+        };
+
+        drop(input);
+        let deadline = Instant::now() + EXIT_GRACE;
+        while Instant::now() < deadline {
+            if child.try_wait()?.is_some() {
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        log::warn!("{}: process did not exit, killing it", self.name);
+        child.kill()?;
+        child.wait()?;
+        Ok(())
+    }
+}
+
+impl AudioSink for ProcessSink {
+    fn write(&mut self, frame: &AudioFrame) -> anyhow::Result<()> {
+        if let Some(running) = self.running.as_mut() {
+            if running.sample_rate != frame.sample_rate {
+                self.stop()?;
+            } else if let Some(status) = running.child.try_wait()? {
+                log::warn!("{}: process exited with {}", self.name, status);
+                self.running = None;
+            }
+        }
+
+        if self.running.is_none() {
+            let backing_off = self
+                .last_spawn
+                .is_some_and(|last| last.elapsed() < self.restart_delay);
+            if backing_off {
+                return Ok(());
+            }
+            self.spawn(frame)?;
+        }
+
+        let running = self.running.as_mut().unwrap();
+        match running.input.try_send(pcm_bytes(&frame.samples)) {
+            Ok(()) => {
+                if running.dropped > 0 {
+                    log::warn!(
+                        "{}: process caught up after {} dropped frames",
+                        self.name,
+                        running.dropped
+                    );
+                    running.dropped = 0;
+                }
+            }
+            Err(TrySendError::Full(_)) => {
+                if running.dropped == 0 {
+                    log::warn!("{}: process is falling behind, dropping frames", self.name);
+                }
+                running.dropped += 1;
+            }
+            // Writing failed, it gets restarted once the delay is up
+            Err(TrySendError::Disconnected(_)) => self.stop()?,
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+
+    use super::*;
+
+    fn frame() -> AudioFrame {
+        AudioFrame {
+            samples: Arc::from(vec![1000; 1200]),
+            sample_rate: 12000,
+            timestamp: Utc::now(),
+            tuning: Tuning::AM {
+                bandwidth: 9000,
+                frequency: 6_080_000.0,
+            },
+            rssi: None,
+        }
+    }
+
+    #[test]
+    fn feeds_the_process_and_publishes_its_output() {
+        let events = EventBus::new();
+        let mut sink = ProcessSink::new(
+            "test".to_string(),
+            "cat | wc -c".to_string(),
+            Duration::from_secs(10),
+            events.clone(),
+        );
+        for _ in 0..3 {
+            sink.write(&frame()).unwrap();
+        }
+        sink.close().unwrap();
+
+        // Printed once its input is closed, and published from a thread of its own
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let expected = (3 * 1200 * 2).to_string();
+        while !events.history().iter().any(|event| {
+            matches!(&event.kind, EventKind::ProcessOutput { line } if line.trim() == expected)
+        }) {
+            assert!(Instant::now() < deadline, "no output from the process");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn drops_frames_for_a_process_that_stops_reading() {
+        let mut sink = ProcessSink::new(
+            "test".to_string(),
+            "sleep 10".to_string(),
+            Duration::from_secs(10),
+            EventBus::new(),
+        );
+        // Far more than the pipe and the queue hold
+        let start = Instant::now();
+        for _ in 0..1000 {
+            sink.write(&frame()).unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(sink.running.as_ref().unwrap().dropped > 0);
+        sink.close().unwrap();
+    }
+}
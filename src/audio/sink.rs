@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    config::{DecoderKind, SinkConfig},
+    events::EventBus,
+    sdr::Tuning,
+};
+
+use super::{process::ProcessSink, DtmfDecoder, UdpSink, Writer};
+
+/// A block of decoded audio along with the context it was received in.
+#[derive(Debug, Clone)]
+pub struct AudioFrame {
+    pub samples: Arc<[i16]>,
+    pub sample_rate: u32,
+    pub timestamp: DateTime<Utc>,
+    pub tuning: Tuning,
+    /// S-meter reading when the frame arrived, in dBm, if the receiver has one.
+    pub rssi: Option<f64>,
+}
+
+/// A consumer of decoded audio. Sinks run on the writer pool, so they are free to block.
+pub trait AudioSink: Send {
+    fn write(&mut self, frame: &AudioFrame) -> anyhow::Result<()>;
+
+    /// Called when the stream is interrupted, e.g. on disconnect or when the scraper stops.
+    fn close(&mut self) -> anyhow::Result<()>;
+}
+
+pub fn build_sinks(
+    name: &str,
+    configs: &[SinkConfig],
+    events: &EventBus,
+) -> Vec<Box<dyn AudioSink>> {
+    configs
+        .iter()
+        .map(|config| -> Box<dyn AudioSink> {
+            match config {
+                SinkConfig::File {
+                    dir,
+                    template,
+                    rotate,
+                    squelch,
+                } => Box::new(
+                    Writer::new(
+                        name.to_string(),
+                        dir,
+                        template.clone(),
+                        *rotate,
+                        events.clone(),
+                    )
+                    .with_squelch(squelch.clone()),
+                ),
+                SinkConfig::Process {
+                    command,
+                    restart_delay,
+                } => Box::new(ProcessSink::new(
+                    name.to_string(),
+                    command.clone(),
+                    *restart_delay,
+                    events.clone(),
+                )),
+                SinkConfig::Stream { address } => {
+                    Box::new(UdpSink::new(name.to_string(), address.clone()))
+                }
+                SinkConfig::Decoder {
+                    decoder: DecoderKind::Dtmf,
+                } => Box::new(DtmfDecoder::new(name.to_string(), events.clone())),
+            }
+        })
+        .collect()
+}
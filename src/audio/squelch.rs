@@ -0,0 +1,288 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use super::{AudioFrame, VoiceDetector};
+use crate::config::{SquelchConfig, SquelchDetector, SquelchMode};
+
+/// How far the noise floor moves towards each reading below and above it. It drops quickly and
+/// creeps back up, so that short signals barely lift it.
+const FLOOR_FALL: f64 = 0.1;
+const FLOOR_RISE: f64 = 0.002;
+
+/// What to do with a frame that went through the squelch.
+#[derive(Debug)]
+pub enum Gate {
+    /// No signal, leave it out.
+    Closed,
+    /// A transmission starts, with the pre-roll leading up to and including the frame.
+    Opened(Vec<AudioFrame>),
+    /// The transmission goes on.
+    Open,
+    /// The transmission is over and the frame isn't part of it.
+    Ended,
+}
+
+/// Decides from the S-meter readings in the frames, or from their audio, where transmissions
+/// start and end. Frames without an S-meter reading count as a signal.
+pub struct Squelch {
+    config: SquelchConfig,
+    voice: Option<VoiceDetector>,
+    noise_floor: Option<f64>,
+    /// When the signal came up, while waiting out the attack.
+    rising: Option<DateTime<Utc>>,
+    /// When the signal was last there, while open.
+    last_heard: Option<DateTime<Utc>>,
+    pre_roll: VecDeque<AudioFrame>,
+}
+
+/// Time from `earlier` to `later`, or zero if the clock went backwards.
+fn elapsed(earlier: DateTime<Utc>, later: DateTime<Utc>) -> Duration {
+    (later - earlier).to_std().unwrap_or_default()
+}
+
+impl Squelch {
+    pub fn new(config: SquelchConfig) -> Self {
+        Squelch {
+            voice: (config.detector == SquelchDetector::Voice).then(|| VoiceDetector::new(&config)),
+            config,
+            noise_floor: None,
+            rising: None,
+            last_heard: None,
+            pre_roll: VecDeque::new(),
+        }
+    }
+
+    pub fn mode(&self) -> SquelchMode {
+        self.config.mode
+    }
+
+    fn above(&self, rssi: f64) -> bool {
+        match (self.config.threshold, self.noise_floor) {
+            (Some(threshold), _) => rssi >= threshold,
+            (None, Some(floor)) => rssi >= floor + self.config.margin,
+            (None, None) => false,
+        }
+    }
+
+    pub fn process(&mut self, frame: &AudioFrame) -> Gate {
+        let time = frame.timestamp;
+        let above = match self.voice.as_mut() {
+            Some(voice) => voice.active(frame),
+            None => frame.rssi.is_none_or(|rssi| self.above(rssi)),
+        };
+
+        if let Some(last_heard) = self.last_heard {
+            if above {
+                self.last_heard = Some(time);
+            } else if elapsed(last_heard, time) > self.config.hang {
+                self.last_heard = None;
+                self.pre_roll.push_back(frame.clone());
+                return Gate::Ended;
+            }
+            return Gate::Open;
+        }
+
+        if let Some(rssi) = frame.rssi.filter(|_| self.voice.is_none()) {
+            let floor = self.noise_floor.get_or_insert(rssi);
+            let rate = if rssi < *floor {
+                FLOOR_FALL
+            } else {
+                FLOOR_RISE
+            };
+            *floor += (rssi - *floor) * rate;
+        }
+
+        self.pre_roll.push_back(frame.clone());
+        while self
+            .pre_roll
+            .front()
+            .is_some_and(|oldest| elapsed(oldest.timestamp, time) > self.config.pre_roll)
+        {
+            self.pre_roll.pop_front();
+        }
+
+        if !above {
+            self.rising = None;
+            return Gate::Closed;
+        }
+        let rising = *self.rising.get_or_insert(time);
+        if elapsed(rising, time) < self.config.attack {
+            return Gate::Closed;
+        }
+
+        self.rising = None;
+        self.last_heard = Some(time);
+        Gate::Opened(self.pre_roll.drain(..).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::sdr::Tuning;
+
+    /// A tenth of a second of silence at `ms` in, with an S-meter reading of `rssi`.
+    fn frame(ms: i64, rssi: Option<f64>) -> AudioFrame {
+        AudioFrame {
+            samples: Arc::from(vec![0; 1200]),
+            sample_rate: 12000,
+            timestamp: Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap()
+                + chrono::Duration::milliseconds(ms),
+            tuning: Tuning::AM {
+                bandwidth: 10000,
+                frequency: 7.2e6,
+            },
+            rssi,
+        }
+    }
+
+    fn squelch(threshold: Option<f64>, attack_ms: u64) -> Squelch {
+        Squelch::new(SquelchConfig {
+            threshold,
+            attack: Duration::from_millis(attack_ms),
+            hang: Duration::from_secs(1),
+            pre_roll: Duration::from_millis(500),
+            ..SquelchConfig::default()
+        })
+    }
+
+    #[test]
+    fn opens_after_the_attack_and_ends_after_the_hang() {
+        let mut squelch = squelch(Some(-100.0), 200);
+        for ms in (0..1000).step_by(100) {
+            assert!(matches!(
+                squelch.process(&frame(ms, Some(-120.0))),
+                Gate::Closed
+            ));
+        }
+
+        // Up at 1.0 s, open once it's been there 0.2 s
+        assert!(matches!(
+            squelch.process(&frame(1000, Some(-90.0))),
+            Gate::Closed
+        ));
+        assert!(matches!(
+            squelch.process(&frame(1100, Some(-90.0))),
+            Gate::Closed
+        ));
+        match squelch.process(&frame(1200, Some(-90.0))) {
+            Gate::Opened(pre_roll) => {
+                // Half a second back, through the frame that opened it
+                let start = frame(0, None).timestamp;
+                let times = pre_roll
+                    .iter()
+                    .map(|frame| (frame.timestamp - start).num_milliseconds())
+                    .collect::<Vec<i64>>();
+                assert_eq!(times, [700, 800, 900, 1000, 1100, 1200]);
+            }
+            other => panic!("expected the squelch to open, got {:?}", other),
+        }
+        assert!(matches!(
+            squelch.process(&frame(1300, Some(-90.0))),
+            Gate::Open
+        ));
+
+        // Gone from 1.4 s, but it comes back within the hang at 2.0 s
+        for ms in (1400..2000).step_by(100) {
+            assert!(matches!(
+                squelch.process(&frame(ms, Some(-120.0))),
+                Gate::Open
+            ));
+        }
+        assert!(matches!(
+            squelch.process(&frame(2000, Some(-90.0))),
+            Gate::Open
+        ));
+        for ms in (2100..=3000).step_by(100) {
+            assert!(matches!(
+                squelch.process(&frame(ms, Some(-120.0))),
+                Gate::Open
+            ));
+        }
+        assert!(matches!(
+            squelch.process(&frame(3100, Some(-120.0))),
+            Gate::Ended
+        ));
+        assert!(matches!(
+            squelch.process(&frame(3200, Some(-120.0))),
+            Gate::Closed
+        ));
+    }
+
+    #[test]
+    fn a_blip_shorter_than_the_attack_stays_closed() {
+        let mut squelch = squelch(Some(-100.0), 200);
+        for (ms, rssi) in [
+            (0, -90.0),
+            (100, -90.0),
+            (200, -120.0),
+            (300, -90.0),
+            (400, -90.0),
+        ] {
+            assert!(matches!(
+                squelch.process(&frame(ms, Some(rssi))),
+                Gate::Closed
+            ));
+        }
+        assert!(matches!(
+            squelch.process(&frame(500, Some(-90.0))),
+            Gate::Opened(_)
+        ));
+    }
+
+    #[test]
+    fn pre_roll_is_bounded_after_a_transmission() {
+        let mut squelch = squelch(Some(-100.0), 0);
+        assert!(matches!(
+            squelch.process(&frame(0, Some(-90.0))),
+            Gate::Opened(_)
+        ));
+        assert!(matches!(
+            squelch.process(&frame(1100, Some(-120.0))),
+            Gate::Ended
+        ));
+        for ms in (1200..5000).step_by(100) {
+            squelch.process(&frame(ms, Some(-120.0)));
+        }
+        match squelch.process(&frame(5000, Some(-90.0))) {
+            Gate::Opened(pre_roll) => assert_eq!(pre_roll.len(), 6),
+            other => panic!("expected the squelch to open, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn follows_the_noise_floor_without_a_threshold() {
+        let mut squelch = squelch(None, 0);
+        // The floor starts at the first reading and falls quickly to the real noise
+        assert!(matches!(
+            squelch.process(&frame(0, Some(-100.0))),
+            Gate::Closed
+        ));
+        assert!(matches!(
+            squelch.process(&frame(100, Some(-108.0))),
+            Gate::Closed
+        ));
+        for ms in (200..6000).step_by(100) {
+            assert!(matches!(
+                squelch.process(&frame(ms, Some(-120.0))),
+                Gate::Closed
+            ));
+        }
+        assert!(matches!(
+            squelch.process(&frame(6000, Some(-108.0))),
+            Gate::Opened(_)
+        ));
+    }
+
+    #[test]
+    fn frames_without_a_reading_count_as_a_signal() {
+        let mut squelch = squelch(Some(-100.0), 0);
+        assert!(matches!(squelch.process(&frame(0, None)), Gate::Opened(_)));
+    }
+}
@@ -0,0 +1,70 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use tokio::sync::broadcast;
+
+use super::{AudioFrame, AudioSink};
+
+/// How many frames a listener may fall behind before it starts skipping audio.
+const STREAM_BACKLOG: usize = 64;
+
+/// Republishes a station's frames to any number of live listeners.
+#[derive(Clone)]
+pub struct AudioStream {
+    sender: broadcast::Sender<AudioFrame>,
+}
+
+impl Default for AudioStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioStream {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(STREAM_BACKLOG);
+        AudioStream { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AudioFrame> {
+        self.sender.subscribe()
+    }
+}
+
+impl AudioSink for AudioStream {
+    fn write(&mut self, frame: &AudioFrame) -> anyhow::Result<()> {
+        // Nobody listening is fine
+        let _ = self.sender.send(frame.clone());
+        Ok(())
+    }
+
+    fn close(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Header for a mono 16-bit WAV stream of unknown length. The sizes are maxed out, which players
+/// understand as "read until the connection ends".
+pub fn wav_stream_header(sample_rate: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.write_u32::<LittleEndian>(u32::MAX).unwrap();
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.write_u32::<LittleEndian>(16).unwrap();
+    // PCM, mono
+    header.write_u16::<LittleEndian>(1).unwrap();
+    header.write_u16::<LittleEndian>(1).unwrap();
+    header.write_u32::<LittleEndian>(sample_rate).unwrap();
+    header.write_u32::<LittleEndian>(sample_rate * 2).unwrap();
+    header.write_u16::<LittleEndian>(2).unwrap();
+    header.write_u16::<LittleEndian>(16).unwrap();
+    header.extend_from_slice(b"data");
+    header.write_u32::<LittleEndian>(u32::MAX).unwrap();
+    header
+}
+
+pub fn pcm_bytes(samples: &[i16]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect()
+}
@@ -0,0 +1,69 @@
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use super::{stream::pcm_bytes, AudioFrame, AudioSink};
+
+/// Samples per datagram, small enough to never be fragmented.
+const DATAGRAM_SAMPLES: usize = 512;
+
+/// Sends raw s16le PCM to a UDP address, the way gqrx feeds decoders such as `direwolf` or
+/// `multimon-ng` listening on a socket. Nothing tells the listener the sample rate, so it has to
+/// be set to the station's.
+pub struct UdpSink {
+    name: String,
+    address: String,
+    socket: Option<UdpSocket>,
+}
+
+impl UdpSink {
+    pub fn new(name: String, address: String) -> Self {
+        UdpSink {
+            name,
+            address,
+            socket: None,
+        }
+    }
+
+    fn connect(&mut self) -> anyhow::Result<&UdpSocket> {
+        if self.socket.is_none() {
+            let address = self
+                .address
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("no address for {}", self.address))?;
+            let local = if address.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(local)?;
+            socket.connect(address)?;
+            log::info!("{}: streaming audio to {}", self.name, address);
+            self.socket = Some(socket);
+        }
+        Ok(self.socket.as_ref().unwrap())
+    }
+}
+
+impl AudioSink for UdpSink {
+    fn write(&mut self, frame: &AudioFrame) -> anyhow::Result<()> {
+        let socket = self.connect()?;
+        for samples in frame.samples.chunks(DATAGRAM_SAMPLES) {
+            match socket.send(&pcm_bytes(samples)) {
+                Ok(_) => {}
+                // Nobody listening is fine
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {}
+                Err(e) => {
+                    // Looked up and bound again on the next frame
+                    self.socket = None;
+                    return Err(e.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.socket = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use crate::config::SquelchConfig;
+use crate::dsp::{fft, Complex};
+
+use super::AudioFrame;
+
+/// Part of the spectrum speech is looked for in, in Hz.
+const VOICE_BAND: (f32, f32) = (300.0, 3000.0);
+
+/// Length of the windows frames are analysed in, rounded up to a power of two samples.
+const WINDOW_SECONDS: f32 = 0.02;
+
+/// How far the noise floor moves towards each window's energy below and above it.
+const FLOOR_FALL: f32 = 0.1;
+const FLOOR_RISE: f32 = 0.002;
+
+/// Tells voice and other signals from noise by the audio alone, for receivers whose S-meter
+/// doesn't help, like SSB under AGC.
+///
+/// Frames are cut into short windows. A window has a signal when its spectrum in the voice band
+/// is far from flat, which AGC can't hide, or when it's `margin` dB louder than the quietest
+/// windows and not crossing zero so often that it's just hiss.
+pub struct VoiceDetector {
+    margin: f32,
+    flatness: f32,
+    max_zcr: f32,
+    /// Energy of the quietest windows, in dBFS.
+    floor: Option<f32>,
+    /// Samples left over from the last frame, short of a window.
+    pending: Vec<f32>,
+    active: bool,
+}
+
+impl VoiceDetector {
+    pub fn new(config: &SquelchConfig) -> Self {
+        VoiceDetector {
+            margin: config.margin as f32,
+            flatness: config.flatness,
+            max_zcr: config.max_zcr,
+            floor: None,
+            pending: Vec::new(),
+            active: false,
+        }
+    }
+
+    /// Whether at least half of the windows completed by the frame have a signal. Frames too
+    /// short to complete one keep the last answer.
+    pub fn active(&mut self, frame: &AudioFrame) -> bool {
+        let size = ((frame.sample_rate as f32 * WINDOW_SECONDS) as usize).next_power_of_two();
+        self.pending
+            .extend(frame.samples.iter().map(|&sample| sample as f32 / 32768.0));
+
+        let (mut windows, mut active) = (0, 0);
+        while self.pending.len() >= size {
+            let window = self.pending.drain(..size).collect::<Vec<f32>>();
+            windows += 1;
+            if self.window(&window, frame.sample_rate) {
+                active += 1;
+            }
+        }
+        if windows > 0 {
+            self.active = active * 2 >= windows;
+        }
+        self.active
+    }
+
+    fn window(&mut self, samples: &[f32], sample_rate: u32) -> bool {
+        let size = samples.len();
+        let energy =
+            10.0 * (samples.iter().map(|x| x * x).sum::<f32>() / size as f32 + 1e-10).log10();
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        let zcr = crossings as f32 / (size - 1) as f32;
+
+        // Hann windowed, so a steady tone stays in its bins
+        let mut spectrum = samples
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| {
+                let hann = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / size as f32).cos();
+                Complex::new(x * hann, 0.0)
+            })
+            .collect::<Vec<Complex>>();
+        fft(&mut spectrum);
+        let bin_width = sample_rate as f32 / size as f32;
+        let low = (VOICE_BAND.0 / bin_width).ceil() as usize;
+        let high = ((VOICE_BAND.1 / bin_width) as usize).min(size / 2);
+        let powers = spectrum[low.min(high)..high]
+            .iter()
+            .map(|bin| bin.norm_sqr() + 1e-12)
+            .collect::<Vec<f32>>();
+        // Geometric over arithmetic mean: near one for noise, near zero for tones and voices
+        let flatness = if powers.is_empty() {
+            1.0
+        } else {
+            let count = powers.len() as f32;
+            (powers.iter().map(|p| p.ln()).sum::<f32>() / count).exp()
+                / (powers.iter().sum::<f32>() / count)
+        };
+
+        let floor = self.floor.get_or_insert(energy);
+        let loud = energy >= *floor + self.margin && zcr <= self.max_zcr;
+        let rate = if energy < *floor {
+            FLOOR_FALL
+        } else {
+            FLOOR_RISE
+        };
+        *floor += (energy - *floor) * rate;
+
+        loud || flatness < self.flatness
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::sdr::Tuning;
+
+    const RATE: u32 = 12000;
+    /// RMS of both signals, about -13 dBFS.
+    const RMS: f64 = 7000.0;
+
+    fn frame(samples: Vec<i16>) -> AudioFrame {
+        AudioFrame {
+            samples: Arc::from(samples),
+            sample_rate: RATE,
+            timestamp: Utc::now(),
+            tuning: Tuning::USB {
+                low_cut: 300,
+                high_cut: 2700,
+                frequency: 14.2e6,
+            },
+            rssi: None,
+        }
+    }
+
+    /// A tenth of a second of uniform white noise at `RMS`.
+    fn noise(rng: &mut StdRng) -> AudioFrame {
+        let peak = RMS * 3f64.sqrt();
+        frame(
+            (0..RATE / 10)
+                .map(|_| rng.gen_range(-peak..peak) as i16)
+                .collect(),
+        )
+    }
+
+    /// A tenth of a second of a 1 kHz tone at `RMS`.
+    fn tone(start: u32) -> AudioFrame {
+        frame(
+            (start..start + RATE / 10)
+                .map(|i| {
+                    let t = i as f64 / RATE as f64;
+                    ((t * 2.0 * std::f64::consts::PI * 1000.0).sin() * RMS * 2f64.sqrt()) as i16
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn tells_a_tone_from_noise_of_the_same_energy() {
+        let mut rng = StdRng::seed_from_u64(46);
+        let mut detector = VoiceDetector::new(&SquelchConfig::default());
+        for _ in 0..10 {
+            assert!(!detector.active(&noise(&mut rng)));
+        }
+        for i in 0..10 {
+            assert!(detector.active(&tone(i * RATE / 10)));
+        }
+        for _ in 0..10 {
+            assert!(!detector.active(&noise(&mut rng)));
+        }
+    }
+
+    #[test]
+    fn short_frames_keep_the_last_answer() {
+        let mut detector = VoiceDetector::new(&SquelchConfig::default());
+        assert!(detector.active(&tone(0)));
+        // Less than a window of silence completes nothing
+        assert!(detector.active(&frame(vec![0; 100])));
+    }
+}
@@ -0,0 +1,266 @@
+//! Simulates the server side of a KiwiSDR for developing and testing against without a receiver:
+//! the `/VER` and `/status` endpoints, `/kiwi/{n}/SND` streaming synthetic tones over noise and
+//! `/kiwi/{n}/W/F` drawing them on a waterfall.
+//!
+//! Faults can be set on the command line and changed while running through `PUT /sim/faults`,
+//! and `POST /sim/drop` cuts every connected client off.
+
+mod session;
+mod signal;
+mod waterfall;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::{State, WebSocketUpgrade};
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use signal::Carrier;
+
+const USAGE: &str = "usage: kiwi-sim [--port 8073] [--name NAME] [--password PW]... [--channels 4]
+                [--carrier HZ:DBM]... [--noise DBM] [--gps LAT,LON] [--busy] [--bad-password]
+                [--drop-after SECS] [--malformed-every FRAMES]";
+
+/// Misbehaviour to put clients through.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Faults {
+    /// Turns every new connection away as if all channels were taken.
+    pub busy: bool,
+    /// Rejects every password, right or not.
+    pub bad_password: bool,
+    /// Drops sessions without a close frame this many seconds after their audio starts.
+    pub drop_after: Option<u64>,
+    /// Sends a malformed frame after every this many sound frames.
+    pub malformed_every: Option<u32>,
+}
+
+pub struct Settings {
+    pub port: u16,
+    pub name: String,
+    /// Accepted user passwords; with none, anyone may connect.
+    pub passwords: Vec<String>,
+    pub channels: usize,
+    pub carriers: Vec<Carrier>,
+    pub noise_dbm: f64,
+    /// Receiver position, in degrees north and east.
+    pub gps: (f64, f64),
+}
+
+pub struct SimState {
+    pub settings: Settings,
+    faults: Mutex<Faults>,
+    users: AtomicUsize,
+    /// Bumped to cut off every session.
+    drops: watch::Sender<u64>,
+}
+
+/// A taken channel, given back when the session ends.
+pub struct User(Arc<SimState>);
+
+impl Drop for User {
+    fn drop(&mut self) {
+        self.0.users.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl SimState {
+    pub fn faults(&self) -> Faults {
+        self.faults.lock().unwrap().clone()
+    }
+
+    /// Takes a channel, unless they're all in use.
+    pub fn join(self: &Arc<Self>) -> Option<User> {
+        self.users
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |users| {
+                (users < self.settings.channels).then_some(users + 1)
+            })
+            .ok()
+            .map(|_| User(self.clone()))
+    }
+
+    /// Whether `password` logs in, given the configured passwords and faults.
+    pub fn accepts(&self, password: &str) -> bool {
+        let passwords = &self.settings.passwords;
+        !self.faults().bad_password
+            && (passwords.is_empty() || passwords.iter().any(|p| p == password))
+    }
+
+    pub fn drops(&self) -> watch::Receiver<u64> {
+        self.drops.subscribe()
+    }
+}
+
+fn parse_args() -> anyhow::Result<(Settings, Faults)> {
+    let mut settings = Settings {
+        port: 8073,
+        name: "kiwi-sim".to_string(),
+        passwords: Vec::new(),
+        channels: 4,
+        carriers: Vec::new(),
+        noise_dbm: -110.0,
+        gps: (51.5, -0.13),
+    };
+    let mut faults = Faults::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--port" => settings.port = value()?.parse()?,
+            "--name" => settings.name = value()?,
+            "--password" => settings.passwords.push(value()?),
+            "--channels" => settings.channels = value()?.parse()?,
+            "--carrier" => settings.carriers.push(value()?.parse()?),
+            "--noise" => settings.noise_dbm = value()?.parse()?,
+            "--gps" => {
+                let value = value()?;
+                let (latitude, longitude) = value
+                    .split_once(',')
+                    .ok_or_else(|| anyhow::anyhow!("expected LAT,LON, got {}", value))?;
+                settings.gps = (latitude.trim().parse()?, longitude.trim().parse()?);
+            }
+            "--busy" => faults.busy = true,
+            "--bad-password" => faults.bad_password = true,
+            "--drop-after" => faults.drop_after = Some(value()?.parse()?),
+            "--malformed-every" => faults.malformed_every = Some(value()?.parse()?),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            other => anyhow::bail!("unknown argument {}\n{}", other, USAGE),
+        }
+    }
+
+    if settings.carriers.is_empty() {
+        settings.carriers = vec![
+            Carrier {
+                frequency: 7_100_000.0,
+                dbm: -73.0,
+            },
+            Carrier {
+                frequency: 9_650_000.0,
+                dbm: -60.0,
+            },
+        ];
+    }
+
+    Ok((settings, faults))
+}
+
+#[tokio::main]
+async fn main() {
+    simple_logger::init_with_level(log::Level::Info).unwrap();
+
+    let (settings, faults) = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    for carrier in &settings.carriers {
+        log::info!(
+            "carrier at {} Hz, {} dBm",
+            carrier.frequency.to_string().green(),
+            carrier.dbm
+        );
+    }
+    if faults.busy
+        || faults.bad_password
+        || faults.drop_after.is_some()
+        || faults.malformed_every.is_some()
+    {
+        log::info!("starting with faults {:?}", faults);
+    }
+
+    let port = settings.port;
+    let state = Arc::new(SimState {
+        settings,
+        faults: Mutex::new(faults),
+        users: AtomicUsize::new(0),
+        drops: watch::channel(0).0,
+    });
+
+    let router = Router::new()
+        .route("/VER", get(version))
+        .route("/status", get(status))
+        .route("/sim/faults", get(get_faults).put(put_faults))
+        .route("/sim/drop", post(drop_sessions))
+        .route("/kiwi/:number/SND", get(sound))
+        .route("/kiwi/:number/W/F", get(waterfall))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
+        .await
+        .unwrap();
+    log::info!("simulating a KiwiSDR on port {}", port.to_string().green());
+    axum::serve(listener, router).await.unwrap();
+}
+
+#[derive(Serialize)]
+struct VerResponse {
+    maj: i32,
+    min: i32,
+    ts: u128,
+}
+
+async fn version() -> Json<VerResponse> {
+    Json(VerResponse {
+        maj: 1,
+        min: 665,
+        ts: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis(),
+    })
+}
+
+/// The `key=value` lines a Kiwi's `/status` gives, as far as they're simulated.
+async fn status(State(state): State<Arc<SimState>>) -> String {
+    let settings = &state.settings;
+    format!(
+        "status=active\noffline=no\nname={}\nsdr_hw=KiwiSDR v1.665\nusers={}\nusers_max={}\ngps=({:.6}, {:.6})\nbands=0-30000000\nsw_version=KiwiSDR_v1.665\n",
+        settings.name,
+        state.users.load(Ordering::SeqCst),
+        settings.channels,
+        settings.gps.0,
+        settings.gps.1
+    )
+}
+
+async fn get_faults(State(state): State<Arc<SimState>>) -> Json<Faults> {
+    Json(state.faults())
+}
+
+async fn put_faults(
+    State(state): State<Arc<SimState>>,
+    Json(faults): Json<Faults>,
+) -> Json<Faults> {
+    log::info!("faults are now {:?}", faults);
+    *state.faults.lock().unwrap() = faults.clone();
+    Json(faults)
+}
+
+async fn drop_sessions(State(state): State<Arc<SimState>>) {
+    log::info!("dropping every session");
+    state.drops.send_modify(|generation| *generation += 1);
+}
+
+async fn sound(ws: WebSocketUpgrade, State(state): State<Arc<SimState>>) -> Response {
+    ws.on_upgrade(move |socket| session::run(socket, state))
+}
+
+async fn waterfall(ws: WebSocketUpgrade, State(state): State<Arc<SimState>>) -> Response {
+    ws.on_upgrade(move |socket| waterfall::run(socket, state))
+}
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::ws::{Message, WebSocket};
+use colored::Colorize;
+use sdr_scraper::audio::ima_adpcm::IMA_ADPCM_Encoder;
+
+use crate::signal::{Channel, Synthesizer, SAMPLE_RATE};
+use crate::SimState;
+
+/// Samples in each SND frame, 512 bytes once compressed.
+const FRAME_SAMPLES: usize = 1024;
+
+/// Set in an SND frame's flags when its audio is ADPCM.
+const FLAG_COMPRESSED: u8 = 0x10;
+
+/// How long a client gets to log in.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Clients that go quiet for this long, keepalives included, are disconnected like a Kiwi does.
+const INACTIVITY_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub fn msg(text: &str) -> Message {
+    Message::Binary(format!("MSG {}", text).into_bytes())
+}
+
+/// Frames a client must cope with: a cut short SND header, a MSG that isn't UTF-8, an
+/// `audio_init` without a rate and an unknown tag.
+fn malformed(seq: u32) -> Message {
+    Message::Binary(match seq % 4 {
+        0 => b"SND\x10\x00".to_vec(),
+        1 => b"MSG \xff\xfe\xfd".to_vec(),
+        2 => b"MSG audio_init".to_vec(),
+        _ => b"XYZ\x00\x01\x02\x03\x04\x05\x06\x07".to_vec(),
+    })
+}
+
+/// Waits for the client's `SET auth t=kiwi p=...`, returning the password it gave.
+pub async fn password(socket: &mut WebSocket) -> Option<String> {
+    loop {
+        let Message::Text(text) = socket.recv().await?.ok()? else {
+            continue;
+        };
+        if let Some(auth) = text.strip_prefix("SET auth ") {
+            let password = auth
+                .split_whitespace()
+                .find_map(|part| part.strip_prefix("p="))
+                .unwrap_or("#");
+            return Some(password.to_string());
+        }
+    }
+}
+
+/// Serves one `/kiwi/{n}/SND` connection.
+pub async fn run(mut socket: WebSocket, state: Arc<SimState>) {
+    let settings = &state.settings;
+
+    let _user = match state.join() {
+        Some(user) if !state.faults().busy => user,
+        _ => {
+            log::info!("{}", "turning a client away, too busy".yellow());
+            let _ = socket
+                .send(msg(&format!("too_busy={}", settings.channels)))
+                .await;
+            let _ = socket.close().await;
+            return;
+        }
+    };
+
+    let Ok(Some(password)) = tokio::time::timeout(AUTH_TIMEOUT, password(&mut socket)).await else {
+        return;
+    };
+    if !state.accepts(&password) {
+        log::info!("{}", "rejecting a bad password".yellow());
+        let _ = socket.send(msg("badp=1")).await;
+        let _ = socket.close().await;
+        return;
+    }
+
+    for text in [
+        "badp=0".to_string(),
+        "version_maj=1 version_min=665".to_string(),
+        "bandwidth=30000000".to_string(),
+        format!("sample_rate={}.000", SAMPLE_RATE),
+        format!("audio_init=0 audio_rate={}", SAMPLE_RATE),
+    ] {
+        if socket.send(msg(&text)).await.is_err() {
+            return;
+        }
+    }
+    log::info!("{}", "client logged in".green());
+
+    let mut synthesizer = Synthesizer::new(settings.carriers.clone(), settings.noise_dbm);
+    let mut encoder = IMA_ADPCM_Encoder::new();
+    let mut channel: Option<Channel> = None;
+    let mut compression = true;
+    let mut streaming_since: Option<Instant> = None;
+    let mut last_activity = Instant::now();
+    let mut seq: u32 = 0;
+    let mut samples = vec![0; FRAME_SAMPLES];
+
+    let mut drops = state.drops();
+    let mut frames = tokio::time::interval(Duration::from_secs_f64(
+        FRAME_SAMPLES as f64 / SAMPLE_RATE as f64,
+    ));
+
+    loop {
+        tokio::select! {
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                last_activity = Instant::now();
+
+                if text.starts_with("SET AR OK") {
+                    streaming_since.get_or_insert_with(Instant::now);
+                } else if text.starts_with("SET mod=") {
+                    channel = Channel::parse(&text);
+                    log::info!("client tuned to {:?}", channel);
+                } else if let Some(value) = text.strip_prefix("SET compression=") {
+                    let enabled = value.trim() != "0";
+                    if enabled && !compression {
+                        encoder = IMA_ADPCM_Encoder::new();
+                    }
+                    compression = enabled;
+                }
+            }
+            _ = frames.tick() => {
+                let Some(since) = streaming_since else {
+                    continue;
+                };
+                let faults = state.faults();
+                if faults.drop_after.is_some_and(|secs| since.elapsed() >= Duration::from_secs(secs)) {
+                    log::info!("{}", "dropping a client".yellow());
+                    return;
+                }
+                if last_activity.elapsed() >= INACTIVITY_TIMEOUT {
+                    log::info!("{}", "client timed out".yellow());
+                    break;
+                }
+
+                let dbm = synthesizer.next(channel.as_ref(), &mut samples);
+                let smeter = ((dbm + 127.0) * 10.0).clamp(0.0, u16::MAX as f64) as u16;
+
+                let mut frame = b"SND".to_vec();
+                frame.push(if compression { FLAG_COMPRESSED } else { 0 });
+                frame.extend(seq.to_le_bytes());
+                frame.extend(smeter.to_be_bytes());
+                if compression {
+                    let start = frame.len();
+                    frame.resize(start + FRAME_SAMPLES / 2, 0);
+                    encoder.encode_block(&samples, &mut frame[start..]);
+                } else {
+                    frame.extend(samples.iter().flat_map(|sample| sample.to_be_bytes()));
+                }
+                if socket.send(Message::Binary(frame)).await.is_err() {
+                    break;
+                }
+
+                seq = seq.wrapping_add(1);
+                if let Some(every) = faults.malformed_every {
+                    if seq.checked_rem(every) == Some(0)
+                        && socket.send(malformed(seq / every)).await.is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+            _ = drops.changed() => {
+                log::info!("{}", "dropping a client".yellow());
+                return;
+            }
+        }
+    }
+
+    let _ = socket.close().await;
+}
@@ -0,0 +1,129 @@
+use std::f64::consts::TAU;
+
+use rand::Rng;
+
+/// Rate of the audio the simulator streams, as announced in `audio_init`.
+pub const SAMPLE_RATE: u32 = 12000;
+
+/// Tone AM and FM carriers are heard as, as if they were modulated with a test tone.
+const MODULATION_TONE: f64 = 1000.0;
+
+/// Level the receiver's AGC holds the strongest signal at, as a fraction of full scale.
+const AGC_TARGET: f64 = 0.3;
+
+/// A signal on the simulated band.
+#[derive(Clone, Debug)]
+pub struct Carrier {
+    pub frequency: f64,
+    pub dbm: f64,
+}
+
+impl std::str::FromStr for Carrier {
+    type Err = anyhow::Error;
+
+    /// Parses `HZ:DBM`, e.g. `7100000:-73`.
+    fn from_str(s: &str) -> anyhow::Result<Carrier> {
+        let (frequency, dbm) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("carrier {} isn't HZ:DBM", s))?;
+        Ok(Carrier {
+            frequency: frequency.parse()?,
+            dbm: dbm.parse()?,
+        })
+    }
+}
+
+/// What a client has asked the channel to listen to, from its `SET mod=...`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    pub mode: String,
+    pub low_cut: f64,
+    pub high_cut: f64,
+    pub frequency: f64,
+}
+
+impl Channel {
+    /// Parses `SET mod=am low_cut=-5000 high_cut=5000 freq=7100.000`.
+    pub fn parse(command: &str) -> Option<Channel> {
+        let mut channel = Channel {
+            mode: String::new(),
+            low_cut: 0.0,
+            high_cut: 0.0,
+            frequency: 0.0,
+        };
+        for (key, value) in command.split_whitespace().filter_map(|p| p.split_once('=')) {
+            match key {
+                "mod" => channel.mode = value.to_string(),
+                "low_cut" => channel.low_cut = value.parse().ok()?,
+                "high_cut" => channel.high_cut = value.parse().ok()?,
+                "freq" => channel.frequency = value.parse::<f64>().ok()? * 1000.0,
+                _ => {}
+            }
+        }
+        (!channel.mode.is_empty()).then_some(channel)
+    }
+
+    /// The audio frequency `carrier` is heard at, if it falls in the passband.
+    fn audio_frequency(&self, carrier: f64) -> Option<f64> {
+        let offset = carrier - self.frequency;
+        let (offset, tone) = match self.mode.as_str() {
+            "lsb" => (-offset, -offset),
+            "usb" | "cw" => (offset, offset),
+            _ => (offset, MODULATION_TONE),
+        };
+        let (low, high) = match self.mode.as_str() {
+            "lsb" => (-self.high_cut, -self.low_cut),
+            _ => (self.low_cut, self.high_cut),
+        };
+        (low..=high).contains(&offset).then_some(tone)
+    }
+}
+
+/// Synthesizes what a channel hears: its carriers as tones over the band noise, levelled by AGC.
+pub struct Synthesizer {
+    carriers: Vec<Carrier>,
+    noise_dbm: f64,
+    phases: Vec<f64>,
+}
+
+impl Synthesizer {
+    pub fn new(carriers: Vec<Carrier>, noise_dbm: f64) -> Synthesizer {
+        Synthesizer {
+            phases: vec![0.0; carriers.len()],
+            carriers,
+            noise_dbm,
+        }
+    }
+
+    /// Fills `samples` with the next block of audio and returns the passband power in dBm.
+    pub fn next(&mut self, channel: Option<&Channel>, samples: &mut [i16]) -> f64 {
+        let mut rng = rand::thread_rng();
+        let noise = 10f64.powf(self.noise_dbm / 20.0);
+
+        let tones = self
+            .carriers
+            .iter()
+            .zip(self.phases.iter_mut())
+            .filter_map(|(carrier, phase)| {
+                let tone = channel?.audio_frequency(carrier.frequency)?;
+                Some((tone, 10f64.powf(carrier.dbm / 20.0), phase))
+            })
+            .collect::<Vec<_>>();
+
+        let power = noise.powi(2) + tones.iter().map(|(_, v, _)| v.powi(2)).sum::<f64>();
+        let gain = AGC_TARGET * i16::MAX as f64 / power.sqrt();
+
+        let mut tones = tones;
+        for sample in samples.iter_mut() {
+            // Sum of uniforms is close enough to Gaussian for a noise floor
+            let mut value = noise * (0..4).map(|_| rng.gen_range(-1.0..1.0)).sum::<f64>() * 0.87;
+            for (frequency, amplitude, phase) in tones.iter_mut() {
+                value += *amplitude * phase.sin();
+                **phase = (**phase + TAU * *frequency / SAMPLE_RATE as f64) % TAU;
+            }
+            *sample = (value * gain).clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+        }
+
+        10.0 * power.log10()
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::ws::{Message, WebSocket};
+use colored::Colorize;
+use rand::Rng;
+use sdr_scraper::audio::ima_adpcm::IMA_ADPCM_Encoder;
+
+use crate::session::{msg, password, AUTH_TIMEOUT};
+use crate::SimState;
+
+/// Bins in each waterfall line.
+const WIDTH: usize = 1024;
+
+/// Full band the simulated Kiwi covers.
+const BANDWIDTH: f64 = 30e6;
+
+/// Deepest zoom, where the band is spread over `WIDTH << ZOOM_MAX` bins.
+const ZOOM_MAX: u32 = 14;
+
+/// Samples appended to each line before compressing it, as the Kiwi does.
+const ADPCM_PAD: usize = 10;
+
+/// Bins either side of a carrier it's drawn across, as ADPCM can't follow a lone spike.
+const CARRIER_BINS: i64 = 3;
+
+/// Lines a second for each `wf_speed`.
+const LINE_RATES: [f64; 5] = [0.0, 1.0, 5.0, 13.0, 23.0];
+
+/// Where the client has the waterfall pointed.
+struct View {
+    zoom: u32,
+    center: f64,
+}
+
+impl View {
+    /// The first bin at full zoom, snapped so the view stays inside the band.
+    fn x_bin(&self) -> u32 {
+        let span = BANDWIDTH / 2f64.powi(self.zoom as i32);
+        let start = (self.center - span / 2.0).clamp(0.0, BANDWIDTH - span);
+        (start / BANDWIDTH * (WIDTH << ZOOM_MAX) as f64) as u32
+    }
+
+    fn update(&mut self, command: &str) {
+        for (key, value) in command.split_whitespace().filter_map(|p| p.split_once('=')) {
+            match key {
+                "zoom" => self.zoom = value.parse::<u32>().unwrap_or(self.zoom).min(ZOOM_MAX),
+                "cf" => self.center = value.parse::<f64>().map_or(self.center, |cf| cf * 1000.0),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Draws one line: the noise floor with every carrier over the bins around it.
+fn line(state: &SimState, view: &View) -> (u32, Vec<u8>) {
+    let mut rng = rand::thread_rng();
+    let x_bin = view.x_bin();
+    let full_zoom_bin = BANDWIDTH / (WIDTH << ZOOM_MAX) as f64;
+    let start = x_bin as f64 * full_zoom_bin;
+    let bin_width = full_zoom_bin * (1 << (ZOOM_MAX - view.zoom)) as f64;
+
+    let mut dbm = (0..WIDTH)
+        .map(|_| state.settings.noise_dbm + rng.gen_range(-3.0..3.0))
+        .collect::<Vec<f64>>();
+    for carrier in &state.settings.carriers {
+        let center = ((carrier.frequency - start) / bin_width).floor() as i64;
+        for bin in center - CARRIER_BINS..=center + CARRIER_BINS {
+            if let Some(bin) = usize::try_from(bin).ok().and_then(|bin| dbm.get_mut(bin)) {
+                *bin = bin.max(carrier.dbm);
+            }
+        }
+    }
+
+    let mut samples = dbm
+        .iter()
+        .map(|dbm| (dbm + 255.0).clamp(0.0, 255.0) as i16)
+        .collect::<Vec<i16>>();
+    samples.resize(WIDTH + ADPCM_PAD, samples[WIDTH - 1]);
+
+    let mut data = vec![0; samples.len() / 2];
+    IMA_ADPCM_Encoder::new().encode_block(&samples, &mut data);
+    (x_bin, data)
+}
+
+/// Serves one `/kiwi/{n}/W/F` connection. Waterfalls don't take a channel of their own.
+pub async fn run(mut socket: WebSocket, state: Arc<SimState>) {
+    let Ok(Some(password)) = tokio::time::timeout(AUTH_TIMEOUT, password(&mut socket)).await else {
+        return;
+    };
+    if !state.accepts(&password) {
+        let _ = socket.send(msg("badp=1")).await;
+        let _ = socket.close().await;
+        return;
+    }
+
+    for text in [
+        "badp=0".to_string(),
+        format!("bandwidth={:.0}", BANDWIDTH),
+        format!("zoom_max={}", ZOOM_MAX),
+        "wf_setup".to_string(),
+    ] {
+        if socket.send(msg(&text)).await.is_err() {
+            return;
+        }
+    }
+    log::info!("{}", "waterfall client logged in".green());
+
+    let mut view = View {
+        zoom: 0,
+        center: BANDWIDTH / 2.0,
+    };
+    let mut speed = 0;
+    let mut seq: u32 = 0;
+    let mut drops = state.drops();
+
+    loop {
+        let rate = LINE_RATES[speed];
+        let next_line = async {
+            if rate > 0.0 {
+                tokio::time::sleep(Duration::from_secs_f64(1.0 / rate)).await
+            } else {
+                std::future::pending().await
+            }
+        };
+
+        tokio::select! {
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                if text.starts_with("SET zoom=") {
+                    view.update(&text);
+                    log::info!("waterfall at zoom {} around {} Hz", view.zoom, view.center);
+                } else if let Some(value) = text.strip_prefix("SET wf_speed=") {
+                    speed = value.trim().parse::<usize>().unwrap_or(speed).min(LINE_RATES.len() - 1);
+                }
+            }
+            _ = next_line => {
+                let (x_bin, data) = line(&state, &view);
+                let mut frame = b"W/F\0".to_vec();
+                frame.extend(x_bin.to_le_bytes());
+                frame.extend(view.zoom.to_le_bytes());
+                frame.extend(seq.to_le_bytes());
+                frame.extend(data);
+                if socket.send(Message::Binary(frame)).await.is_err() {
+                    break;
+                }
+                seq = seq.wrapping_add(1);
+            }
+            _ = drops.changed() => {
+                log::info!("{}", "dropping a waterfall client".yellow());
+                return;
+            }
+        }
+    }
+
+    let _ = socket.close().await;
+}
@@ -0,0 +1,125 @@
+//! Submits, lists and cancels one-off recording jobs on a running scraper through its `/jobs`
+//! API.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use colored::Colorize;
+
+use sdr_scraper::jobs::{Job, JobRequest};
+use sdr_scraper::sdr::Mode;
+
+const USAGE: &str = "usage: sdr-jobs [--server http://localhost:3000] COMMAND
+  list
+  submit STATION KHZ MODE START END [PRIORITY]
+                                     record from START to END, or for END like 30m
+  cancel ID
+
+MODE is am, fm, lsb or usb. Times are UTC, like 2026-10-19T19:00:00Z, 2026-10-19 19:00
+or now. Jobs of a higher PRIORITY than stations on the same Kiwi may take their channels.";
+
+/// Reads a UTC time written in RFC 3339, as `YYYY-MM-DD HH:MM`, or as `now`.
+fn parse_time(text: &str) -> anyhow::Result<DateTime<Utc>> {
+    if text == "now" {
+        return Ok(Utc::now());
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M")
+        .map(|time| time.and_utc())
+        .map_err(|_| anyhow::anyhow!("invalid time {:?}", text))
+}
+
+fn print_job(job: &Job) {
+    println!(
+        "{:>4}  {:<10} {:<12} {:>8.1} kHz {:<3}  {} - {}",
+        job.id.to_string().green(),
+        format!("{:?}", job.state),
+        job.station,
+        job.tuning.frequency() / 1000.0,
+        job.tuning.mode(),
+        job.start.format("%Y-%m-%d %H:%M"),
+        job.end.format("%Y-%m-%d %H:%M"),
+    );
+    if let Some(error) = &job.error {
+        println!("      {}", error.red());
+    }
+    for file in &job.files {
+        println!("      {}", file);
+    }
+}
+
+/// Fails with the server's explanation if it turned the request down.
+async fn check(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    anyhow::bail!("{}: {}", status, response.text().await.unwrap_or_default())
+}
+
+async fn run() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1).collect::<Vec<String>>();
+    let mut server = "http://localhost:3000".to_string();
+    if args.first().map(String::as_str) == Some("--server") {
+        if args.len() < 2 {
+            anyhow::bail!("--server needs a value\n{}", USAGE);
+        }
+        server = args.remove(1);
+        args.remove(0);
+    }
+    let server = server.trim_end_matches('/');
+    let client = reqwest::Client::new();
+
+    match args.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+        ["list"] => {
+            let response = check(client.get(format!("{}/jobs", server)).send().await?).await?;
+            for job in response.json::<Vec<Job>>().await? {
+                print_job(&job);
+            }
+        }
+        ["submit", station, khz, mode, start, end, ref priority @ ..] if priority.len() <= 1 => {
+            let tuning = mode
+                .parse::<Mode>()?
+                .tuning(khz.parse::<f64>()? * 1000.0, None, None);
+            let start = parse_time(start)?;
+            let end = match humantime::parse_duration(end) {
+                Ok(duration) => start + chrono::Duration::from_std(duration)?,
+                Err(_) => parse_time(end)?,
+            };
+            let request = JobRequest {
+                station: station.to_string(),
+                tuning,
+                start,
+                end,
+                priority: priority
+                    .first()
+                    .map_or(Ok(0), |priority| priority.parse())?,
+            };
+            let response = client
+                .post(format!("{}/jobs", server))
+                .json(&request)
+                .send()
+                .await?;
+            print_job(&check(response).await?.json::<Job>().await?);
+        }
+        ["cancel", id] => {
+            let id = id.parse::<u64>()?;
+            let response = client
+                .delete(format!("{}/jobs/{}", server, id))
+                .send()
+                .await?;
+            print_job(&check(response).await?.json::<Job>().await?);
+        }
+        ["--help" | "-h"] => println!("{}", USAGE),
+        _ => anyhow::bail!("{}", USAGE),
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
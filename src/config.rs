@@ -1,20 +1,430 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::Weekday;
 use serde::{Deserialize, Serialize};
 
-use crate::sdr::Tuning;
+use crate::audio::OverflowPolicy;
+use crate::schedule::{Cron, TimeOfDay};
+use crate::sdr::{Mode, Tuning};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// (De)serializes durations as human readable strings like `30m` or `1h 30m`.
+mod humantime_duration {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        humantime::parse_duration(&string).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub enum SDRKind {
+    #[default]
     KiwiSDR,
+    OpenWebRX,
+    RtlTcp,
+    SpyServer,
+    WebSDR,
+    HttpStream,
+    /// Replays an IQ WAV or Kiwi capture, with the file's path as the endpoint.
+    File,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SDRStationConfig {
     pub name: String,
+    #[serde(default)]
+    pub kind: SDRKind,
     pub endpoint: String,
     pub password: Option<String>,
     pub agc: bool,
     pub gain: Option<i32>,
-    pub frequency: Vec<f64>,
+    pub frequency: Vec<FrequencyConfig>,
+    #[serde(default = "default_sinks")]
+    pub sinks: Vec<SinkConfig>,
+    /// Playback speed of `File` stations, 1.0 being real time and 0 as fast as possible.
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+    /// File name template for `KiwiSDR` stations to tee every received frame to, for later replay.
+    #[serde(default)]
+    pub capture: Option<String>,
+    /// Archives the waterfall of `KiwiSDR` stations alongside their audio.
+    #[serde(default)]
+    pub waterfall: Option<WaterfallConfig>,
+    /// Sweeps a range with the waterfall of a `KiwiSDR` station, as an extra `{name}_survey` station.
+    #[serde(default)]
+    pub survey: Option<SurveyConfig>,
+    /// Records new signals seen on the waterfall of a `KiwiSDR` station, as an extra
+    /// `{name}_trigger` station.
+    #[serde(default)]
+    pub trigger: Option<TriggerConfig>,
+    /// When the station is on air, for its frequencies without a schedule of their own and its
+    /// survey and trigger. Without one, it's on air all the time.
+    #[serde(default)]
+    pub schedule: Option<Vec<WindowConfig>>,
+    /// Where the receiver is, for schedules around sunrise and sunset. `KiwiSDR` stations
+    /// without one use the GPS position from their `/status`.
+    #[serde(default)]
+    pub coordinates: Option<Coordinates>,
+    /// Which stations go first when a `KiwiSDR` hasn't enough channels for all of them, higher
+    /// before lower. Those left out wait for a channel to come free.
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// A frequency to record, in Hz, either on its own in USB or with a mode, passband and schedule.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum FrequencyConfig {
+    Always(f64),
+    Tuned {
+        frequency: f64,
+        #[serde(default)]
+        mode: Mode,
+        /// Passband edges in Hz from `frequency`, like -2700 and -300 for LSB. Default to the
+        /// usual ones for the mode, see `Mode::tuning`.
+        #[serde(default)]
+        low_cut: Option<i32>,
+        #[serde(default)]
+        high_cut: Option<i32>,
+        #[serde(default)]
+        schedule: Option<Vec<WindowConfig>>,
+    },
+}
+
+impl FrequencyConfig {
+    pub fn frequency(&self) -> f64 {
+        match self {
+            FrequencyConfig::Always(frequency) | FrequencyConfig::Tuned { frequency, .. } => {
+                *frequency
+            }
+        }
+    }
+
+    pub fn tuning(&self) -> Tuning {
+        match self {
+            FrequencyConfig::Always(frequency) => Mode::USB.tuning(*frequency, None, None),
+            FrequencyConfig::Tuned {
+                frequency,
+                mode,
+                low_cut,
+                high_cut,
+                ..
+            } => mode.tuning(*frequency, *low_cut, *high_cut),
+        }
+    }
+
+    pub fn schedule(&self) -> Option<&[WindowConfig]> {
+        match self {
+            FrequencyConfig::Always(_) => None,
+            FrequencyConfig::Tuned { schedule, .. } => schedule.as_deref(),
+        }
+    }
+}
+
+/// A time a station is on air, see `schedule::on_air`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum WindowConfig {
+    /// From `start` to `end` every day, or only on `days` like `"Mon"`. Times are UTC like
+    /// `"21:30"`, or relative to the sun like `"sunset-30m"` or `"sunrise+1h"`. Windows that
+    /// end before they start run into the next day, and count as the day they start on.
+    Daily {
+        start: TimeOfDay,
+        end: TimeOfDay,
+        #[serde(default)]
+        days: Vec<Weekday>,
+        /// Where to take sunrise and sunset at instead of the receiver, such as the far end of
+        /// a path.
+        #[serde(default)]
+        coordinates: Option<Coordinates>,
+    },
+    /// For `duration` from every minute `cron` matches, e.g. `"0 */2 * * 1-5"`.
+    Cron {
+        cron: Cron,
+        #[serde(with = "humantime_duration")]
+        duration: Duration,
+    },
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum SinkConfig {
+    /// Rotating WAV files, see `audio::Writer`.
+    File {
+        #[serde(default = "default_record_dir")]
+        dir: PathBuf,
+        /// File name, see `audio::render_template`.
+        #[serde(default = "default_file_template")]
+        template: String,
+        #[serde(default = "default_rotate", with = "humantime_duration")]
+        rotate: Duration,
+        /// Only records while there's a signal, going by the S-meter.
+        #[serde(default)]
+        squelch: Option<SquelchConfig>,
+    },
+    /// An external command fed raw s16le PCM on stdin, see `audio::ProcessSink`.
+    Process {
+        command: String,
+        #[serde(default = "default_restart_delay", with = "humantime_duration")]
+        restart_delay: Duration,
+    },
+    /// Raw s16le PCM sent in UDP datagrams to `address`, like `host:7355`, as gqrx does for
+    /// decoders listening on a socket. See `audio::UdpSink`.
+    Stream { address: String },
+    /// A decoder run on the audio in-process, its output published as station events.
+    Decoder { decoder: DecoderKind },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DecoderKind {
+    /// Touch tones, see `audio::DtmfDecoder`.
+    Dtmf,
+}
+
+/// What a squelched `File` sink does with each transmission. Either way, the transmissions in
+/// each file are listed in a `.json` next to it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SquelchMode {
+    /// A file of its own.
+    #[default]
+    Files,
+    /// Appended to the current file, with a cue marker where it starts.
+    Cues,
+    /// Everything is recorded, with cue markers where transmissions start.
+    Continuous,
+}
+
+/// What tells a squelch there's a signal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SquelchDetector {
+    /// The receiver's S-meter.
+    #[default]
+    Rssi,
+    /// The audio itself, see `audio::VoiceDetector`.
+    Voice,
+}
+
+/// Gating of a `File` sink on the S-meter, see `audio::Squelch`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SquelchConfig {
+    pub detector: SquelchDetector,
+    /// Opens above this S-meter reading, in dBm. Without one, opens `margin` dB above the
+    /// noise floor, which is followed as it moves. `Voice` goes by the audio's loudness instead.
+    pub threshold: Option<f64>,
+    pub margin: f64,
+    /// `Voice` only: audio whose spectral flatness, from 0 for a pure tone to about 0.56 for
+    /// noise, is below this counts as a signal whatever its level.
+    pub flatness: f32,
+    /// `Voice` only: audio crossing zero more often than this per sample is taken for hiss,
+    /// however loud.
+    pub max_zcr: f32,
+    /// How long the signal must be there before the squelch opens.
+    #[serde(with = "humantime_duration")]
+    pub attack: Duration,
+    /// How long the squelch stays open once the signal is gone.
+    #[serde(with = "humantime_duration")]
+    pub hang: Duration,
+    /// Audio from before the squelch opened that goes at the start of a transmission.
+    #[serde(with = "humantime_duration")]
+    pub pre_roll: Duration,
+    pub mode: SquelchMode,
+}
+
+impl Default for SquelchConfig {
+    fn default() -> Self {
+        SquelchConfig {
+            detector: SquelchDetector::Rssi,
+            threshold: None,
+            margin: 10.0,
+            flatness: 0.35,
+            max_zcr: 0.35,
+            attack: Duration::from_millis(200),
+            hang: Duration::from_secs(2),
+            pre_roll: Duration::from_secs(1),
+            mode: SquelchMode::Files,
+        }
+    }
+}
+
+/// A Kiwi waterfall archived to spectrogram PNGs and NPY row files, see `spectrum::Archive`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WaterfallConfig {
+    /// The band's full width is shown over 2^zoom.
+    pub zoom: u8,
+    /// Defaults to the station's frequency.
+    pub center_frequency: Option<f64>,
+    /// Line rate, from 1 (about one a second) through 4 (fastest).
+    pub speed: u8,
+    /// Range the PNG's colors are spread over, also sent to the Kiwi.
+    pub min_db: i32,
+    pub max_db: i32,
+    pub dir: PathBuf,
+    /// File name without extension, see `audio::render_template`. `.npy` and `.png` are appended.
+    pub template: String,
+    #[serde(with = "humantime_duration")]
+    pub rotate: Duration,
+}
+
+impl Default for WaterfallConfig {
+    fn default() -> Self {
+        WaterfallConfig {
+            zoom: 4,
+            center_frequency: None,
+            speed: 1,
+            min_db: -110,
+            max_db: -10,
+            dir: default_record_dir(),
+            template: "{name}_waterfall_%Y%m%d_%H%M%S".to_string(),
+            rotate: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// A band occupancy survey, see `spectrum::Survey`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SurveyConfig {
+    /// Range to sweep, in Hz.
+    pub start: f64,
+    pub end: f64,
+    /// Each step covers the band's full width over 2^zoom. Bins should come out narrower than
+    /// `channel_width`, or some channels go unmeasured.
+    pub zoom: u8,
+    /// Width of the channels the results are reported for, in Hz.
+    pub channel_width: f64,
+    /// How long the waterfall stays on each step.
+    #[serde(with = "humantime_duration")]
+    pub dwell: Duration,
+    /// Line rate, from 1 (about one a second) through 4 (fastest).
+    pub speed: u8,
+    /// dB a channel must stand above the noise to count as occupied.
+    pub threshold: f32,
+    /// Results are summed up over periods this long.
+    #[serde(with = "humantime_duration")]
+    pub interval: Duration,
+    /// How long results are kept around for the HTTP API.
+    #[serde(with = "humantime_duration")]
+    pub retention: Duration,
+}
+
+impl Default for SurveyConfig {
+    fn default() -> Self {
+        SurveyConfig {
+            start: 0.0,
+            end: 30e6,
+            zoom: 6,
+            channel_width: 5000.0,
+            dwell: Duration::from_secs(10),
+            speed: 4,
+            threshold: 10.0,
+            interval: Duration::from_secs(900),
+            retention: Duration::from_secs(7 * 86400),
+        }
+    }
+}
+
+/// A position on Earth, in degrees north and east.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// A span of frequencies, in Hz.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct FrequencyRange {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl FrequencyRange {
+    pub fn contains(&self, frequency: f64) -> bool {
+        (self.start..=self.end).contains(&frequency)
+    }
+}
+
+/// Recording of signals as they show up on the waterfall, see `sdr::kiwi::KiwiTriggerScraper`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TriggerConfig {
+    /// Ranges to watch. The waterfall is centered between the lowest and the highest, so they
+    /// must all fit into its view at `zoom`, or the station won't start.
+    pub ranges: Vec<FrequencyRange>,
+    /// The band's full width is shown over 2^zoom.
+    pub zoom: u8,
+    /// Line rate, from 1 (about one a second) through 4 (fastest).
+    pub speed: u8,
+    /// dB a signal must stand above the noise floor.
+    pub margin: f32,
+    /// Lines a signal must be seen in before it's recorded.
+    pub confirm: u32,
+    /// How long a signal may be missing before its recording ends.
+    #[serde(with = "humantime_duration")]
+    pub hold: Duration,
+    /// Recordings are cut off after this long, even if the signal is still there.
+    #[serde(with = "humantime_duration")]
+    pub max_duration: Duration,
+    /// Signals recorded at once, each taking one of the Kiwi's channels.
+    pub channels: usize,
+    pub dir: PathBuf,
+    /// File name, see `audio::render_template`. A `.json` file with the detection goes next to it.
+    pub template: String,
+}
+
+impl Default for TriggerConfig {
+    fn default() -> Self {
+        TriggerConfig {
+            ranges: Vec::new(),
+            zoom: 8,
+            speed: 2,
+            margin: 15.0,
+            confirm: 3,
+            hold: Duration::from_secs(10),
+            max_duration: Duration::from_secs(600),
+            channels: 1,
+            dir: default_record_dir(),
+            template: "{name}_{frequency}_{mode}_%Y%m%d_%H%M%S.wav".to_string(),
+        }
+    }
+}
+
+fn default_record_dir() -> PathBuf {
+    PathBuf::from("./RECORD")
+}
+
+fn default_file_template() -> String {
+    "{name}_%Y%m%d_%H%M%S.wav".to_string()
+}
+
+fn default_rotate() -> Duration {
+    Duration::from_secs(1800)
+}
+
+fn default_restart_delay() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_sinks() -> Vec<SinkConfig> {
+    vec![SinkConfig::File {
+        dir: default_record_dir(),
+        template: default_file_template(),
+        rotate: default_rotate(),
+        squelch: None,
+    }]
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -22,4 +432,95 @@ pub struct Config {
     pub location: String,
     pub identity: String,
     pub stations: Vec<SDRStationConfig>,
+    #[serde(default)]
+    pub writer: WriterConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+}
+
+/// One-off recordings submitted through `/jobs`, see `jobs::JobQueue`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// File the queue is kept in.
+    pub path: PathBuf,
+    /// Where job recordings go. Files are named after the station and job, like `kiwi_job3`.
+    pub sinks: Vec<SinkConfig>,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            path: PathBuf::from("jobs.json"),
+            sinks: default_sinks(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WriterConfig {
+    /// Number of blocking threads doing audio decoding and disk I/O.
+    pub threads: usize,
+    /// Packets each station may have queued before `overflow` kicks in.
+    pub queue_depth: usize,
+    pub overflow: OverflowPolicy,
+    /// Packets each station may have queued past `queue_depth` with the `Spill` policy,
+    /// before the oldest are dropped.
+    pub spill_limit: usize,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        WriterConfig {
+            threads: 2,
+            queue_depth: 64,
+            overflow: OverflowPolicy::Block,
+            spill_limit: 4096,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequencies_take_a_mode_and_passband() {
+        let frequencies: Vec<FrequencyConfig> = serde_json::from_str(
+            r#"[
+                7100000,
+                {"frequency": 6080000, "mode": "AM"},
+                {"frequency": 3630000, "mode": "LSB", "low_cut": -3000},
+                {"frequency": 5000000, "schedule": [{"cron": "0 * * * *", "duration": "5m"}]}
+            ]"#,
+        )
+        .unwrap();
+        let tunings = frequencies
+            .iter()
+            .map(FrequencyConfig::tuning)
+            .collect::<Vec<Tuning>>();
+        assert_eq!(
+            tunings[0],
+            Mode::USB.tuning(7_100_000.0, Some(300), Some(2700))
+        );
+        assert_eq!(
+            tunings[1],
+            Tuning::AM {
+                bandwidth: 9000,
+                frequency: 6_080_000.0
+            }
+        );
+        assert_eq!(
+            tunings[2],
+            Tuning::LSB {
+                low_cut: -3000,
+                high_cut: -300,
+                frequency: 3_630_000.0
+            }
+        );
+        assert_eq!(tunings[3].mode(), "usb");
+        assert!(frequencies[3].schedule().is_some());
+        assert!(frequencies[2].schedule().is_none());
+    }
 }
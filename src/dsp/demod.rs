@@ -0,0 +1,183 @@
+use std::f64::consts::PI;
+
+use crate::sdr::Tuning;
+
+use super::{
+    filter::{lowpass, Decimator, Resampler},
+    Complex, AUDIO_RATE,
+};
+
+/// Rate the channel is filtered and demodulated at, before resampling to `AUDIO_RATE`.
+const CHANNEL_RATE: f64 = 48000.0;
+
+/// Output level the AGC aims for, as a fraction of full scale.
+const AGC_TARGET: f32 = 0.3;
+
+/// Mixes samples with a complex oscillator, shifting them by `-frequency`.
+pub struct Nco {
+    phase: f64,
+    step: f64,
+}
+
+impl Nco {
+    pub fn new(frequency: f64, sample_rate: f64) -> Self {
+        Nco {
+            phase: 0.0,
+            step: -2.0 * PI * frequency / sample_rate,
+        }
+    }
+
+    pub fn mix(&mut self, samples: &mut [Complex]) {
+        // Rotate a phasor for speed and renormalize from the exact phase once per block
+        let mut phasor = Complex::from_polar(1.0, self.phase as f32);
+        let rotation = Complex::from_polar(1.0, self.step as f32);
+        for sample in samples.iter_mut() {
+            *sample *= phasor;
+            phasor *= rotation;
+        }
+        self.phase = (self.phase + self.step * samples.len() as f64) % (2.0 * PI);
+    }
+}
+
+/// Evens out the audio level, reacting instantly to peaks and recovering over about half a second.
+struct Agc {
+    envelope: f32,
+    release: f32,
+}
+
+impl Agc {
+    fn new(sample_rate: f64) -> Self {
+        Agc {
+            envelope: 1e-3,
+            release: (-1.0 / (0.5 * sample_rate)).exp() as f32,
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            self.envelope = sample.abs().max(self.envelope * self.release).max(1e-6);
+            *sample *= AGC_TARGET / self.envelope;
+        }
+    }
+}
+
+enum Detector {
+    Am {
+        dc: f32,
+    },
+    Fm {
+        previous: Complex,
+    },
+    /// Single sideband, the passband is shifted down to be centered at zero before filtering.
+    Ssb {
+        down: Nco,
+        up: Nco,
+    },
+}
+
+/// Turns IQ centered near the wanted signal into audio at `AUDIO_RATE`, according to a `Tuning`.
+pub struct Demodulator {
+    mixer: Nco,
+    decimator: Decimator<Complex>,
+    detector: Detector,
+    channel: Decimator<Complex>,
+    agc: Agc,
+    resampler: Resampler,
+}
+
+impl Demodulator {
+    /// `offset` is how far the wanted frequency sits from the center of the IQ, in Hz.
+    pub fn new(input_rate: f64, offset: f64, tuning: &Tuning) -> Self {
+        let factor = ((input_rate / CHANNEL_RATE).floor() as usize).max(1);
+        let channel_rate = input_rate / factor as f64;
+        let taps = (8 * factor).max(31);
+        let decimator = Decimator::new(
+            lowpass((0.4 * channel_rate / input_rate) as f32, taps),
+            factor,
+        );
+
+        let (detector, half_width) = match *tuning {
+            Tuning::AM { bandwidth, .. } => (Detector::Am { dc: 0.0 }, bandwidth as f64 / 2.0),
+            Tuning::FM {
+                low_cut, high_cut, ..
+            } => (
+                Detector::Fm {
+                    previous: Complex::new(0.0, 0.0),
+                },
+                low_cut.abs().max(high_cut.abs()) as f64,
+            ),
+            Tuning::LSB {
+                low_cut, high_cut, ..
+            }
+            | Tuning::USB {
+                low_cut, high_cut, ..
+            } => {
+                let center = (low_cut + high_cut) as f64 / 2.0;
+                (
+                    Detector::Ssb {
+                        down: Nco::new(center, channel_rate),
+                        up: Nco::new(-center, channel_rate),
+                    },
+                    (high_cut - low_cut).abs() as f64 / 2.0,
+                )
+            }
+        };
+        let half_width = half_width.clamp(100.0, 0.45 * channel_rate);
+
+        Demodulator {
+            mixer: Nco::new(offset, input_rate),
+            decimator,
+            detector,
+            channel: Decimator::new(lowpass((half_width / channel_rate) as f32, 127), 1),
+            agc: Agc::new(channel_rate),
+            resampler: Resampler::new(channel_rate, AUDIO_RATE as f64, 0.45 * AUDIO_RATE as f64),
+        }
+    }
+
+    /// Demodulates a block of IQ, returning the audio and the channel power in dBFS.
+    pub fn process(&mut self, iq: &[Complex]) -> (Vec<i16>, f64) {
+        let mut iq = iq.to_vec();
+        self.mixer.mix(&mut iq);
+        let mut baseband = self.decimator.process(&iq);
+
+        if let Detector::Ssb { down, .. } = &mut self.detector {
+            down.mix(&mut baseband);
+        }
+        let mut channel = self.channel.process(&baseband);
+
+        let power = channel.iter().map(|s| s.norm_sqr()).sum::<f32>() / channel.len().max(1) as f32;
+        let power = 10.0 * (power.max(1e-20) as f64).log10();
+
+        let mut audio: Vec<f32> = match &mut self.detector {
+            Detector::Am { dc } => channel
+                .iter()
+                .map(|sample| {
+                    let envelope = sample.norm();
+                    *dc += 0.001 * (envelope - *dc);
+                    envelope - *dc
+                })
+                .collect(),
+            Detector::Fm { previous } => channel
+                .iter()
+                .map(|&sample| {
+                    let phase = (sample * previous.conj()).arg();
+                    *previous = sample;
+                    phase
+                })
+                .collect(),
+            Detector::Ssb { up, .. } => {
+                up.mix(&mut channel);
+                channel.iter().map(|sample| sample.re).collect()
+            }
+        };
+
+        self.agc.process(&mut audio);
+        let audio = self
+            .resampler
+            .process(&audio)
+            .into_iter()
+            .map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect();
+        (audio, power)
+    }
+}
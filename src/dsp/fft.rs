@@ -0,0 +1,43 @@
+use std::f32::consts::PI;
+
+use super::Complex;
+
+/// In-place radix-2 FFT. The length of `buffer` must be a power of two.
+pub fn fft(buffer: &mut [Complex]) {
+    let n = buffer.len();
+    assert!(
+        n.is_power_of_two(),
+        "FFT length {} is not a power of two",
+        n
+    );
+
+    // Bit-reversed order, so the butterflies can work in place
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            buffer.swap(i, j);
+        }
+    }
+
+    let mut size = 2;
+    while size <= n {
+        let step = Complex::from_polar(1.0, -2.0 * PI / size as f32);
+        for start in (0..n).step_by(size) {
+            let mut twiddle = Complex::new(1.0, 0.0);
+            for k in 0..size / 2 {
+                let even = buffer[start + k];
+                let odd = buffer[start + k + size / 2] * twiddle;
+                buffer[start + k] = even + odd;
+                buffer[start + k + size / 2] = even - odd;
+                twiddle *= step;
+            }
+        }
+        size *= 2;
+    }
+}
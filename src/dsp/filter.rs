@@ -0,0 +1,117 @@
+use std::f32::consts::PI;
+use std::ops::{Add, Mul};
+
+/// Windowed-sinc low-pass taps with unity gain at DC. `cutoff` is a fraction of the sample rate.
+pub fn lowpass(cutoff: f32, taps: usize) -> Vec<f32> {
+    let taps = taps | 1;
+    let middle = (taps / 2) as f32;
+    let mut coefficients: Vec<f32> = (0..taps)
+        .map(|i| {
+            let x = i as f32 - middle;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * PI * cutoff * x).sin() / (PI * x)
+            };
+            // Blackman window
+            let phase = 2.0 * PI * i as f32 / (taps - 1) as f32;
+            let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+            sinc * window
+        })
+        .collect();
+
+    let gain: f32 = coefficients.iter().sum();
+    coefficients.iter_mut().for_each(|c| *c /= gain);
+    coefficients
+}
+
+/// An FIR filter that keeps every `factor`-th output, only computing the outputs that are kept.
+pub struct Decimator<T> {
+    taps: Vec<f32>,
+    factor: usize,
+    buffer: Vec<T>,
+    /// Index in `buffer` of the newest sample of the next output's window.
+    next: usize,
+}
+
+impl<T> Decimator<T>
+where
+    T: Copy + Default + Add<Output = T> + Mul<f32, Output = T>,
+{
+    pub fn new(taps: Vec<f32>, factor: usize) -> Self {
+        let history = taps.len() - 1;
+        Decimator {
+            taps,
+            factor: factor.max(1),
+            buffer: vec![T::default(); history],
+            next: history,
+        }
+    }
+
+    pub fn process(&mut self, input: &[T]) -> Vec<T> {
+        let history = self.taps.len() - 1;
+        self.buffer.extend_from_slice(input);
+
+        let mut output = Vec::with_capacity(input.len() / self.factor + 1);
+        while self.next < self.buffer.len() {
+            let window = &self.buffer[self.next - history..=self.next];
+            let sample = window
+                .iter()
+                .zip(self.taps.iter())
+                .fold(T::default(), |sum, (&sample, &tap)| sum + sample * tap);
+            output.push(sample);
+            self.next += self.factor;
+        }
+
+        let consumed = (self.next - history).min(self.buffer.len());
+        self.buffer.drain(..consumed);
+        self.next -= consumed;
+        output
+    }
+}
+
+/// Converts real samples between arbitrary rates with a low-pass followed by linear interpolation.
+pub struct Resampler {
+    filter: Decimator<f32>,
+    /// Input samples advanced per output sample.
+    step: f64,
+    /// Position of the next output, relative to `previous`.
+    position: f64,
+    previous: f32,
+}
+
+impl Resampler {
+    pub fn new(input_rate: f64, output_rate: f64, cutoff: f64) -> Self {
+        Resampler {
+            filter: Decimator::new(lowpass((cutoff / input_rate) as f32, 63), 1),
+            step: input_rate / output_rate,
+            position: 0.0,
+            previous: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let filtered = self.filter.process(input);
+        let mut output = Vec::with_capacity((filtered.len() as f64 / self.step) as usize + 1);
+
+        // Sample -1 is `previous`, so positions run from -1 up to the last filtered sample
+        while self.position < filtered.len() as f64 - 1.0 {
+            let index = self.position.floor();
+            let fraction = (self.position - index) as f32;
+            let a = if index < 0.0 {
+                self.previous
+            } else {
+                filtered[index as usize]
+            };
+            let b = filtered[(index + 1.0) as usize];
+            output.push(a + (b - a) * fraction);
+            self.position += self.step;
+        }
+
+        if let Some(&last) = filtered.last() {
+            self.previous = last;
+            self.position -= filtered.len() as f64;
+        }
+        output
+    }
+}
@@ -0,0 +1,35 @@
+mod demod;
+mod fft;
+mod filter;
+
+pub use demod::Demodulator;
+pub use fft::fft;
+
+pub type Complex = num_complex::Complex32;
+
+/// Rate of the audio produced by local demodulation.
+pub const AUDIO_RATE: u32 = 12000;
+
+/// Converts unsigned 8-bit interleaved IQ, as produced by RTL-SDR dongles, to complex samples.
+pub fn iq_from_u8(data: &[u8]) -> Vec<Complex> {
+    data.chunks_exact(2)
+        .map(|iq| {
+            Complex::new(
+                (iq[0] as f32 - 127.5) / 127.5,
+                (iq[1] as f32 - 127.5) / 127.5,
+            )
+        })
+        .collect()
+}
+
+/// Converts signed 16-bit little endian interleaved IQ to complex samples.
+pub fn iq_from_i16(data: &[u8]) -> Vec<Complex> {
+    data.chunks_exact(4)
+        .map(|iq| {
+            Complex::new(
+                i16::from_le_bytes([iq[0], iq[1]]) as f32 / 32768.0,
+                i16::from_le_bytes([iq[2], iq[3]]) as f32 / 32768.0,
+            )
+        })
+        .collect()
+}
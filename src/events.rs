@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// How many events are kept around for `GET /events`.
+const HISTORY_LENGTH: usize = 256;
+
+/// How many events a live subscriber may fall behind before it starts skipping.
+const BACKLOG: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum StationState {
+    Connecting,
+    Connected,
+    Ready,
+    Disconnected,
+    Stopped,
+    /// Started, but waiting for one of the receiver's channels to come free.
+    Waiting,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum EventKind {
+    /// S-meter reading, sent for every sound packet.
+    Rssi {
+        rssi: f64,
+    },
+    StateChanged {
+        state: StationState,
+    },
+    /// Why the station went to `Disconnected`.
+    Disconnected {
+        reason: String,
+    },
+    Reconnected,
+    ReconnectFailed {
+        error: String,
+    },
+    FileOpened {
+        path: String,
+    },
+    FileClosed {
+        path: String,
+    },
+    /// A message from the receiver that the scraper does not act upon.
+    Message {
+        message: String,
+    },
+    /// A signal showed up on the waterfall and is being recorded.
+    SignalDetected {
+        frequency: f64,
+        peak_dbm: f32,
+        noise_dbm: f32,
+    },
+    /// A signal being recorded went away, or was recorded for as long as allowed.
+    SignalLost {
+        frequency: f64,
+    },
+    /// A line printed by a station's external process sink.
+    ProcessOutput {
+        line: String,
+    },
+    /// Something a station's decoder sink made out of the audio.
+    Decoded {
+        decoder: String,
+        text: String,
+    },
+}
+
+impl EventKind {
+    /// Whether the event is too frequent to be worth keeping in the history.
+    fn is_transient(&self) -> bool {
+        matches!(self, EventKind::Rssi { .. })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StationEvent {
+    pub station: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// Fans station events out to any number of subscribers, keeping a short history for late joiners.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<StationEvent>,
+    history: Arc<Mutex<VecDeque<StationEvent>>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(BACKLOG);
+        EventBus {
+            sender,
+            history: Arc::new(Mutex::new(VecDeque::with_capacity(HISTORY_LENGTH))),
+        }
+    }
+
+    pub fn publish(&self, station: &str, kind: EventKind) {
+        let event = StationEvent {
+            station: station.to_string(),
+            timestamp: Utc::now(),
+            kind,
+        };
+
+        if !event.kind.is_transient() {
+            let mut history = self.history.lock().unwrap();
+            if history.len() == HISTORY_LENGTH {
+                history.pop_front();
+            }
+            history.push_back(event.clone());
+        }
+
+        // Nobody listening is fine
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StationEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn history(&self) -> Vec<StationEvent> {
+        self.history.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Publishes a station's state transitions and remembers the latest one, along with when it was entered.
+#[derive(Clone)]
+pub struct StateTracker {
+    station: String,
+    events: EventBus,
+    current: Arc<Mutex<(StationState, DateTime<Utc>)>>,
+}
+
+impl StateTracker {
+    pub fn new(station: &str, events: &EventBus) -> Self {
+        StateTracker {
+            station: station.to_string(),
+            events: events.clone(),
+            current: Arc::new(Mutex::new((StationState::Stopped, Utc::now()))),
+        }
+    }
+
+    pub fn set(&self, state: StationState) {
+        *self.current.lock().unwrap() = (state, Utc::now());
+        self.events
+            .publish(&self.station, EventKind::StateChanged { state });
+    }
+
+    pub fn get(&self) -> (StationState, DateTime<Utc>) {
+        *self.current.lock().unwrap()
+    }
+}
@@ -0,0 +1,560 @@
+//! One-off recordings at a set time, like "6080 kHz AM on kiwi1 from 19:00 to 19:30 UTC",
+//! submitted through `POST /jobs` and kept in a file so they survive restarts.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    audio::WriterPool,
+    events::{EventBus, EventKind, StationEvent},
+    sdr::{
+        kiwi::{KiwiSDRScraper, KiwiSDRScraperSettings, SlotAllocator, SlottedScraper},
+        SDRScraper, Tuning,
+    },
+    server::AppState,
+};
+
+/// How often the queue is checked for jobs to start and stop.
+const TICK: Duration = Duration::from_secs(1);
+
+/// A recording to make, as submitted.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JobRequest {
+    /// Name of a configured `KiwiSDR` station to record from.
+    pub station: String,
+    pub tuning: Tuning,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Against the stations sharing the Kiwi's channels, see `SDRStationConfig::priority`.
+    #[serde(default)]
+    pub priority: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum JobState {
+    Queued,
+    Recording,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Done | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Job {
+    pub id: u64,
+    pub station: String,
+    pub tuning: Tuning,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    #[serde(default)]
+    pub priority: i32,
+    pub state: JobState,
+    /// Recordings made for the job so far.
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// Why the job failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Job {
+    /// Name of the scraper recording the job, and of its files and events.
+    pub fn name(&self) -> String {
+        format!("{}_job{}", self.station, self.id)
+    }
+}
+
+/// What goes into the queue file.
+#[derive(Default, Deserialize, Serialize)]
+struct StoredQueue {
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+/// Every job submitted, finished or not, saved to a file after each change.
+pub struct JobQueue {
+    path: PathBuf,
+    /// Stations jobs may record from.
+    stations: Vec<String>,
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+impl JobQueue {
+    /// Reads the queue from `path`, or starts an empty one if there's no such file.
+    pub fn load(path: &Path, stations: Vec<String>) -> anyhow::Result<JobQueue> {
+        let stored = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("error parsing {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoredQueue::default(),
+            Err(e) => anyhow::bail!("error reading {}: {}", path.display(), e),
+        };
+
+        let mut jobs = stored.jobs;
+        // Recordings cut short by a restart carry on if there's time left
+        for job in &mut jobs {
+            if job.state == JobState::Recording {
+                job.state = JobState::Queued;
+            }
+        }
+        Ok(JobQueue {
+            path: path.to_path_buf(),
+            stations,
+            next_id: stored
+                .next_id
+                .max(jobs.iter().map(|job| job.id + 1).max().unwrap_or(1)),
+            jobs,
+        })
+    }
+
+    /// Writes the queue out, replacing the file in one go so a crash can't leave half of it.
+    fn save(&self) -> anyhow::Result<()> {
+        let stored = StoredQueue {
+            next_id: self.next_id,
+            jobs: self.jobs.clone(),
+        };
+        let temporary = self.path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(&stored)?)?;
+        std::fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+
+    fn save_or_log(&self) {
+        if let Err(e) = self.save() {
+            log::error!("error saving jobs to {}: {}", self.path.display(), e);
+        }
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    fn get_mut(&mut self, id: u64) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    /// Queues a recording, if it's from a known station and not over already.
+    pub fn submit(&mut self, request: JobRequest) -> anyhow::Result<Job> {
+        if !self.stations.contains(&request.station) {
+            anyhow::bail!("no KiwiSDR station called {}", request.station);
+        }
+        if request.end <= request.start {
+            anyhow::bail!("job ends before it starts");
+        }
+        if request.end <= Utc::now() {
+            anyhow::bail!("job ends in the past");
+        }
+
+        let job = Job {
+            id: self.next_id,
+            station: request.station,
+            tuning: request.tuning,
+            start: request.start,
+            end: request.end,
+            priority: request.priority,
+            state: JobState::Queued,
+            files: Vec::new(),
+            error: None,
+        };
+        self.next_id += 1;
+        self.jobs.push(job.clone());
+        self.save()?;
+        Ok(job)
+    }
+
+    /// Cancels a job that hasn't finished, stopping its recording if it has started.
+    pub fn cancel(&mut self, id: u64) -> anyhow::Result<Job> {
+        let job = self
+            .get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("no job {}", id))?;
+        if job.state.is_finished() {
+            anyhow::bail!("job {} is {:?} already", id, job.state);
+        }
+        job.state = JobState::Cancelled;
+        let job = job.clone();
+        self.save()?;
+        Ok(job)
+    }
+
+    /// Marks queued jobs whose time has come as recording and returns them. Those that were
+    /// missed entirely, say while the scraper was down, fail instead.
+    fn due(&mut self, now: DateTime<Utc>) -> Vec<Job> {
+        let mut due = Vec::new();
+        let mut changed = false;
+        for job in &mut self.jobs {
+            if job.state != JobState::Queued || job.start > now {
+                continue;
+            }
+            changed = true;
+            if job.end <= now {
+                if job.files.is_empty() {
+                    job.state = JobState::Failed;
+                    job.error = Some("missed, the scraper wasn't running".to_string());
+                } else {
+                    job.state = JobState::Done;
+                }
+                continue;
+            }
+            job.state = JobState::Recording;
+            due.push(job.clone());
+        }
+        if changed {
+            self.save_or_log();
+        }
+        due
+    }
+
+    fn finish(&mut self, id: u64, state: JobState, error: Option<String>) {
+        if let Some(job) = self.get_mut(id) {
+            job.state = state;
+            job.error = error;
+            self.save_or_log();
+        }
+    }
+
+    fn add_file(&mut self, id: u64, path: String) {
+        if let Some(job) = self.get_mut(id) {
+            job.files.push(path);
+            self.save_or_log();
+        }
+    }
+}
+
+/// Records the jobs in `state.jobs` as they come due, each on a `KiwiSDRScraper` of its own made
+/// from the station's entry in `stations` and sharing the Kiwi through `allocator`. On
+/// cancellation, recordings in progress are stopped but left as they are in the queue, to carry
+/// on after a restart.
+pub async fn run(
+    state: Arc<Mutex<AppState>>,
+    stations: HashMap<String, KiwiSDRScraperSettings>,
+    allocator: Arc<SlotAllocator>,
+    pool: Arc<WriterPool>,
+    events: EventBus,
+    token: CancellationToken,
+) {
+    let mut recording: HashMap<u64, (String, SlottedScraper)> = HashMap::new();
+    let mut receiver = events.subscribe();
+    let mut tick = tokio::time::interval(TICK);
+    loop {
+        tokio::select! {
+            _ = tick.tick() => {}
+            event = receiver.recv() => {
+                match event {
+                    Ok(StationEvent {
+                        station,
+                        kind: EventKind::FileOpened { path },
+                        ..
+                    }) => {
+                        let id = recording
+                            .iter()
+                            .find(|(_, (name, _))| *name == station)
+                            .map(|(id, _)| *id);
+                        if let Some(id) = id {
+                            state.lock().await.jobs.add_file(id, path);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::debug!("job runner skipped {} events", skipped);
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            _ = token.cancelled() => break,
+        }
+
+        let now = Utc::now();
+        // Decided under the lock, then started and stopped outside it
+        let (due, over) = {
+            let mut state = state.lock().await;
+            let over = recording
+                .keys()
+                .filter(|id| {
+                    state
+                        .jobs
+                        .get(**id)
+                        .is_none_or(|job| job.state != JobState::Recording || job.end <= now)
+                })
+                .copied()
+                .collect::<Vec<u64>>();
+            (state.jobs.due(now), over)
+        };
+
+        for id in over {
+            let Some((name, mut scraper)) = recording.remove(&id) else {
+                continue;
+            };
+            if let Err(e) = scraper.stop().await {
+                log::error!("{}: error stopping: {}", name.red(), e);
+            }
+            allocator.remove(&name).await;
+            let mut state = state.lock().await;
+            if state
+                .jobs
+                .get(id)
+                .is_some_and(|job| job.state == JobState::Recording)
+            {
+                if scraper.granted() {
+                    state.jobs.finish(id, JobState::Done, None);
+                } else {
+                    state
+                        .jobs
+                        .finish(id, JobState::Failed, Some("no free channel".to_string()));
+                }
+            }
+            if let Some(job) = state.jobs.get(id) {
+                log::info!(
+                    "job {} {:?}, recorded {}",
+                    id.to_string().green(),
+                    job.state,
+                    job.files.join(", ")
+                );
+            }
+        }
+
+        for job in due {
+            let Some(settings) = stations.get(&job.station) else {
+                let error = format!("no KiwiSDR station called {}", job.station);
+                state
+                    .lock()
+                    .await
+                    .jobs
+                    .finish(job.id, JobState::Failed, Some(error));
+                continue;
+            };
+            let name = job.name();
+            log::info!(
+                "job {}: recording {} until {}",
+                job.id.to_string().green(),
+                job.tuning,
+                job.end
+            );
+            let scraper = KiwiSDRScraper::new(
+                KiwiSDRScraperSettings {
+                    name: name.clone(),
+                    station: job.tuning.clone(),
+                    ..settings.clone()
+                },
+                &pool,
+                &events,
+            );
+            let mut scraper = allocator
+                .add(&settings.endpoint, job.priority, 1, Box::new(scraper))
+                .await;
+            match scraper.start().await {
+                Ok(()) => {
+                    recording.insert(job.id, (name, scraper));
+                }
+                Err(e) => {
+                    log::error!("job {}: error starting: {}", job.id, e.to_string().red());
+                    allocator.remove(&name).await;
+                    state
+                        .lock()
+                        .await
+                        .jobs
+                        .finish(job.id, JobState::Failed, Some(e.to_string()));
+                }
+            }
+        }
+    }
+
+    for (_, (name, mut scraper)) in recording {
+        log::info!("stopping {}", name.green());
+        if let Err(e) = scraper.stop().await {
+            log::error!("{}: error stopping: {}", name.red(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdr::Mode;
+
+    struct TempQueue(PathBuf);
+
+    impl TempQueue {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "sdr-scraper-{}-{}.json",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+            TempQueue(path)
+        }
+
+        fn load(&self) -> JobQueue {
+            JobQueue::load(&self.0, vec!["kiwi1".to_string()]).unwrap()
+        }
+    }
+
+    impl Drop for TempQueue {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_dir_all(self.0.with_extension("tmp"));
+        }
+    }
+
+    /// A recording on kiwi1 from `start` to `end` minutes from now.
+    fn request(start: i64, end: i64) -> JobRequest {
+        JobRequest {
+            station: "kiwi1".to_string(),
+            tuning: Mode::AM.tuning(6_080_000.0, None, None),
+            start: Utc::now() + chrono::Duration::minutes(start),
+            end: Utc::now() + chrono::Duration::minutes(end),
+            priority: 0,
+        }
+    }
+
+    fn states(queue: &JobQueue) -> Vec<(u64, JobState)> {
+        queue.jobs().iter().map(|job| (job.id, job.state)).collect()
+    }
+
+    #[test]
+    fn submits_and_reloads() {
+        let file = TempQueue::new("jobs-reload");
+        let mut queue = file.load();
+        assert!(queue.jobs().is_empty());
+
+        assert_eq!(queue.submit(request(10, 20)).unwrap().id, 1);
+        assert_eq!(queue.submit(request(30, 40)).unwrap().id, 2);
+        assert!(queue
+            .submit(JobRequest {
+                station: "kiwi2".to_string(),
+                ..request(10, 20)
+            })
+            .is_err());
+        assert!(queue.submit(request(20, 10)).is_err());
+        assert!(queue.submit(request(-20, -10)).is_err());
+
+        let mut queue = file.load();
+        assert_eq!(
+            states(&queue),
+            [(1, JobState::Queued), (2, JobState::Queued)]
+        );
+        assert_eq!(queue.get(2).unwrap().tuning.frequency(), 6_080_000.0);
+        assert_eq!(queue.submit(request(10, 20)).unwrap().id, 3);
+    }
+
+    #[test]
+    fn resumes_recordings_and_recovers_the_next_id() {
+        let file = TempQueue::new("jobs-resume");
+        let mut queue = file.load();
+        for _ in 0..3 {
+            queue.submit(request(10, 20)).unwrap();
+        }
+        queue.jobs[1].state = JobState::Recording;
+        queue.jobs[2].state = JobState::Done;
+        // As if written before the next id was kept
+        queue.next_id = 0;
+        queue.save().unwrap();
+
+        let mut queue = file.load();
+        assert_eq!(
+            states(&queue),
+            [
+                (1, JobState::Queued),
+                (2, JobState::Queued),
+                (3, JobState::Done)
+            ]
+        );
+        assert_eq!(queue.submit(request(10, 20)).unwrap().id, 4);
+    }
+
+    #[test]
+    fn starts_due_jobs_and_fails_missed_ones() {
+        let file = TempQueue::new("jobs-due");
+        let mut queue = file.load();
+        let now = Utc::now();
+        queue.submit(request(10, 20)).unwrap();
+        queue.submit(request(10, 20)).unwrap();
+        queue.submit(request(10, 20)).unwrap();
+        queue.submit(request(60, 70)).unwrap();
+        queue.add_file(2, "kiwi1_job2.wav".to_string());
+
+        // The first three come due once, the last is still to come
+        let due = queue.due(now + chrono::Duration::minutes(15));
+        assert_eq!(due.iter().map(|job| job.id).collect::<Vec<_>>(), [1, 2, 3]);
+        assert!(queue.due(now + chrono::Duration::minutes(15)).is_empty());
+
+        // Put back as after a restart, then missed entirely
+        for id in [2, 3] {
+            queue.get_mut(id).unwrap().state = JobState::Queued;
+        }
+        assert!(queue.due(now + chrono::Duration::minutes(30)).is_empty());
+        assert_eq!(
+            states(&queue),
+            [
+                (1, JobState::Recording),
+                (2, JobState::Done),
+                (3, JobState::Failed),
+                (4, JobState::Queued)
+            ]
+        );
+        assert!(queue.get(3).unwrap().error.is_some());
+        // Saved as they went, with the recording picked up again on load
+        assert_eq!(
+            states(&file.load()),
+            [
+                (1, JobState::Queued),
+                (2, JobState::Done),
+                (3, JobState::Failed),
+                (4, JobState::Queued)
+            ]
+        );
+    }
+
+    #[test]
+    fn cancels_unfinished_jobs() {
+        let file = TempQueue::new("jobs-cancel");
+        let mut queue = file.load();
+        queue.submit(request(10, 20)).unwrap();
+        queue.submit(request(10, 20)).unwrap();
+        queue.finish(2, JobState::Done, None);
+
+        assert_eq!(queue.cancel(1).unwrap().state, JobState::Cancelled);
+        assert!(queue.cancel(1).is_err());
+        assert!(queue.cancel(2).is_err());
+        assert!(queue.cancel(3).is_err());
+        assert_eq!(
+            states(&file.load()),
+            [(1, JobState::Cancelled), (2, JobState::Done)]
+        );
+    }
+
+    #[test]
+    fn keeps_the_old_file_when_saving_fails() {
+        let file = TempQueue::new("jobs-save");
+        let mut queue = file.load();
+        queue.submit(request(10, 20)).unwrap();
+        assert!(!file.0.with_extension("tmp").exists());
+        let saved = std::fs::read(&file.0).unwrap();
+
+        // Nowhere to write the new queue to
+        std::fs::create_dir(file.0.with_extension("tmp")).unwrap();
+        assert!(queue.submit(request(10, 20)).is_err());
+        assert_eq!(std::fs::read(&file.0).unwrap(), saved);
+    }
+}
@@ -0,0 +1,9 @@
+pub mod audio;
+pub mod config;
+pub mod dsp;
+pub mod events;
+pub mod jobs;
+pub mod schedule;
+pub mod sdr;
+pub mod server;
+pub mod spectrum;
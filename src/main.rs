@@ -1,34 +1,31 @@
-mod audio;
-mod config;
-mod sdr;
-
+use std::collections::HashMap;
 use std::future::IntoFuture;
 use std::sync::Arc;
 
-use axum::extract::State;
-use axum::{Json, Router};
 use colored::Colorize;
 
-use reqwest::StatusCode;
-use sdr::kiwi::{KiwiSDRScraper, KiwiSDRScraperSettings, KiwiScraperStats};
-use sdr::Tuning;
+use sdr_scraper::audio::WriterPool;
+use sdr_scraper::sdr::file::{FileScraper, FileScraperSettings};
+use sdr_scraper::sdr::http_stream::{HttpStreamScraper, HttpStreamScraperSettings};
+use sdr_scraper::sdr::kiwi::{
+    self, KiwiSDRScraper, KiwiSDRScraperSettings, KiwiSurveyScraper, KiwiSurveyScraperSettings,
+    KiwiTriggerScraper, KiwiTriggerScraperSettings, SlotAllocator,
+};
+use sdr_scraper::sdr::openwebrx::{OpenWebRxScraper, OpenWebRxScraperSettings};
+use sdr_scraper::sdr::rtl_tcp::{RtlTcpScraper, RtlTcpScraperSettings};
+use sdr_scraper::sdr::spyserver::{SpyServerScraper, SpyServerScraperSettings};
+use sdr_scraper::sdr::websdr::{WebSdrScraper, WebSdrScraperSettings};
+use sdr_scraper::sdr::Tuning;
 
 use tokio::sync::Mutex;
 use url::Url;
 
-use crate::config::{Config, SDRKind};
-use crate::sdr::{SDRScraper, ScraperStatus};
-
-struct AppState {
-    stats: Vec<KiwiScraperStats>,
-}
-
-async fn root(
-    State(app_state): State<Arc<Mutex<AppState>>>,
-) -> Result<Json<Vec<KiwiScraperStats>>, StatusCode> {
-    let state = app_state.lock().await;
-    Ok(Json(state.stats.clone()))
-}
+use sdr_scraper::config::{Config, Coordinates, FrequencyConfig, SDRKind};
+use sdr_scraper::events::EventBus;
+use sdr_scraper::jobs::{self, JobQueue};
+use sdr_scraper::schedule::{self, Schedule};
+use sdr_scraper::sdr::{SDRScraper, ScraperStatus};
+use sdr_scraper::server::{self, AppState};
 
 #[tokio::main]
 // Use multi threading
@@ -67,38 +64,287 @@ async fn main() {
         config.stations.len().to_string().green()
     );
 
+    let pool = Arc::new(WriterPool::new(
+        config.writer.threads,
+        config.writer.queue_depth,
+        config.writer.overflow,
+        config.writer.spill_limit,
+    ));
+
+    let events = EventBus::new();
+
+    let allocator = SlotAllocator::new(&events);
     let mut stations: Vec<Box<dyn SDRScraper>> = Vec::new();
-    config.stations.iter().for_each(|station_config| {
-        let endpoint = "ws://".to_owned() + &station_config.endpoint;
-        let endpoint = Url::parse(&endpoint).unwrap();
+    let mut schedules: Vec<Schedule> = Vec::new();
+    // What jobs record with, for each KiwiSDR station
+    let mut job_stations: HashMap<String, KiwiSDRScraperSettings> = HashMap::new();
+    for station_config in &config.stations {
+        // Endpoints are host and path unless they bring their own scheme, e.g. an https stream
+        let endpoint = if station_config.endpoint.contains("://") {
+            station_config.endpoint.clone()
+        } else {
+            "ws://".to_owned() + &station_config.endpoint
+        };
+        let endpoint = match station_config.kind {
+            SDRKind::File => {
+                Url::from_file_path(std::path::absolute(&station_config.endpoint).unwrap()).unwrap()
+            }
+            _ => Url::parse(&endpoint).unwrap(),
+        };
 
         log::debug!(
             "found {} at {}",
-            "KiwiSDR".green(),
+            format!("{:?}", station_config.kind).green(),
             endpoint.to_string().green()
         );
 
-        station_config.frequency.iter().for_each(|frequency| {
+        // Schedules around sunrise and sunset need to know where the receiver is
+        let mut coordinates = station_config.coordinates;
+        let needs_coordinates = station_config
+            .schedule
+            .iter()
+            .map(Vec::as_slice)
+            .chain(
+                station_config
+                    .frequency
+                    .iter()
+                    .filter_map(FrequencyConfig::schedule),
+            )
+            .any(schedule::needs_coordinates);
+        if needs_coordinates && coordinates.is_none() {
+            if let SDRKind::KiwiSDR = station_config.kind {
+                match kiwi::status(&endpoint).await {
+                    Ok(status) => coordinates = status.gps,
+                    Err(e) => log::error!(
+                        "error getting the position of {}: {}",
+                        station_config.name,
+                        e.to_string().red()
+                    ),
+                }
+            }
+            match coordinates {
+                Some(Coordinates {
+                    latitude,
+                    longitude,
+                }) => log::info!(
+                    "{} is at {:.4}, {:.4}",
+                    station_config.name.green(),
+                    latitude,
+                    longitude
+                ),
+                None => log::warn!(
+                    "{} has no coordinates, its windows around sunrise and sunset stay off air",
+                    station_config.name.yellow()
+                ),
+            }
+        }
+
+        if let SDRKind::KiwiSDR = station_config.kind {
+            job_stations.insert(
+                station_config.name.clone(),
+                KiwiSDRScraperSettings {
+                    name: station_config.name.clone(),
+                    endpoint: endpoint.clone(),
+                    password: station_config.password.clone(),
+                    agc: station_config.agc,
+                    location: config.location.clone(),
+                    identity: config.identity.clone(),
+                    sinks: config.jobs.sinks.clone(),
+                    capture: None,
+                    waterfall: None,
+                    station: Tuning::USB {
+                        low_cut: 300,
+                        high_cut: 2700,
+                        frequency: 0.0,
+                    },
+                },
+            );
+        }
+
+        if let (SDRKind::KiwiSDR, Some(survey)) = (&station_config.kind, &station_config.survey) {
+            if let Some(schedule) = &station_config.schedule {
+                schedules.push(Schedule {
+                    name: format!("{}_survey", station_config.name),
+                    windows: schedule.clone(),
+                    coordinates,
+                });
+            }
+            let survey = KiwiSurveyScraper::new(
+                KiwiSurveyScraperSettings {
+                    name: format!("{}_survey", station_config.name),
+                    endpoint: endpoint.clone(),
+                    password: station_config.password.clone(),
+                    survey: survey.clone(),
+                },
+                &pool,
+                &events,
+            );
+            stations.push(Box::new(
+                allocator
+                    .add(&endpoint, station_config.priority, 1, Box::new(survey))
+                    .await,
+            ));
+        }
+
+        if let (SDRKind::KiwiSDR, Some(trigger)) = (&station_config.kind, &station_config.trigger) {
+            if let Some(schedule) = &station_config.schedule {
+                schedules.push(Schedule {
+                    name: format!("{}_trigger", station_config.name),
+                    windows: schedule.clone(),
+                    coordinates,
+                });
+            }
+            let trigger_scraper = KiwiTriggerScraper::new(
+                KiwiTriggerScraperSettings {
+                    name: format!("{}_trigger", station_config.name),
+                    endpoint: endpoint.clone(),
+                    password: station_config.password.clone(),
+                    agc: station_config.agc,
+                    location: config.location.clone(),
+                    identity: config.identity.clone(),
+                    trigger: trigger.clone(),
+                },
+                &pool,
+                &events,
+            );
+            // The waterfall, and every channel it may record on
+            let channels = 1 + trigger.channels;
+            stations.push(Box::new(
+                allocator
+                    .add(
+                        &endpoint,
+                        station_config.priority,
+                        channels,
+                        Box::new(trigger_scraper),
+                    )
+                    .await,
+            ));
+        }
+
+        for frequency_config in &station_config.frequency {
+            let frequency = frequency_config.frequency();
             // name in megahertz
             let name = format!("{}_{:.0}", station_config.name.clone(), frequency / 1_000.0);
             log::debug!("tuning to {}", frequency.to_string().green());
-            stations.push(Box::new(KiwiSDRScraper::new(KiwiSDRScraperSettings {
-                name: name,
-                endpoint: endpoint.clone(),
-                password: station_config.password.clone(),
-                agc: station_config.agc,
-                location: config.location.clone(),
-                identity: config.identity.clone(),
-                station: Tuning::USB {
-                    low_cut: 300,
-                    high_cut: 2700,
-                    frequency: frequency.to_owned(),
-                },
-            })));
-        });
-    });
+            if let Some(schedule) = frequency_config
+                .schedule()
+                .or(station_config.schedule.as_deref())
+            {
+                schedules.push(Schedule {
+                    name: name.clone(),
+                    windows: schedule.to_vec(),
+                    coordinates,
+                });
+            }
+            let station = frequency_config.tuning();
+            stations.push(match station_config.kind {
+                SDRKind::KiwiSDR => {
+                    let scraper = KiwiSDRScraper::new(
+                        KiwiSDRScraperSettings {
+                            name,
+                            endpoint: endpoint.clone(),
+                            password: station_config.password.clone(),
+                            agc: station_config.agc,
+                            location: config.location.clone(),
+                            identity: config.identity.clone(),
+                            sinks: station_config.sinks.clone(),
+                            capture: station_config.capture.clone(),
+                            waterfall: station_config.waterfall.clone(),
+                            station,
+                        },
+                        &pool,
+                        &events,
+                    );
+                    // Plus one for the waterfall
+                    let channels = 1 + station_config.waterfall.is_some() as usize;
+                    Box::new(
+                        allocator
+                            .add(
+                                &endpoint,
+                                station_config.priority,
+                                channels,
+                                Box::new(scraper),
+                            )
+                            .await,
+                    )
+                }
+                SDRKind::OpenWebRX => Box::new(OpenWebRxScraper::new(
+                    OpenWebRxScraperSettings {
+                        name,
+                        endpoint: endpoint.clone(),
+                        key: station_config.password.clone(),
+                        sinks: station_config.sinks.clone(),
+                        station,
+                    },
+                    &pool,
+                    &events,
+                )),
+                SDRKind::RtlTcp => Box::new(RtlTcpScraper::new(
+                    RtlTcpScraperSettings {
+                        name,
+                        endpoint: endpoint.clone(),
+                        gain: station_config.gain,
+                        agc: station_config.agc,
+                        sinks: station_config.sinks.clone(),
+                        station,
+                    },
+                    &pool,
+                    &events,
+                )),
+                SDRKind::SpyServer => Box::new(SpyServerScraper::new(
+                    SpyServerScraperSettings {
+                        name,
+                        endpoint: endpoint.clone(),
+                        gain: station_config.gain,
+                        sinks: station_config.sinks.clone(),
+                        station,
+                    },
+                    &pool,
+                    &events,
+                )),
+                SDRKind::WebSDR => Box::new(WebSdrScraper::new(
+                    WebSdrScraperSettings {
+                        name,
+                        endpoint: endpoint.clone(),
+                        sinks: station_config.sinks.clone(),
+                        station,
+                    },
+                    &pool,
+                    &events,
+                )),
+                SDRKind::HttpStream => Box::new(HttpStreamScraper::new(
+                    HttpStreamScraperSettings {
+                        name,
+                        endpoint: endpoint.clone(),
+                        station,
+                        sinks: station_config.sinks.clone(),
+                    },
+                    &pool,
+                    &events,
+                )),
+                SDRKind::File => Box::new(FileScraper::new(
+                    FileScraperSettings {
+                        name,
+                        path: endpoint.to_file_path().unwrap(),
+                        speed: station_config.speed,
+                        station,
+                        sinks: station_config.sinks.clone(),
+                    },
+                    &pool,
+                    &events,
+                )),
+            });
+        }
+    }
 
+    // Scheduled stations are left to the scheduler, which starts them once they're on air
     for station in &mut stations {
+        if schedules
+            .iter()
+            .any(|schedule| schedule.name == station.name())
+        {
+            continue;
+        }
         log::info!("starting {}", station.name().green());
         match station.start().await {
             Ok(_) => {}
@@ -106,12 +352,33 @@ async fn main() {
         }
     }
 
-    let state = Arc::new(Mutex::new(AppState { stats: Vec::new() }));
+    let jobs = match JobQueue::load(&config.jobs.path, job_stations.keys().cloned().collect()) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            log::error!("error loading jobs: {}", e.to_string().red());
+            std::process::exit(1);
+        }
+    };
+
+    let state = Arc::new(Mutex::new(AppState {
+        stations,
+        events: events.clone(),
+        jobs,
+    }));
+
+    let slots = tokio::spawn(allocator.clone().run());
+    let scheduler = tokio::spawn(schedule::run(schedules, state.clone()));
+    let jobs_token = tokio_util::sync::CancellationToken::new();
+    let job_runner = tokio::spawn(jobs::run(
+        state.clone(),
+        job_stations,
+        allocator,
+        pool.clone(),
+        events.clone(),
+        jobs_token.clone(),
+    ));
 
-    let router = Router::new()
-        // `GET /` goes to `root`
-        .route("/", axum::routing::get(root))
-        .with_state(state.clone());
+    let router = server::router(state.clone());
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
 
@@ -128,33 +395,18 @@ async fn main() {
         }
     });
 
-    loop {
-        let future1 = async {
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-            let mut state = state.lock().await;
-            let mut stats = Vec::new();
-            for station in &stations {
-                stats.push(station.get_stats());
-            }
-
-            state.stats = stats;
-        };
-
-        let future2 = tokio::signal::ctrl_c();
-
-        tokio::select! {
-            _ = future1 => {
-            }
-            _ = future2 => {
-                log::info!("ctrl-c received");
-                break;
-            }
-        }
+    tokio::signal::ctrl_c().await.unwrap();
+    log::info!("ctrl-c received");
+    scheduler.abort();
+    slots.abort();
+    jobs_token.cancel();
+    if job_runner.await.is_err() {
+        log::error!("job runner panicked");
     }
 
     println!();
 
-    for station in &mut stations {
+    for station in &mut state.lock().await.stations {
         log::info!("stopping {}", station.name().green());
         if station.status() == ScraperStatus::Stopped {
             continue;
@@ -165,5 +417,13 @@ async fn main() {
         }
     }
 
+    log::info!("flushing recordings...");
+    // The job runner, the last other owner, is done by now
+    if let Some(pool) = Arc::into_inner(pool) {
+        tokio::task::spawn_blocking(move || pool.shutdown())
+            .await
+            .unwrap();
+    }
+
     log::info!("{}", "goodbye!")
 }
@@ -0,0 +1,224 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Which values of one field match, as a bit per value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Field(u64);
+
+impl Field {
+    /// Parses `*`, `5`, `1-5`, `*/15`, `10-20/5` and lists of those, for values in `min..=max`.
+    fn parse(text: &str, min: u32, max: u32) -> anyhow::Result<Field> {
+        let mut bits = 0u64;
+        for part in text.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>()?),
+                None => (part, 1),
+            };
+            if step == 0 {
+                anyhow::bail!("step of zero in {}", text);
+            }
+            let (low, high) = if range == "*" {
+                (min, max)
+            } else if let Some((low, high)) = range.split_once('-') {
+                (low.parse()?, high.parse()?)
+            } else {
+                let value = range.parse()?;
+                // `5/10` means from 5 to the end, every 10
+                (value, if step > 1 { max } else { value })
+            };
+            if low < min || high > max || low > high {
+                anyhow::bail!("{} is outside {}-{}", part, min, max);
+            }
+            for value in (low..=high).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(Field(bits))
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.0 & (1 << value) != 0
+    }
+}
+
+/// A cron expression of five fields, minute, hour, day of month, month and day of week (0 or 7
+/// being Sunday), matched against UTC.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cron {
+    text: String,
+    minute: Field,
+    hour: Field,
+    day: Field,
+    month: Field,
+    weekday: Field,
+    /// Whether day of month and day of week were both restricted, in which case either
+    /// matching is enough, as in cron.
+    either_day: bool,
+}
+
+impl Cron {
+    /// Whether the minute `time` falls in matches.
+    pub fn matches(&self, time: DateTime<Utc>) -> bool {
+        let day = self.day.matches(time.day());
+        let weekday = self.weekday.matches(time.weekday().num_days_from_sunday());
+        let days = if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        days && self.minute.matches(time.minute())
+            && self.hour.matches(time.hour())
+            && self.month.matches(time.month())
+    }
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Cron> {
+        let fields = text.split_whitespace().collect::<Vec<&str>>();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            anyhow::bail!("expected 5 fields in cron expression {:?}", text);
+        };
+
+        let mut weekdays = Field::parse(weekday, 0, 7)?;
+        if weekdays.matches(7) {
+            weekdays.0 |= 1;
+        }
+        Ok(Cron {
+            text: text.to_string(),
+            minute: Field::parse(minute, 0, 59)?,
+            hour: Field::parse(hour, 0, 23)?,
+            day: Field::parse(day, 1, 31)?,
+            month: Field::parse(month, 1, 12)?,
+            weekday: weekdays,
+            either_day: day != "*" && weekday != "*",
+        })
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> anyhow::Result<Cron> {
+        text.parse()
+    }
+}
+
+impl From<Cron> for String {
+    fn from(cron: Cron) -> String {
+        cron.text
+    }
+}
+
+impl Display for Cron {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2026-10-18 is a Sunday
+        Utc.with_ymd_and_hms(2026, 10, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn cron(text: &str) -> Cron {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn steps() {
+        let every_quarter = cron("*/15 * * * *");
+        for minute in [0, 15, 30, 45] {
+            assert!(every_quarter.matches(at(18, 7, minute)));
+        }
+        for minute in [1, 14, 59] {
+            assert!(!every_quarter.matches(at(18, 7, minute)));
+        }
+
+        // From 5 to the end of the hour, every 10
+        let from_five = cron("5/10 * * * *");
+        assert!(from_five.matches(at(18, 7, 5)));
+        assert!(from_five.matches(at(18, 7, 55)));
+        assert!(!from_five.matches(at(18, 7, 0)));
+    }
+
+    #[test]
+    fn ranges_and_lists() {
+        let weekdays = cron("0 9 * * 1-5");
+        assert!(weekdays.matches(at(19, 9, 0)));
+        assert!(weekdays.matches(at(23, 9, 0)));
+        assert!(!weekdays.matches(at(18, 9, 0)));
+        assert!(!weekdays.matches(at(24, 9, 0)));
+        assert!(!weekdays.matches(at(19, 9, 1)));
+
+        let hours = cron("30 6,18-20 * * *");
+        assert!(hours.matches(at(18, 6, 30)));
+        assert!(hours.matches(at(18, 19, 30)));
+        assert!(!hours.matches(at(18, 7, 30)));
+    }
+
+    #[test]
+    fn sunday_is_zero_or_seven() {
+        for text in ["0 19 * * 7", "0 19 * * 0"] {
+            let sunday = cron(text);
+            assert!(sunday.matches(at(18, 19, 0)), "{}", text);
+            assert!(sunday.matches(at(25, 19, 0)), "{}", text);
+            assert!(!sunday.matches(at(19, 19, 0)), "{}", text);
+        }
+        assert!(cron("0 19 * * 5-7").matches(at(18, 19, 0)));
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // Both restricted: either will do
+        let either = cron("0 12 1 * 1");
+        assert!(either.matches(at(1, 12, 0)));
+        assert!(either.matches(at(19, 12, 0)));
+        assert!(!either.matches(at(20, 12, 0)));
+
+        // Only one restricted: that one has to match
+        let first = cron("0 12 1 * *");
+        assert!(first.matches(at(1, 12, 0)));
+        assert!(!first.matches(at(19, 12, 0)));
+        let mondays = cron("0 12 * * 1");
+        assert!(!mondays.matches(at(1, 12, 0)));
+        assert!(mondays.matches(at(19, 12, 0)));
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        for text in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(text.parse::<Cron>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn serializes_as_written() {
+        let cron: Cron = serde_json::from_str("\"0 */2 * * 1-5\"").unwrap();
+        assert_eq!(serde_json::to_string(&cron).unwrap(), "\"0 */2 * * 1-5\"");
+        assert!(serde_json::from_str::<Cron>("\"0 25 * * *\"").is_err());
+    }
+}
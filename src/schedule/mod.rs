@@ -0,0 +1,355 @@
+//! Starting and stopping stations as their schedules say they're on air.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, DurationRound, NaiveDate, NaiveTime, Utc};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    config::{Coordinates, WindowConfig},
+    sdr::ScraperStatus,
+    server::AppState,
+};
+
+mod cron;
+pub mod sun;
+
+pub use cron::Cron;
+
+/// How often schedules are checked.
+const TICK: Duration = Duration::from_secs(10);
+
+/// A time of day, either in UTC and written `HH:MM`, or relative to the sun at some place and
+/// written like `sunset`, `sunrise+30m` or `sunset-1h 30m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TimeOfDay {
+    Clock(NaiveTime),
+    Sunrise(chrono::Duration),
+    Sunset(chrono::Duration),
+}
+
+impl TimeOfDay {
+    /// When it is on `date`, or `None` if it's relative to the sun and there are no
+    /// coordinates or the sun doesn't rise or set there that day.
+    fn on(&self, date: NaiveDate, coordinates: Option<&Coordinates>) -> Option<DateTime<Utc>> {
+        match self {
+            TimeOfDay::Clock(time) => Some(date.and_time(*time).and_utc()),
+            TimeOfDay::Sunrise(offset) => Some(sun::sunrise(date, coordinates?)? + *offset),
+            TimeOfDay::Sunset(offset) => Some(sun::sunset(date, coordinates?)? + *offset),
+        }
+    }
+
+    fn solar(&self) -> bool {
+        !matches!(self, TimeOfDay::Clock(_))
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<TimeOfDay> {
+        let solar = [
+            (
+                "sunrise",
+                TimeOfDay::Sunrise as fn(chrono::Duration) -> TimeOfDay,
+            ),
+            ("sunset", TimeOfDay::Sunset),
+        ];
+        for (name, time) in solar {
+            let Some(offset) = text.trim().strip_prefix(name) else {
+                continue;
+            };
+            let offset = offset.trim();
+            if offset.is_empty() {
+                return Ok(time(chrono::Duration::zero()));
+            }
+            let (sign, offset) = if let Some(offset) = offset.strip_prefix('+') {
+                (1, offset)
+            } else if let Some(offset) = offset.strip_prefix(['-', '−']) {
+                (-1, offset)
+            } else {
+                anyhow::bail!("expected + or - after {} in {:?}", name, text);
+            };
+            let offset = humantime::parse_duration(offset.trim())
+                .map_err(|e| anyhow::anyhow!("invalid offset in {:?}: {}", text, e))?;
+            return Ok(time(chrono::Duration::from_std(offset)? * sign));
+        }
+
+        NaiveTime::parse_from_str(text, "%H:%M")
+            .map(TimeOfDay::Clock)
+            .map_err(|e| anyhow::anyhow!("invalid time of day {:?}: {}", text, e))
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> anyhow::Result<TimeOfDay> {
+        text.parse()
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> String {
+        time.to_string()
+    }
+}
+
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (name, offset) = match self {
+            TimeOfDay::Clock(time) => return write!(f, "{}", time.format("%H:%M")),
+            TimeOfDay::Sunrise(offset) => ("sunrise", offset),
+            TimeOfDay::Sunset(offset) => ("sunset", offset),
+        };
+        write!(f, "{}", name)?;
+        if let Ok(magnitude) = offset.abs().to_std() {
+            if !magnitude.is_zero() {
+                let sign = if *offset < chrono::Duration::zero() {
+                    '-'
+                } else {
+                    '+'
+                };
+                write!(f, "{}{}", sign, humantime::format_duration(magnitude))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Start and end of a daily window on `date`, if it happens that day.
+fn daily_span(
+    start: &TimeOfDay,
+    end: &TimeOfDay,
+    date: NaiveDate,
+    coordinates: Option<&Coordinates>,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let from = start.on(date, coordinates)?;
+    let mut to = end.on(date, coordinates)?;
+    if to <= from {
+        to = end.on(date.succ_opt()?, coordinates)?;
+    }
+    Some((from, to))
+}
+
+fn window_contains(
+    window: &WindowConfig,
+    time: DateTime<Utc>,
+    receiver: Option<&Coordinates>,
+) -> bool {
+    match window {
+        WindowConfig::Daily {
+            start,
+            end,
+            days,
+            coordinates,
+        } => {
+            // A window can start the UTC day before, and sunrise and sunset far from Greenwich
+            // fall on the UTC day after
+            let coordinates = coordinates.as_ref().or(receiver);
+            let today = time.date_naive();
+            [today.pred_opt(), Some(today), today.succ_opt()]
+                .into_iter()
+                .flatten()
+                .filter(|date| days.is_empty() || days.contains(&date.weekday()))
+                .filter_map(|date| daily_span(start, end, date, coordinates))
+                .any(|(from, to)| from <= time && time < to)
+        }
+        WindowConfig::Cron { cron, duration } => {
+            let Ok(duration) = chrono::Duration::from_std(*duration) else {
+                return false;
+            };
+            let Ok(minute) = time.duration_trunc(chrono::Duration::minutes(1)) else {
+                return false;
+            };
+            (0..=duration.num_minutes())
+                .map(|back| minute - chrono::Duration::minutes(back))
+                .any(|started| started + duration > time && cron.matches(started))
+        }
+    }
+}
+
+/// Whether any of `windows` go by the sun at the receiver, so need to know where it is.
+pub fn needs_coordinates(windows: &[WindowConfig]) -> bool {
+    windows.iter().any(|window| match window {
+        WindowConfig::Daily {
+            start,
+            end,
+            coordinates: None,
+            ..
+        } => start.solar() || end.solar(),
+        _ => false,
+    })
+}
+
+/// A station the scheduler starts and stops.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub name: String,
+    pub windows: Vec<WindowConfig>,
+    /// Where the receiver is, for windows around sunrise and sunset.
+    pub coordinates: Option<Coordinates>,
+}
+
+impl Schedule {
+    /// Whether any of the windows has the station on air at `time`.
+    pub fn on_air(&self, time: DateTime<Utc>) -> bool {
+        self.windows
+            .iter()
+            .any(|window| window_contains(window, time, self.coordinates.as_ref()))
+    }
+}
+
+/// Starts and stops the named stations whenever their schedule goes on or off air. Stations
+/// are only touched when that happens, so starting or stopping one by hand holds until the
+/// next change.
+pub async fn run(schedules: Vec<Schedule>, state: Arc<Mutex<AppState>>) {
+    let mut last: HashMap<String, bool> = HashMap::new();
+    let mut tick = tokio::time::interval(TICK);
+    loop {
+        tick.tick().await;
+        let now = Utc::now();
+        let mut state = state.lock().await;
+        for schedule in &schedules {
+            let name = &schedule.name;
+            let wanted = schedule.on_air(now);
+            if last.insert(name.clone(), wanted) == Some(wanted) {
+                continue;
+            }
+            let Some(station) = state
+                .stations
+                .iter_mut()
+                .find(|station| station.name() == name)
+            else {
+                continue;
+            };
+
+            let result = match (wanted, station.status()) {
+                (true, ScraperStatus::Stopped) => {
+                    log::info!("{}: on air, starting", name.green());
+                    station.start().await
+                }
+                (false, ScraperStatus::Running) => {
+                    log::info!("{}: off air, stopping", name.yellow());
+                    station.stop().await
+                }
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                log::error!("{}: scheduled start or stop failed: {}", name.red(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2026-10-18 is a Sunday
+        Utc.with_ymd_and_hms(2026, 10, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn schedule(windows: &str) -> Schedule {
+        Schedule {
+            name: "test".to_string(),
+            windows: serde_json::from_str(windows).unwrap(),
+            coordinates: None,
+        }
+    }
+
+    #[test]
+    fn daily_windows() {
+        let evenings = schedule(r#"[{"start": "19:00", "end": "21:30"}]"#);
+        assert!(!evenings.on_air(at(18, 18, 59)));
+        assert!(evenings.on_air(at(18, 19, 0)));
+        assert!(evenings.on_air(at(18, 21, 29)));
+        assert!(!evenings.on_air(at(18, 21, 30)));
+    }
+
+    #[test]
+    fn overnight_windows_count_as_the_day_they_start() {
+        let friday_nights = schedule(r#"[{"start": "22:00", "end": "02:00", "days": ["Fri"]}]"#);
+        assert!(friday_nights.on_air(at(23, 23, 0)));
+        assert!(friday_nights.on_air(at(24, 1, 59)));
+        assert!(!friday_nights.on_air(at(24, 2, 0)));
+        assert!(!friday_nights.on_air(at(23, 1, 0)));
+        assert!(!friday_nights.on_air(at(24, 23, 0)));
+    }
+
+    #[test]
+    fn cron_windows_last_their_duration() {
+        let nightly = schedule(r#"[{"cron": "55 23 * * *", "duration": "10m"}]"#);
+        assert!(!nightly.on_air(at(18, 23, 54)));
+        assert!(nightly.on_air(at(18, 23, 55)));
+        assert!(nightly.on_air(at(19, 0, 4) + chrono::Duration::seconds(59)));
+        assert!(!nightly.on_air(at(19, 0, 5)));
+
+        let quarters = schedule(r#"[{"cron": "*/15 * * * 1-5", "duration": "5m"}]"#);
+        assert!(quarters.on_air(at(19, 10, 47)));
+        assert!(!quarters.on_air(at(19, 10, 50)));
+        assert!(!quarters.on_air(at(18, 10, 47)));
+    }
+
+    #[test]
+    fn times_of_day() {
+        let minutes = chrono::Duration::minutes;
+        let parse = |text: &str| text.parse::<TimeOfDay>().unwrap();
+        assert_eq!(
+            parse("21:30"),
+            TimeOfDay::Clock(NaiveTime::from_hms_opt(21, 30, 0).unwrap())
+        );
+        assert_eq!(parse("sunrise"), TimeOfDay::Sunrise(minutes(0)));
+        assert_eq!(parse("sunset-30m"), TimeOfDay::Sunset(minutes(-30)));
+        assert_eq!(parse("sunset − 30m"), TimeOfDay::Sunset(minutes(-30)));
+        assert_eq!(parse("sunrise+1h 30m"), TimeOfDay::Sunrise(minutes(90)));
+        for text in ["sunset*2", "sunrise+soon", "25:00", "noon"] {
+            assert!(text.parse::<TimeOfDay>().is_err(), "{}", text);
+        }
+        for text in ["21:30", "sunrise", "sunset-30m", "sunrise+1h 30m"] {
+            assert_eq!(parse(text).to_string(), text);
+        }
+    }
+
+    #[test]
+    fn windows_around_the_sun() {
+        let mut nights = schedule(r#"[{"start": "sunset-30m", "end": "sunrise+1h"}]"#);
+        // Nowhere to take the sun at
+        assert!(!nights.on_air(at(18, 23, 0)));
+        assert!(needs_coordinates(&nights.windows));
+
+        // London's sunset is at about 17:02 UTC that day, and sunrise about 06:30 the next
+        nights.coordinates = Some(Coordinates {
+            latitude: 51.5074,
+            longitude: -0.1278,
+        });
+        assert!(!nights.on_air(at(18, 16, 20)));
+        assert!(nights.on_air(at(18, 16, 40)));
+        assert!(nights.on_air(at(19, 7, 20)));
+        assert!(!nights.on_air(at(19, 7, 45)));
+        assert!(!nights.on_air(at(19, 12, 0)));
+
+        // At the far end of a path, whatever the receiver's coordinates
+        let sydney_days = schedule(
+            r#"[{
+                "start": "sunrise",
+                "end": "sunset",
+                "coordinates": {"latitude": -33.8688, "longitude": 151.2093}
+            }]"#,
+        );
+        assert!(!needs_coordinates(&sydney_days.windows));
+        assert!(sydney_days.on_air(at(18, 2, 0)));
+        assert!(!sydney_days.on_air(at(18, 12, 0)));
+    }
+}
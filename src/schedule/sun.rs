@@ -0,0 +1,124 @@
+//! Sunrise and sunset times, from the sunrise equation.
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+
+use crate::config::Coordinates;
+
+/// Julian dates of 2000-01-01 12:00 UTC and of the Unix epoch.
+const J2000: f64 = 2_451_545.0;
+const UNIX_EPOCH: f64 = 2_440_587.5;
+/// Altitude of the sun's center when its upper edge meets the horizon, refraction included.
+const HORIZON: f64 = -0.833;
+/// Tilt of the Earth's axis.
+const OBLIQUITY: f64 = 23.4397;
+
+/// Solar noon and the hour angle of sunrise and sunset either side of it, in days, or `None`
+/// if the sun stays up or down all day.
+fn solar_day(date: NaiveDate, coordinates: &Coordinates) -> Option<(f64, f64)> {
+    let noon = date.and_time(NaiveTime::MIN).and_utc().timestamp() as f64 / 86400.0 + 0.5;
+    let day = (noon + UNIX_EPOCH - J2000).round() - coordinates.longitude / 360.0;
+
+    let anomaly = (357.5291 + 0.98560028 * day).rem_euclid(360.0).to_radians();
+    let center =
+        1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let longitude = (anomaly.to_degrees() + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit = J2000 + day + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * longitude).sin();
+
+    let declination = (longitude.sin() * OBLIQUITY.to_radians().sin()).asin();
+    let latitude = coordinates.latitude.to_radians();
+    let cos_hour_angle = (HORIZON.to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    Some((transit, cos_hour_angle.acos().to_degrees() / 360.0))
+}
+
+fn from_julian(julian: f64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(((julian - UNIX_EPOCH) * 86_400_000.0).round() as i64)
+}
+
+/// When the sun rises at `coordinates` on `date`, taken as the local day whose solar noon is
+/// nearest to noon UTC shifted by the longitude. Far from Greenwich, that can be on the UTC day
+/// before or after.
+pub fn sunrise(date: NaiveDate, coordinates: &Coordinates) -> Option<DateTime<Utc>> {
+    let (transit, hour_angle) = solar_day(date, coordinates)?;
+    from_julian(transit - hour_angle)
+}
+
+/// When the sun sets at `coordinates` on `date`, see `sunrise`.
+pub fn sunset(date: NaiveDate, coordinates: &Coordinates) -> Option<DateTime<Utc>> {
+    let (transit, hour_angle) = solar_day(date, coordinates)?;
+    from_julian(transit + hour_angle)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    const LONDON: Coordinates = Coordinates {
+        latitude: 51.5074,
+        longitude: -0.1278,
+    };
+    const SYDNEY: Coordinates = Coordinates {
+        latitude: -33.8688,
+        longitude: 151.2093,
+    };
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, month, day).unwrap()
+    }
+
+    /// Within two minutes of the published times, which the sunrise equation keeps to away from
+    /// the poles.
+    fn assert_near(time: Option<DateTime<Utc>>, expected: (u32, u32, u32, u32)) {
+        let (month, day, hour, minute) = expected;
+        let expected = Utc
+            .with_ymd_and_hms(2026, month, day, hour, minute, 0)
+            .unwrap();
+        let time = time.expect("no sunrise or sunset");
+        assert!(
+            (time - expected).num_seconds().abs() <= 120,
+            "{} is not near {}",
+            time,
+            expected
+        );
+    }
+
+    #[test]
+    fn london() {
+        // 04:43 and 21:21 BST
+        assert_near(sunrise(date(6, 21), &LONDON), (6, 21, 3, 43));
+        assert_near(sunset(date(6, 21), &LONDON), (6, 21, 20, 21));
+        assert_near(sunrise(date(12, 21), &LONDON), (12, 21, 8, 4));
+        assert_near(sunset(date(12, 21), &LONDON), (12, 21, 15, 54));
+    }
+
+    #[test]
+    fn sydney() {
+        // 05:41 and 20:05 AEDT, so sunrise is on the UTC day before
+        assert_near(sunrise(date(12, 21), &SYDNEY), (12, 20, 18, 41));
+        assert_near(sunset(date(12, 21), &SYDNEY), (12, 21, 9, 5));
+        // 07:00 and 16:54 AEST
+        assert_near(sunrise(date(6, 21), &SYDNEY), (6, 20, 21, 0));
+        assert_near(sunset(date(6, 21), &SYDNEY), (6, 21, 6, 54));
+    }
+
+    #[test]
+    fn polar_day_and_night() {
+        let tromso = Coordinates {
+            latitude: 69.6492,
+            longitude: 18.9553,
+        };
+        assert_eq!(sunrise(date(12, 21), &tromso), None);
+        assert_eq!(sunset(date(12, 21), &tromso), None);
+        assert_eq!(sunrise(date(6, 21), &tromso), None);
+        assert_eq!(sunset(date(6, 21), &tromso), None);
+        // The sun rises and sets there in spring
+        assert!(sunrise(date(3, 21), &tromso).is_some());
+    }
+}
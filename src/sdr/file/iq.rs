@@ -0,0 +1,106 @@
+use std::path::Path;
+use std::time::Duration;
+
+use hound::{SampleFormat, WavReader};
+use regex::Regex;
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    dsp::{Complex, Demodulator, AUDIO_RATE},
+    sdr::Tuning,
+};
+
+use super::{send, FileEvent, Pacer};
+
+/// IQ is read and demodulated in blocks of this length.
+const BLOCK: Duration = Duration::from_millis(100);
+
+/// Center frequency of an IQ recording, from the `..._7100000Hz_...` in names given by SDR# and SDR++.
+fn center_frequency(path: &Path) -> Option<f64> {
+    let name = path.file_name()?.to_string_lossy();
+    let frequency = Regex::new(r"(\d+)Hz").unwrap();
+    frequency.captures_iter(&name).last()?[1].parse().ok()
+}
+
+/// Demodulates a two channel IQ WAV, I left and Q right.
+pub fn replay(
+    path: &Path,
+    tuning: &Tuning,
+    pacer: &Pacer,
+    token: &CancellationToken,
+    events: &Sender<FileEvent>,
+) -> anyhow::Result<()> {
+    let mut reader = WavReader::open(path)?;
+    let spec = reader.spec();
+    if spec.channels != 2 {
+        anyhow::bail!("IQ recordings need 2 channels, not {}", spec.channels);
+    }
+
+    let center = center_frequency(path).unwrap_or_else(|| {
+        log::warn!(
+            "no center frequency in {}, assuming {}",
+            path.display(),
+            tuning.frequency()
+        );
+        tuning.frequency()
+    });
+    log::info!(
+        "replaying {} Hz IQ centered on {} Hz",
+        spec.sample_rate,
+        center
+    );
+    let mut demodulator =
+        Demodulator::new(spec.sample_rate as f64, tuning.frequency() - center, tuning);
+
+    // Every format ends up scaled to [-1, 1)
+    let mut values: Box<dyn Iterator<Item = hound::Result<f32>>> =
+        match (spec.sample_format, spec.bits_per_sample) {
+            (SampleFormat::Float, 32) => Box::new(reader.samples::<f32>()),
+            (SampleFormat::Int, 8) => {
+                Box::new(reader.samples::<i8>().map(|s| s.map(|s| s as f32 / 128.0)))
+            }
+            (SampleFormat::Int, 16) => Box::new(
+                reader
+                    .samples::<i16>()
+                    .map(|s| s.map(|s| s as f32 / 32768.0)),
+            ),
+            (format, bits) => anyhow::bail!("unsupported {}-bit {:?} IQ", bits, format),
+        };
+
+    if !send(events, FileEvent::Ready(AUDIO_RATE), token) {
+        return Ok(());
+    }
+
+    let block = (spec.sample_rate as f64 * BLOCK.as_secs_f64()) as usize;
+    let mut position = Duration::ZERO;
+    loop {
+        let mut iq = Vec::with_capacity(block);
+        while iq.len() < block {
+            let (Some(i), Some(q)) = (values.next(), values.next()) else {
+                break;
+            };
+            iq.push(Complex::new(i?, q?));
+        }
+        if iq.is_empty() {
+            return Ok(());
+        }
+
+        let (samples, power) = demodulator.process(&iq);
+        if !send(
+            events,
+            FileEvent::SoundData {
+                samples,
+                rssi: power,
+            },
+            token,
+        ) {
+            return Ok(());
+        }
+
+        position += BLOCK;
+        if !pacer.wait(position, token) {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,137 @@
+mod iq;
+mod scraper;
+mod snd;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+
+pub use scraper::{FileScraper, FileScraperSettings};
+
+use crate::sdr::{kiwi::capture, Tuning};
+
+#[derive(Debug)]
+pub enum FileCloseReason {
+    EndOfFile,
+    Error(String),
+}
+
+/// Mirrors `KiwiEvent`, with the audio already decoded.
+#[derive(Debug)]
+pub enum FileEvent {
+    Close(FileCloseReason),
+    Ready(u32),
+    SoundData { samples: Vec<i16>, rssi: f64 },
+}
+
+/// Holds playback to the rate the file was recorded at, scaled by `speed`.
+struct Pacer {
+    start: Instant,
+    speed: f64,
+}
+
+impl Pacer {
+    fn new(speed: f64) -> Self {
+        Pacer {
+            start: Instant::now(),
+            speed,
+        }
+    }
+
+    /// Sleeps until `position` into the recording is due, returning false if cancelled meanwhile.
+    fn wait(&self, position: Duration, token: &CancellationToken) -> bool {
+        if self.speed > 0.0 {
+            let due = self.start + position.div_f64(self.speed);
+            while !token.is_cancelled() {
+                let now = Instant::now();
+                if now >= due {
+                    break;
+                }
+                std::thread::sleep((due - now).min(Duration::from_millis(100)));
+            }
+        }
+        !token.is_cancelled()
+    }
+}
+
+/// Plays back a recording as if it were a live receiver.
+pub struct FileSource {
+    cancellation_token: CancellationToken,
+    event_channel_rx: Option<tokio::sync::mpsc::Receiver<FileEvent>>,
+    path: PathBuf,
+    speed: f64,
+}
+
+impl FileSource {
+    pub fn new(path: PathBuf, speed: f64) -> Self {
+        Self {
+            cancellation_token: CancellationToken::new(),
+            event_channel_rx: None,
+            path,
+            speed,
+        }
+    }
+
+    /// Starts playback from the top. IQ recordings are demodulated according to `tuning`.
+    pub async fn connect(&mut self, tuning: Tuning) -> anyhow::Result<()> {
+        log::debug!("Replaying {}", self.path.display());
+
+        let mut magic = [0; 8];
+        File::open(&self.path)?.read_exact(&mut magic)?;
+        let is_capture = &magic == capture::MAGIC;
+        if !is_capture && &magic[..4] != b"RIFF" {
+            anyhow::bail!("{} is neither an IQ WAV nor a capture", self.path.display());
+        }
+
+        // Create event channels
+        let (event_tx, event_rx) = tokio::sync::mpsc::channel::<FileEvent>(100);
+        self.event_channel_rx = Some(event_rx);
+
+        self.cancellation_token = CancellationToken::new();
+        let token = self.cancellation_token.clone();
+        let path = self.path.clone();
+        let pacer = Pacer::new(self.speed);
+        tokio::task::spawn_blocking(move || {
+            let result = if is_capture {
+                snd::replay(&path, &pacer, &token, &event_tx)
+            } else {
+                iq::replay(&path, &tuning, &pacer, &token, &event_tx)
+            };
+            let reason = match result {
+                Ok(()) => FileCloseReason::EndOfFile,
+                Err(e) => {
+                    log::error!("Error replaying {}: {:?}", path.display(), e);
+                    FileCloseReason::Error(e.to_string())
+                }
+            };
+            if !token.is_cancelled() {
+                let _ = event_tx.blocking_send(FileEvent::Close(reason));
+            }
+        });
+
+        Ok(())
+    }
+
+    pub async fn read_event(&mut self, timeout: Duration) -> Option<FileEvent> {
+        let rx = self.event_channel_rx.as_mut()?;
+
+        tokio::time::timeout(timeout, rx.recv())
+            .await
+            .unwrap_or_default()
+    }
+
+    fn shutdown(&self) -> anyhow::Result<()> {
+        log::debug!("Shutting down replay of {}", self.path.display());
+        self.cancellation_token.cancel();
+        Ok(())
+    }
+}
+
+/// Sends `event` unless the replay was stopped, returning whether to carry on.
+fn send(events: &Sender<FileEvent>, event: FileEvent, token: &CancellationToken) -> bool {
+    !token.is_cancelled() && events.blocking_send(event).is_ok()
+}
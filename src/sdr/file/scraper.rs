@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use colored::Colorize;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    audio::{AudioStream, WriterPool},
+    config::SinkConfig,
+    events::{EventBus, EventKind, StationState},
+    sdr::{
+        file::{FileCloseReason, FileEvent, FileSource},
+        scraper::{SDRScraper, ScraperOutputs, ScraperStats, ScraperStatus},
+        Tuning,
+    },
+};
+
+#[derive(Clone)]
+pub struct FileScraperSettings {
+    pub name: String,
+    pub path: PathBuf,
+    /// Playback speed, 1.0 being real time and 0 as fast as possible.
+    pub speed: f64,
+    pub station: Tuning,
+    pub sinks: Vec<SinkConfig>,
+}
+
+pub struct FileScraper {
+    settings: FileScraperSettings,
+    sdr: Arc<Mutex<FileSource>>,
+    status: ScraperStatus,
+    token: CancellationToken,
+    outputs: ScraperOutputs,
+}
+
+impl FileScraper {
+    pub fn new(settings: FileScraperSettings, pool: &WriterPool, events: &EventBus) -> FileScraper {
+        FileScraper {
+            settings: settings.clone(),
+            sdr: Arc::new(Mutex::new(FileSource::new(settings.path, settings.speed))),
+            status: ScraperStatus::Stopped,
+            token: CancellationToken::new(),
+            outputs: ScraperOutputs::new(
+                &settings.name,
+                settings.station,
+                &settings.sinks,
+                pool,
+                events,
+            ),
+        }
+    }
+
+    /// Plays the file once; unlike a receiver it isn't reconnected at the end.
+    async fn run(
+        settings: FileScraperSettings,
+        sdr: Arc<Mutex<FileSource>>,
+        outputs: ScraperOutputs,
+    ) {
+        outputs.state.set(StationState::Connecting);
+        if let Err(e) = sdr.lock().await.connect(outputs.tuning()).await {
+            log::error!("{}: failed to open: {}", settings.name.red(), e);
+            outputs.publish(EventKind::Disconnected {
+                reason: e.to_string(),
+            });
+            outputs.state.set(StationState::Disconnected);
+            return;
+        }
+        outputs.state.set(StationState::Connected);
+
+        let mut sample_rate = 12000;
+        loop {
+            // Make sure SDR instance lock is dropped immediately after fetching the latest message
+            let event = sdr.lock().await.read_event(Duration::from_secs(1)).await;
+            let Some(event) = event else {
+                continue;
+            };
+
+            match event {
+                FileEvent::Close(reason) => {
+                    match &reason {
+                        FileCloseReason::EndOfFile => {
+                            log::info!("{}: end of file", settings.name.yellow())
+                        }
+                        FileCloseReason::Error(e) => {
+                            log::error!("{}: replay failed: {}", settings.name.red(), e)
+                        }
+                    }
+                    outputs.publish(EventKind::Disconnected {
+                        reason: format!("{:?}", reason),
+                    });
+                    outputs.state.set(StationState::Disconnected);
+                    break;
+                }
+                FileEvent::Ready(rate) => {
+                    log::info!("{} is ready at {} Hz", settings.name.green(), rate);
+                    outputs.state.set(StationState::Ready);
+                    sample_rate = rate;
+                }
+                FileEvent::SoundData { samples, rssi } => {
+                    outputs.set_rssi(rssi);
+                    outputs.write(samples, sample_rate).await;
+                }
+            }
+        }
+
+        outputs.writer.close();
+    }
+}
+
+#[async_trait::async_trait]
+impl SDRScraper for FileScraper {
+    async fn start(&mut self) -> anyhow::Result<()> {
+        if self.status == ScraperStatus::Running {
+            log::warn!("SDR for {} is already running", self.settings.name);
+            return Ok(());
+        }
+
+        log::debug!("starting scraper for {}", self.settings.name.green());
+        self.token = CancellationToken::new();
+
+        let token = self.token.clone();
+        let run = Self::run(
+            self.settings.clone(),
+            self.sdr.clone(),
+            self.outputs.clone(),
+        );
+        let name = self.settings.name.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = run => {}
+                _ = token.cancelled() => {
+                    log::debug!("{}: event loop cancelled", name.yellow());
+                }
+            }
+        });
+
+        self.status = ScraperStatus::Running;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        log::debug!("Stopping scraper for {}", self.settings.name.green());
+
+        self.token.cancel();
+        self.sdr.lock().await.shutdown()?;
+        self.outputs.writer.close();
+        self.status = ScraperStatus::Stopped;
+        self.outputs.state.set(StationState::Stopped);
+
+        Ok(())
+    }
+
+    /// IQ recordings are demodulated with the new tuning the next time the replay is started.
+    async fn tune(&mut self, tuning: Tuning) -> anyhow::Result<()> {
+        log::info!("{}: tuning to {}", self.settings.name.green(), tuning);
+        self.outputs.set_tuning(tuning);
+        Ok(())
+    }
+
+    fn status(&self) -> ScraperStatus {
+        self.status.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    fn get_stats(&self) -> ScraperStats {
+        self.outputs.stats(self.status.clone())
+    }
+
+    fn audio(&self) -> AudioStream {
+        self.outputs.audio.clone()
+    }
+}
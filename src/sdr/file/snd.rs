@@ -0,0 +1,61 @@
+use std::path::Path;
+
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    audio::ima_adpcm::IMA_ADPCM_Decoder,
+    sdr::kiwi::{capture::CaptureReader, parse_message, KiwiEvent},
+};
+
+use super::{send, FileEvent, Pacer};
+
+/// Rate assumed for captures that start after the Kiwi's `audio_init`.
+const DEFAULT_SAMPLE_RATE: u32 = 12000;
+
+/// Replays a Kiwi capture through the same parsing as a live connection, keeping the frames' spacing.
+pub fn replay(
+    path: &Path,
+    pacer: &Pacer,
+    token: &CancellationToken,
+    events: &Sender<FileEvent>,
+) -> anyhow::Result<()> {
+    let mut reader = CaptureReader::open(path)?;
+    let mut decoder = IMA_ADPCM_Decoder::new();
+    let mut ready = false;
+    let mut start = None;
+
+    while let Some(frame) = reader.next_frame()? {
+        let start = *start.get_or_insert(frame.timestamp);
+        let position = (frame.timestamp - start).to_std().unwrap_or_default();
+        if !pacer.wait(position, token) {
+            return Ok(());
+        }
+
+        let event = match parse_message(frame.message) {
+            Some(KiwiEvent::Ready(rate)) => {
+                decoder = IMA_ADPCM_Decoder::new();
+                ready = true;
+                FileEvent::Ready(rate)
+            }
+            Some(KiwiEvent::SoundData { data, rssi }) => {
+                if !ready {
+                    ready = true;
+                    if !send(events, FileEvent::Ready(DEFAULT_SAMPLE_RATE), token) {
+                        return Ok(());
+                    }
+                }
+
+                let mut samples = vec![0; data.len() * 2];
+                decoder.decode_block(&data, &mut samples);
+                FileEvent::SoundData { samples, rssi }
+            }
+            _ => continue,
+        };
+        if !send(events, event, token) {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
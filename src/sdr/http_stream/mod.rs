@@ -0,0 +1,191 @@
+mod decode;
+mod scraper;
+
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+pub use scraper::{HttpStreamScraper, HttpStreamScraperSettings};
+
+use self::decode::{decode_stream, ChannelReader};
+
+#[derive(Debug)]
+pub enum HttpStreamEvent {
+    Close(String),
+    Audio { samples: Vec<i16>, sample_rate: u32 },
+}
+
+/// A plain HTTP or Icecast audio stream, decoded to mono PCM.
+pub struct HttpStream {
+    cancellation_token: CancellationToken,
+    event_channel_rx: Option<tokio::sync::mpsc::Receiver<HttpStreamEvent>>,
+    endpoint: Url,
+}
+
+impl HttpStream {
+    pub fn new(mut endpoint: Url) -> Self {
+        // Endpoints without a scheme are taken as WebSocket ones elsewhere
+        match endpoint.scheme() {
+            "ws" => endpoint.set_scheme("http").unwrap(),
+            "wss" => endpoint.set_scheme("https").unwrap(),
+            _ => {}
+        }
+        Self {
+            cancellation_token: CancellationToken::new(),
+            event_channel_rx: None,
+            endpoint,
+        }
+    }
+
+    pub async fn connect(&mut self) -> anyhow::Result<()> {
+        log::debug!("Connecting to stream at {}", self.endpoint);
+
+        let request = reqwest::get(self.endpoint.clone());
+        let mut response = tokio::time::timeout(Duration::from_secs(5), request)
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection timeout"))??
+            .error_for_status()?;
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        if let Some(name) = response.headers().get("icy-name") {
+            log::info!("stream name: {}", String::from_utf8_lossy(name.as_bytes()));
+        }
+
+        // Create event channels
+        let (event_tx, event_rx) = tokio::sync::mpsc::channel::<HttpStreamEvent>(100);
+        let (data_tx, data_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(64);
+        self.event_channel_rx = Some(event_rx);
+
+        self.cancellation_token = CancellationToken::new();
+        let token = self.cancellation_token.clone();
+        let endpoint = self.endpoint.clone();
+        let close_tx = event_tx.clone();
+        tokio::spawn(async move {
+            log::debug!("starting read loop for stream at {}", endpoint);
+            let read_loop = async {
+                loop {
+                    match response.chunk().await {
+                        Ok(Some(chunk)) => {
+                            if data_tx.send(chunk.to_vec()).await.is_err() {
+                                // The decoder gave up and reports why itself
+                                return;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            log::error!("Error reading stream: {:?}", e);
+                            break;
+                        }
+                    }
+                }
+                // Dropping the sender lets the decoder drain what's left before it closes
+                drop(data_tx);
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = read_loop => {}
+            };
+        });
+
+        // Decoding is blocking work, so it gets a thread of its own reading from the channel
+        tokio::task::spawn_blocking(move || {
+            let reader = ChannelReader::new(data_rx);
+            let reason = match decode_stream(reader, content_type.as_deref(), &event_tx) {
+                Ok(()) => "StreamEnded".to_string(),
+                Err(e) => e.to_string(),
+            };
+            let _ = close_tx.blocking_send(HttpStreamEvent::Close(reason));
+        });
+
+        Ok(())
+    }
+
+    pub async fn read_event(&mut self, timeout: Duration) -> Option<HttpStreamEvent> {
+        let rx = self.event_channel_rx.as_mut()?;
+
+        tokio::time::timeout(timeout, rx.recv())
+            .await
+            .unwrap_or_default()
+    }
+
+    fn shutdown(&self) -> anyhow::Result<()> {
+        log::debug!("Shutting down stream");
+        self.cancellation_token.cancel();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Samples in an MPEG-1 Layer III frame.
+    const FRAME_SAMPLES: usize = 1152;
+
+    /// A silent mono MP3 frame at 128 kbit/s and 44.1 kHz: a header, then side info and main
+    /// data of all zeros, which decode to nothing but silence.
+    fn silent_frame() -> Vec<u8> {
+        let mut frame = vec![0xff, 0xfb, 0x90, 0xc0];
+        // 144 * 128000 / 44100 bytes in all
+        frame.resize(417, 0);
+        frame
+    }
+
+    /// Answers one request with `body`, written in small pieces, then hangs up.
+    async fn serve(listener: TcpListener, body: Vec<u8>) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0; 1024];
+        let _ = socket.read(&mut request).await.unwrap();
+        socket
+            .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: audio/mpeg\r\nicy-name: Test FM\r\n\r\n")
+            .await
+            .unwrap();
+        for piece in body.chunks(300) {
+            socket.write_all(piece).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn decodes_an_mp3_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let frames = 20;
+        tokio::spawn(serve(listener, silent_frame().repeat(frames)));
+
+        // Configured endpoints without a scheme come as WebSocket ones
+        let endpoint = Url::parse(&format!("ws://127.0.0.1:{}/stream", port)).unwrap();
+        let mut stream = HttpStream::new(endpoint);
+        stream.connect().await.unwrap();
+
+        let mut samples = Vec::new();
+        let reason = loop {
+            match stream.read_event(Duration::from_secs(5)).await {
+                Some(HttpStreamEvent::Audio {
+                    samples: audio,
+                    sample_rate,
+                }) => {
+                    assert_eq!(sample_rate, 44100);
+                    samples.extend(audio);
+                }
+                Some(HttpStreamEvent::Close(reason)) => break reason,
+                None => panic!("stream neither ended nor sent audio"),
+            }
+        };
+
+        assert_eq!(reason, "StreamEnded");
+        // The decoder may hold back a frame at either end
+        assert!(samples.len() >= (frames - 2) * FRAME_SAMPLES);
+        assert!(samples.len() <= frames * FRAME_SAMPLES);
+        assert!(samples.iter().all(|&sample| sample == 0));
+    }
+}
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use colored::Colorize;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::{
+    audio::{AudioStream, WriterPool},
+    config::SinkConfig,
+    events::{EventBus, EventKind, StationState},
+    sdr::{
+        http_stream::{HttpStream, HttpStreamEvent},
+        scraper::{SDRScraper, ScraperOutputs, ScraperStats, ScraperStatus},
+        Tuning,
+    },
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(4);
+
+#[derive(Clone)]
+pub struct HttpStreamScraperSettings {
+    pub name: String,
+    pub endpoint: Url,
+    /// What the stream is listening to, only used to name and describe recordings.
+    pub station: Tuning,
+    pub sinks: Vec<SinkConfig>,
+}
+
+pub struct HttpStreamScraper {
+    settings: HttpStreamScraperSettings,
+    sdr: Arc<Mutex<HttpStream>>,
+    status: ScraperStatus,
+    token: CancellationToken,
+    outputs: ScraperOutputs,
+}
+
+impl HttpStreamScraper {
+    pub fn new(
+        settings: HttpStreamScraperSettings,
+        pool: &WriterPool,
+        events: &EventBus,
+    ) -> HttpStreamScraper {
+        HttpStreamScraper {
+            settings: settings.clone(),
+            sdr: Arc::new(Mutex::new(HttpStream::new(settings.endpoint))),
+            status: ScraperStatus::Stopped,
+            token: CancellationToken::new(),
+            outputs: ScraperOutputs::new(
+                &settings.name,
+                settings.station,
+                &settings.sinks,
+                pool,
+                events,
+            ),
+        }
+    }
+
+    async fn run(
+        settings: HttpStreamScraperSettings,
+        sdr: Arc<Mutex<HttpStream>>,
+        outputs: ScraperOutputs,
+    ) {
+        let mut reconnecting = false;
+        loop {
+            outputs.state.set(StationState::Connecting);
+            if let Err(e) = sdr.lock().await.connect().await {
+                log::error!("{}: failed to connect: {}", settings.name.red(), e);
+                if reconnecting {
+                    outputs.publish(EventKind::ReconnectFailed {
+                        error: e.to_string(),
+                    });
+                } else {
+                    outputs.publish(EventKind::Disconnected {
+                        reason: e.to_string(),
+                    });
+                }
+                outputs.state.set(StationState::Disconnected);
+                reconnecting = true;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+
+            if reconnecting {
+                log::info!("{}: reconnected", settings.name.green());
+                outputs.publish(EventKind::Reconnected);
+            }
+            outputs.state.set(StationState::Connected);
+
+            loop {
+                // Make sure SDR instance lock is dropped immediately after fetching the latest message
+                let event = sdr.lock().await.read_event(Duration::from_secs(1)).await;
+                let Some(event) = event else {
+                    continue;
+                };
+
+                match event {
+                    HttpStreamEvent::Close(reason) => {
+                        log::error!("{}: stream closed: {}", settings.name.red(), reason);
+                        outputs.publish(EventKind::Disconnected { reason });
+                        outputs.state.set(StationState::Disconnected);
+                        break;
+                    }
+                    HttpStreamEvent::Audio {
+                        samples,
+                        sample_rate,
+                    } => {
+                        if outputs.state.get().0 != StationState::Ready {
+                            log::info!("{} is ready at {} Hz", settings.name.green(), sample_rate);
+                            outputs.state.set(StationState::Ready);
+                        }
+                        outputs.write(samples, sample_rate).await;
+                    }
+                }
+            }
+
+            outputs.writer.close();
+            reconnecting = true;
+            log::info!("{}: reconnecting in 4...", settings.name.yellow());
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SDRScraper for HttpStreamScraper {
+    async fn start(&mut self) -> anyhow::Result<()> {
+        if self.status == ScraperStatus::Running {
+            log::warn!("SDR for {} is already running", self.settings.name);
+            return Ok(());
+        }
+
+        log::debug!("starting scraper for {}", self.settings.name.green());
+        self.token = CancellationToken::new();
+
+        let token = self.token.clone();
+        let run = Self::run(
+            self.settings.clone(),
+            self.sdr.clone(),
+            self.outputs.clone(),
+        );
+        let name = self.settings.name.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = run => {}
+                _ = token.cancelled() => {
+                    log::debug!("{}: event loop cancelled", name.yellow());
+                }
+            }
+        });
+
+        self.status = ScraperStatus::Running;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        log::debug!("Stopping scraper for {}", self.settings.name.green());
+
+        self.token.cancel();
+        self.sdr.lock().await.shutdown()?;
+        self.outputs.writer.close();
+        self.status = ScraperStatus::Stopped;
+        self.outputs.state.set(StationState::Stopped);
+
+        Ok(())
+    }
+
+    /// The stream can't be tuned, this only changes how it's labelled.
+    async fn tune(&mut self, tuning: Tuning) -> anyhow::Result<()> {
+        log::info!("{}: labelling as {}", self.settings.name.green(), tuning);
+        self.outputs.set_tuning(tuning);
+        Ok(())
+    }
+
+    fn status(&self) -> ScraperStatus {
+        self.status.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    fn get_stats(&self) -> ScraperStats {
+        self.outputs.stats(self.status.clone())
+    }
+
+    fn audio(&self) -> AudioStream {
+        self.outputs.audio.clone()
+    }
+}
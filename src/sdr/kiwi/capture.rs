@@ -0,0 +1,262 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{DateTime, Utc};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Start of every capture file, followed by the frames back to back.
+pub const MAGIC: &[u8; 8] = b"KIWICAP1";
+
+const TEXT: u8 = 0;
+const BINARY: u8 = 1;
+
+/// Far larger than any frame a Kiwi sends, so a corrupt length isn't allocated.
+const MAX_FRAME_LENGTH: u32 = 1 << 20;
+
+/// A WebSocket frame as it was received from a Kiwi.
+///
+/// Each frame is stored as its kind (0 text, 1 binary), the receive time in microseconds since the
+/// epoch as an i64, the payload length as a u32 and the payload, all little endian.
+pub struct CaptureFrame {
+    pub timestamp: DateTime<Utc>,
+    pub message: Message,
+}
+
+pub struct CaptureReader {
+    reader: BufReader<File>,
+    path: PathBuf,
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path) -> anyhow::Result<CaptureReader> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            anyhow::bail!("{} is not a Kiwi capture", path.display());
+        }
+        Ok(CaptureReader {
+            reader,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Reads the next frame, or `None` at the end of the capture. A frame cut short, as the
+    /// writer leaves behind when the scraper is killed, also ends the capture.
+    pub fn next_frame(&mut self) -> anyhow::Result<Option<CaptureFrame>> {
+        let kind = match self.reader.read_u8() {
+            Ok(kind) => kind,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let frame = self.read_frame();
+        let Some((timestamp, payload)) = self.truncated(frame)? else {
+            return Ok(None);
+        };
+
+        let message = match kind {
+            TEXT => Message::Text(String::from_utf8(payload)?),
+            BINARY => Message::Binary(payload),
+            other => anyhow::bail!("unknown frame kind {}", other),
+        };
+        Ok(Some(CaptureFrame {
+            timestamp: DateTime::from_timestamp_micros(timestamp)
+                .ok_or_else(|| anyhow::anyhow!("bad timestamp {}", timestamp))?,
+            message,
+        }))
+    }
+
+    fn read_frame(&mut self) -> std::io::Result<(i64, Vec<u8>)> {
+        let timestamp = self.reader.read_i64::<LittleEndian>()?;
+        let length = self.reader.read_u32::<LittleEndian>()?;
+        if length > MAX_FRAME_LENGTH {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("frame of {} bytes", length),
+            ));
+        }
+        let mut payload = vec![0; length as usize];
+        self.reader.read_exact(&mut payload)?;
+        Ok((timestamp, payload))
+    }
+
+    /// Turns a frame that ran into the end of the file into `None`.
+    fn truncated<T>(&self, read: std::io::Result<T>) -> anyhow::Result<Option<T>> {
+        match read {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                log::warn!(
+                    "{} ends partway through a frame, stopping there",
+                    self.path.display()
+                );
+                Ok(None)
+            }
+            Err(e) => Err(anyhow::anyhow!("{}: {}", self.path.display(), e)),
+        }
+    }
+}
+
+/// Tees frames into a capture file as they're received.
+pub struct CaptureWriter {
+    writer: BufWriter<File>,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path) -> anyhow::Result<CaptureWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        Ok(CaptureWriter { writer })
+    }
+
+    /// Appends `message`, skipping the frames that carry nothing to replay.
+    pub fn write(&mut self, timestamp: DateTime<Utc>, message: &Message) -> anyhow::Result<()> {
+        let (kind, payload) = match message {
+            Message::Text(text) => (TEXT, text.as_bytes()),
+            Message::Binary(bin) => (BINARY, bin.as_slice()),
+            _ => return Ok(()),
+        };
+        self.writer.write_u8(kind)?;
+        self.writer
+            .write_i64::<LittleEndian>(timestamp.timestamp_micros())?;
+        self.writer
+            .write_u32::<LittleEndian>(payload.len() as u32)?;
+        self.writer.write_all(payload)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::sdr::kiwi::{event::KiwiEvent, parse_message};
+
+    /// A capture file of its own for each test, removed when dropped.
+    struct TempCapture(PathBuf);
+
+    impl TempCapture {
+        fn new(name: &str) -> Self {
+            TempCapture(std::env::temp_dir().join(format!(
+                "sdr-scraper-{}-{}.kiwicap",
+                name,
+                std::process::id()
+            )))
+        }
+    }
+
+    impl Drop for TempCapture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn sound_frame(smeter: u16, data: &[u8]) -> Vec<u8> {
+        let mut frame = b"SND".to_vec();
+        frame.push(0);
+        frame.extend_from_slice(&7u32.to_le_bytes());
+        frame.extend_from_slice(&smeter.to_be_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    #[test]
+    fn frames_round_trip_and_replay() {
+        let capture = TempCapture::new("round-trip");
+        let start = Utc.with_ymd_and_hms(2026, 10, 18, 19, 0, 0).unwrap();
+        let frames = [
+            Message::Text("MSG client_public_ip=127.0.0.1".to_string()),
+            Message::Binary(b"MSG audio_init=0 audio_rate=12000".to_vec()),
+            Message::Ping(vec![1, 2, 3]),
+            Message::Binary(sound_frame(770, &[0x12, 0x34, 0x56])),
+        ];
+
+        let mut writer = CaptureWriter::create(&capture.0).unwrap();
+        for (i, frame) in frames.iter().enumerate() {
+            let timestamp = start + chrono::Duration::microseconds(1_500_001 * i as i64);
+            writer.write(timestamp, frame).unwrap();
+        }
+        drop(writer);
+
+        let mut reader = CaptureReader::open(&capture.0).unwrap();
+        let mut read = Vec::new();
+        while let Some(frame) = reader.next_frame().unwrap() {
+            read.push(frame);
+        }
+
+        // The ping carries nothing to replay and isn't kept
+        let kept = [0, 1, 3];
+        assert_eq!(read.len(), kept.len());
+        for (frame, &i) in read.iter().zip(&kept) {
+            assert_eq!(frame.message, frames[i]);
+            assert_eq!(
+                frame.timestamp,
+                start + chrono::Duration::microseconds(1_500_001 * i as i64)
+            );
+        }
+
+        let events = read
+            .into_iter()
+            .map(|frame| parse_message(frame.message))
+            .collect::<Vec<Option<KiwiEvent>>>();
+        assert!(
+            matches!(&events[0], Some(KiwiEvent::Message(msg)) if msg == "MSG client_public_ip=127.0.0.1")
+        );
+        assert!(matches!(events[1], Some(KiwiEvent::Ready(12000))));
+        match &events[2] {
+            Some(KiwiEvent::SoundData { data, rssi }) => {
+                assert_eq!(data, &[0x12, 0x34, 0x56]);
+                assert!((rssi - (77.0 - 127.0)).abs() < 1e-9);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ends_at_a_truncated_frame() {
+        let capture = TempCapture::new("truncated");
+        let start = Utc.with_ymd_and_hms(2026, 10, 18, 19, 0, 0).unwrap();
+        let mut writer = CaptureWriter::create(&capture.0).unwrap();
+        writer
+            .write(start, &Message::Binary(sound_frame(770, &[0x12; 64])))
+            .unwrap();
+        writer
+            .write(start, &Message::Binary(sound_frame(770, &[0x34; 64])))
+            .unwrap();
+        drop(writer);
+
+        let whole = std::fs::read(&capture.0).unwrap();
+        let frame = (whole.len() - MAGIC.len()) / 2;
+        // Cut in the second frame's timestamp, length and payload
+        for cut in [3, 10, frame - 1] {
+            std::fs::write(&capture.0, &whole[..MAGIC.len() + frame + cut]).unwrap();
+            let mut reader = CaptureReader::open(&capture.0).unwrap();
+            assert!(reader.next_frame().unwrap().is_some());
+            assert!(reader.next_frame().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let capture = TempCapture::new("oversized");
+        let mut file = MAGIC.to_vec();
+        file.push(BINARY);
+        file.extend_from_slice(&0i64.to_le_bytes());
+        file.extend_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&capture.0, file).unwrap();
+
+        let mut reader = CaptureReader::open(&capture.0).unwrap();
+        assert!(reader.next_frame().is_err());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let capture = TempCapture::new("not-a-capture");
+        std::fs::write(&capture.0, b"RIFF\0\0\0\0WAVE").unwrap();
+        assert!(CaptureReader::open(&capture.0).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+use chrono::{DateTime, Utc};
+
 #[derive(Debug)]
 pub enum KiwiCloseReason {
     ServerClosed,
@@ -12,3 +14,29 @@ pub enum KiwiEvent {
     SoundData { data: Vec<u8>, rssi: f64 },
     Ping,
 }
+
+/// One line of a waterfall, in dBm per bin.
+#[derive(Clone, Debug)]
+pub struct SpectrumRow {
+    pub timestamp: DateTime<Utc>,
+    pub sequence: u32,
+    /// Frequency of the first bin's lower edge, in Hz.
+    pub start_frequency: f64,
+    /// Width of each bin, in Hz.
+    pub bin_width: f64,
+    pub bins: Vec<f32>,
+}
+
+impl SpectrumRow {
+    /// Frequency at the center of bin `index`.
+    pub fn frequency(&self, index: usize) -> f64 {
+        self.start_frequency + (index as f64 + 0.5) * self.bin_width
+    }
+}
+
+#[derive(Debug)]
+pub enum WaterfallEvent {
+    Close(KiwiCloseReason),
+    Message(String),
+    Row(SpectrumRow),
+}
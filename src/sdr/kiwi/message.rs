@@ -43,6 +43,18 @@ pub enum KiwiClientMessage {
         gain: i64,
     },
     Tune(Tuning),
+    /// Centers the waterfall on `center` Hz, showing the band's full width over 2^`zoom`.
+    SetZoom {
+        zoom: u8,
+        center: f64,
+    },
+    /// Waterfall line rate, from 0 (off) through 4 (fastest).
+    SetWaterfallSpeed(u8),
+    SetDbRange {
+        min: i32,
+        max: i32,
+    },
+    SetWaterfallCompression(bool),
     Unknown(String),
 }
 
@@ -67,8 +79,8 @@ impl From<KiwiClientMessage> for Message {
                     frequency,
                 } => Message::Text(format!(
                     "SET mod=am low_cut={} high_cut={} freq={}",
-                    -(bandwidth / 2) as i32,
-                    (bandwidth / 2) as i32,
+                    -(bandwidth / 2),
+                    bandwidth / 2,
                     frequency / 1000.0
                 )),
                 Tuning::FM {
@@ -101,9 +113,6 @@ impl From<KiwiClientMessage> for Message {
                     high_cut,
                     frequency / 1000.0
                 )),
-                _ => {
-                    todo!();
-                }
             },
             KiwiClientMessage::SetCompression(enabled) => {
                 Message::Text(format!("SET compression={}", if enabled { 1 } else { 0 }))
@@ -132,6 +141,18 @@ impl From<KiwiClientMessage> for Message {
                 decay,
                 gain
             )),
+            KiwiClientMessage::SetZoom { zoom, center } => {
+                Message::Text(format!("SET zoom={} cf={:.3}", zoom, center / 1000.0))
+            }
+            KiwiClientMessage::SetWaterfallSpeed(speed) => {
+                Message::Text(format!("SET wf_speed={}", speed))
+            }
+            KiwiClientMessage::SetDbRange { min, max } => {
+                Message::Text(format!("SET maxdb={} mindb={}", max, min))
+            }
+            KiwiClientMessage::SetWaterfallCompression(enabled) => {
+                Message::Text(format!("SET wf_comp={}", if enabled { 1 } else { 0 }))
+            }
             KiwiClientMessage::Unknown(msg) => Message::Text(msg),
         }
     }
@@ -142,7 +163,6 @@ pub enum KiwiServerMessage {
     AuthenticationResult(bool),
     Unknown(String),
     AudioInit(u32),
-    SoundData(Vec<u8>),
 }
 
 impl From<String> for KiwiServerMessage {
@@ -154,13 +174,39 @@ impl From<String> for KiwiServerMessage {
                 KiwiServerMessage::AuthenticationResult(true)
             }
         } else if msg.contains("audio_init") {
-            let parts = msg.split_whitespace().collect::<Vec<&str>>();
-            let parts = parts[1].split('=').collect::<Vec<&str>>();
-            let rate = parts[1].parse::<u32>().unwrap();
+            // Like "audio_init=0 audio_rate=12000"
+            let rate = msg
+                .split_whitespace()
+                .nth(1)
+                .and_then(|part| part.split('=').nth(1))
+                .and_then(|rate| rate.parse::<u32>().ok());
 
-            KiwiServerMessage::AudioInit(rate)
+            match rate {
+                Some(rate) => KiwiServerMessage::AudioInit(rate),
+                None => KiwiServerMessage::Unknown(msg),
+            }
         } else {
             KiwiServerMessage::Unknown(msg)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_audio_init() {
+        let init = KiwiServerMessage::from("audio_init=0 audio_rate=12000".to_string());
+        assert!(matches!(init, KiwiServerMessage::AudioInit(12000)));
+
+        for bad in [
+            "audio_init",
+            "audio_init=0 audio_rate",
+            "audio_init=0 audio_rate=fast",
+        ] {
+            let init = KiwiServerMessage::from(bad.to_string());
+            assert!(matches!(init, KiwiServerMessage::Unknown(_)), "{}", bad);
+        }
+    }
+}
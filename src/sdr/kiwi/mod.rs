@@ -1,23 +1,39 @@
+pub mod capture;
 pub mod event;
 mod message;
 mod scraper;
+mod slots;
+mod survey;
+mod trigger;
+mod waterfall;
 
 use std::time::Duration;
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use chrono::Utc;
 use futures_util::{SinkExt, StreamExt};
 
 use rand::Rng;
-pub use scraper::{KiwiSDRScraper, KiwiSDRScraperSettings, KiwiScraperStats};
+pub use scraper::{KiwiSDRScraper, KiwiSDRScraperSettings};
+pub use slots::{SlotAllocator, SlottedScraper};
+pub use survey::{KiwiSurveyScraper, KiwiSurveyScraperSettings};
+pub use trigger::{KiwiTriggerScraper, KiwiTriggerScraperSettings};
 
 use serde::{Deserialize, Serialize};
 use tokio_tungstenite::tungstenite::Message;
 use tokio_util::sync::CancellationToken;
 use url::Url;
 
-use crate::sdr::kiwi::{event::KiwiCloseReason, message::KiwiServerMessage};
+use crate::config::Coordinates;
+use crate::sdr::kiwi::{
+    capture::CaptureWriter, event::KiwiCloseReason, message::KiwiServerMessage,
+};
 
-pub use self::{event::KiwiEvent, message::KiwiClientMessage};
+pub use self::{
+    event::{KiwiEvent, SpectrumRow, WaterfallEvent},
+    message::KiwiClientMessage,
+    waterfall::{KiwiWaterfall, WaterfallSettings},
+};
 
 #[derive(Deserialize, Serialize)]
 pub struct VerResponse {
@@ -29,6 +45,128 @@ pub struct VerResponse {
     pub code: Option<i128>,
 }
 
+/// Asks the Kiwi for its version, returning the number its sockets are opened under.
+async fn connection_number(endpoint: &Url) -> anyhow::Result<i128> {
+    let mut url = endpoint.clone();
+    url.set_scheme("http").unwrap();
+    url = url.join("VER").unwrap();
+
+    log::info!("getting version from {}", url);
+    let response = reqwest::get(url).await?;
+    let version = response.json::<VerResponse>().await?;
+    log::info!("KiwiSDR version: {}.{}", version.major, version.minor);
+
+    Ok(match version.code {
+        Some(code) => code,
+        None => rand::thread_rng().gen_range(0..1000),
+    })
+}
+
+/// What a Kiwi says about itself on `/status`, as far as it's used.
+#[derive(Debug, Clone, Default)]
+pub struct KiwiStatus {
+    /// Where the receiver is, as set up by its owner or from its GPS.
+    pub gps: Option<Coordinates>,
+    /// Channels in use and how many there are.
+    pub users: Option<usize>,
+    pub users_max: Option<usize>,
+}
+
+/// How long a Kiwi gets to answer `/status`, body included.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Fetches the `key=value` lines of a Kiwi's `/status`.
+pub async fn status(endpoint: &Url) -> anyhow::Result<KiwiStatus> {
+    let mut url = endpoint.clone();
+    url.set_scheme("http").unwrap();
+    url = url.join("status").unwrap();
+
+    let text = reqwest::Client::new()
+        .get(url)
+        .timeout(STATUS_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let mut status = KiwiStatus::default();
+    for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+        if key == "users" {
+            status.users = value.trim().parse().ok();
+        } else if key == "users_max" {
+            status.users_max = value.trim().parse().ok();
+        } else if key == "gps" {
+            // Like "(51.500000, -0.130000)"
+            status.gps = value
+                .trim_matches(|c| c == '(' || c == ')')
+                .split_once(',')
+                .and_then(|(latitude, longitude)| {
+                    Some(Coordinates {
+                        latitude: latitude.trim().parse().ok()?,
+                        longitude: longitude.trim().parse().ok()?,
+                    })
+                });
+        }
+    }
+    Ok(status)
+}
+
+/// Turns a frame from the SND socket into an event, if it carries one. Live connections and replayed
+/// captures both go through here.
+pub fn parse_message(msg: Message) -> Option<KiwiEvent> {
+    match msg {
+        Message::Text(text) => match KiwiServerMessage::from(text) {
+            KiwiServerMessage::Unknown(msg) => Some(KiwiEvent::Message(msg)),
+            _ => None,
+        },
+        Message::Binary(bin) => parse_frame(&bin),
+        Message::Close(_close) => Some(KiwiEvent::Close(KiwiCloseReason::ServerClosed)),
+        Message::Ping(_ping) => Some(KiwiEvent::Ping),
+        Message::Pong(pong) => {
+            log::debug!("Received pong message: {:?}", pong);
+            None
+        }
+        _ => None,
+    }
+}
+
+fn parse_frame(bin: &[u8]) -> Option<KiwiEvent> {
+    match bin.get(..3)? {
+        b"SND" => {
+            let data = bin.get(3..10)?;
+            let _flags = data[0];
+            let _seq = LittleEndian::read_u32(&data[1..5]);
+            let smeter = BigEndian::read_u16(&data[5..7]);
+
+            let rssi = 0.1 * smeter as f64 - 127.0;
+
+            Some(KiwiEvent::SoundData {
+                data: bin[10..].to_vec(),
+                rssi,
+            })
+        }
+        b"MSG" => {
+            let str = match String::from_utf8(bin.get(4..)?.to_vec()) {
+                Ok(str) => str,
+                Err(e) => {
+                    log::error!("Error decoding binary message: {:?}", e);
+                    return None;
+                }
+            };
+
+            match KiwiServerMessage::from(str) {
+                KiwiServerMessage::Unknown(msg) => Some(KiwiEvent::Message(msg)),
+                KiwiServerMessage::AuthenticationResult(false) => {
+                    Some(KiwiEvent::Close(KiwiCloseReason::AuthenticationFailed))
+                }
+                KiwiServerMessage::AudioInit(rate) => Some(KiwiEvent::Ready(rate)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
 pub struct KiwiSDR {
     cancellation_token: CancellationToken,
     event_channel_rx: Option<tokio::sync::mpsc::Receiver<KiwiEvent>>,
@@ -46,38 +184,25 @@ impl KiwiSDR {
         }
     }
 
-    pub async fn connect(&mut self, password: Option<String>) -> anyhow::Result<()> {
+    /// Connects and logs in, teeing every frame received to `capture` if given.
+    pub async fn connect(
+        &mut self,
+        password: Option<String>,
+        mut capture: Option<CaptureWriter>,
+    ) -> anyhow::Result<()> {
         log::debug!("Connecting to KiwiSDR at {}", self.endpoint.clone());
 
-        let mut url = self.endpoint.clone();
-        url.set_scheme("http").unwrap();
-        url = url.join("VER").unwrap();
-
-        log::info!("getting version from {}", url);
-        let response = reqwest::get(url).await?;
-        let version = response.json::<VerResponse>().await?;
-        log::info!("KiwiSDR version: {}.{}", version.major, version.minor);
-
-        // reqwest::get
-        let number = if let Some(code) = version.code {
-            code
-        } else {
-            let mut rng = rand::thread_rng();
-            rng.gen_range(0..1000)
-        };
+        let number = connection_number(&self.endpoint).await?;
 
         // Connect and login
-        let connect = tokio_tungstenite::connect_async(format!(
-            "{}/kiwi/{}/SND",
-            self.endpoint.clone(),
-            number
-        ));
+        let url = self.endpoint.join(&format!("kiwi/{}/SND", number))?;
+        let connect = tokio_tungstenite::connect_async(url);
 
         let (ws_socket, _) = tokio::time::timeout(Duration::from_secs(2), connect)
             .await
             .map_err(|_| anyhow::anyhow!("Connection timeout"))??;
 
-        let (mut write, read) = ws_socket.split();
+        let (mut write, mut read) = ws_socket.split();
         write
             .send(KiwiClientMessage::Login(password).into())
             .await?;
@@ -94,105 +219,47 @@ impl KiwiSDR {
         tokio::spawn(async move {
             log::debug!("starting event loop for KiwiSDR at {}", endpoint);
             let token = token_clone;
-            let read_loop = read.for_each(|msg| async {
-                let msg = match msg {
-                    Ok(msg) => msg,
-                    Err(e) => {
-                        log::error!("Error reading message: {:?}", e);
-                        return;
-                    }
-                };
+            let read_loop = async {
+                while let Some(msg) = read.next().await {
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            log::error!("Error reading message: {:?}", e);
+                            break;
+                        }
+                    };
 
-                match msg {
-                    Message::Text(text) => {
-                        let message: KiwiServerMessage = text.into();
-                        if let KiwiServerMessage::Unknown(msg) = message {
-                            event_tx.send(KiwiEvent::Message(msg)).await.unwrap();
+                    if let Some(writer) = capture.as_mut() {
+                        if let Err(e) = writer.write(Utc::now(), &msg) {
+                            log::error!("Error writing capture, stopping it: {:?}", e);
+                            capture = None;
                         }
                     }
-                    Message::Binary(bin) => {
-                        let code = String::from_utf8(bin[..3].to_vec()).unwrap();
-                        match code.as_str() {
-                            "SND" => {
-                                let data = bin[3..].to_vec();
-                                let _flags = data[0];
-                                let _seq = LittleEndian::read_u32(&data[1..5]);
-                                let smeter = BigEndian::read_u16(&data[5..7]);
-
-                                let rssi = 0.1 * smeter as f64 - 127.0;
-
-                                let data = data[7..].to_vec();
-                                event_tx
-                                    .send(KiwiEvent::SoundData { data, rssi })
-                                    .await
-                                    .unwrap();
-                            }
-                            "MSG" => {
-                                let str = match String::from_utf8(bin[4..].to_vec()) {
-                                    Ok(str) => str,
-                                    Err(e) => {
-                                        log::error!("Error decoding binary message: {:?}", e);
-                                        return;
-                                    }
-                                };
-
-                                match KiwiServerMessage::from(str) {
-                                    KiwiServerMessage::Unknown(msg) => {
-                                        event_tx.send(KiwiEvent::Message(msg)).await.unwrap();
-                                    }
-                                    KiwiServerMessage::AuthenticationResult(result) => {
-                                        if !result {
-                                            token.cancel();
-                                            event_tx
-                                                .send(KiwiEvent::Close(
-                                                    KiwiCloseReason::AuthenticationFailed,
-                                                ))
-                                                .await
-                                                .unwrap();
-                                        }
-                                    }
-                                    KiwiServerMessage::AudioInit(rate) => {
-                                        event_tx.send(KiwiEvent::Ready(rate)).await.unwrap();
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            _ => {
-                                let _str = match String::from_utf8(bin[4..].to_vec()) {
-                                    Ok(str) => str,
-                                    Err(e) => {
-                                        log::error!("Error decoding binary message: {:?}", e);
-                                        return;
-                                    }
-                                };
-                            }
+
+                    if let Some(event) = parse_message(msg) {
+                        let closed = matches!(event, KiwiEvent::Close(_));
+                        if closed {
+                            token.cancel();
+                        }
+                        event_tx.send(event).await.unwrap();
+                        if closed {
+                            return;
                         }
                     }
-                    Message::Close(_close) => {
-                        token.cancel();
-                        event_tx
-                            .send(KiwiEvent::Close(KiwiCloseReason::ServerClosed))
-                            .await
-                            .unwrap();
-                        return;
-                    }
-                    Message::Ping(_ping) => {
-                        event_tx.send(KiwiEvent::Ping).await.unwrap();
-                    }
-                    Message::Pong(pong) => {
-                        log::debug!("Received pong message: {:?}", pong);
-                    }
-                    _ => {}
                 }
-            });
+
+                // The connection dropped without a close frame
+                token.cancel();
+                let _ = event_tx
+                    .send(KiwiEvent::Close(KiwiCloseReason::ServerClosed))
+                    .await;
+            };
 
             tokio::select! {
                 _ = token.cancelled() => {
 
                 }
-                _ = read_loop => {
-                    panic!("Read loop ended");
-                }
+                _ = read_loop => {}
             };
         });
 
@@ -206,8 +273,11 @@ impl KiwiSDR {
                     if let Some(msg) = msg_rx.recv().await {
                         let msg: Message = msg.into();
                         log::debug!("Sending message: {:?}", msg);
-                        log::debug!("Message: {:?}", Message::from(msg.clone()));
-                        write.send(msg).await.unwrap();
+                        log::debug!("Message: {:?}", msg.clone());
+                        if let Err(e) = write.send(msg).await {
+                            log::error!("Error sending message: {:?}", e);
+                            return;
+                        }
                     }
                 }
             };
@@ -215,22 +285,25 @@ impl KiwiSDR {
             tokio::select! {
                 _ = token.cancelled() => {
                 }
-                _ = write_loop => {
-                    panic!("Write loop ended");
-                }
+                _ = write_loop => {}
             };
         });
 
         Ok(())
     }
 
+    /// Cancelled once the current connection closes, drops or is shut down.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+
     pub async fn read_event(&mut self, timeout: Duration) -> Option<KiwiEvent> {
-        if self.event_channel_rx.is_none() {
-            return None;
-        }
+        let rx = self.event_channel_rx.as_mut()?;
 
-        match tokio::time::timeout(timeout, self.event_channel_rx.as_mut().unwrap().recv()).await {
-            Ok(event) => event,
+        match tokio::time::timeout(timeout, rx.recv()).await {
+            Ok(Some(event)) => Some(event),
+            // The connection's reader is gone, so the connection is too
+            Ok(None) => Some(KiwiEvent::Close(KiwiCloseReason::ServerClosed)),
             Err(_) => None,
         }
     }
@@ -239,7 +312,7 @@ impl KiwiSDR {
         log::debug!("Sending message: {:?}", message);
         self.message_channel_tx
             .as_ref()
-            .unwrap()
+            .ok_or_else(|| anyhow::anyhow!("not connected"))?
             .send(message)
             .await?;
         Ok(())
@@ -1,65 +1,51 @@
-use std::sync::{
-    atomic::{AtomicU64, Ordering},
-    Arc,
-};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
+use chrono::Utc;
 use colored::Colorize;
 
-use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tokio_util::sync::CancellationToken;
 use url::Url;
 
 use crate::{
-    audio::Writer,
+    audio::{ima_adpcm::IMA_ADPCM_Decoder, render_template, AudioStream, WriterPool},
+    config::{SinkConfig, WaterfallConfig},
+    events::{EventBus, EventKind, StationState},
     sdr::{
         kiwi::{
-            event::{KiwiCloseReason, KiwiEvent},
+            capture::CaptureWriter,
+            event::{KiwiCloseReason, KiwiEvent, SpectrumRow, WaterfallEvent},
             message::KiwiClientMessage,
+            KiwiWaterfall, WaterfallSettings,
         },
-        scraper::{SDRScraper, ScraperStatus},
+        scraper::{SDRScraper, ScraperOutputs, ScraperStats, ScraperStatus},
         Tuning,
     },
+    spectrum::{spawn_sinks, Archive},
 };
 
 use super::KiwiSDR;
 
+const RECONNECT_DELAY: Duration = Duration::from_secs(4);
+
 #[derive(Clone)]
 pub struct KiwiSDRScraperSettings {
     pub name: String,
     pub endpoint: Url,
     pub password: Option<String>,
     pub station: Tuning,
+    // AGC is always on for now
+    #[allow(dead_code)]
     pub agc: bool,
     pub location: String,
     pub identity: String,
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct KiwiScraperStats {
-    name: String,
-    rssi: f64,
-}
-
-#[derive(Debug)]
-pub struct AtomicF64 {
-    storage: AtomicU64,
-}
-impl AtomicF64 {
-    pub fn new(value: f64) -> Self {
-        let as_u64 = value.to_bits();
-        Self {
-            storage: AtomicU64::new(as_u64),
-        }
-    }
-    pub fn store(&self, value: f64, ordering: Ordering) {
-        let as_u64 = value.to_bits();
-        self.storage.store(as_u64, ordering)
-    }
-    pub fn load(&self, ordering: Ordering) -> f64 {
-        let as_u64 = self.storage.load(ordering);
-        f64::from_bits(as_u64)
-    }
+    pub sinks: Vec<SinkConfig>,
+    /// File name template to tee received frames to, see `audio::render_template`.
+    pub capture: Option<String>,
+    pub waterfall: Option<WaterfallConfig>,
 }
 
 pub struct KiwiSDRScraper {
@@ -67,33 +53,135 @@ pub struct KiwiSDRScraper {
     sdr: Arc<Mutex<Box<KiwiSDR>>>,
     status: ScraperStatus,
     token: CancellationToken,
-    writer: Arc<Mutex<Writer>>,
-    rssi: Arc<AtomicF64>,
+    outputs: ScraperOutputs,
+    /// Whether the waterfall, if there is one, is connected.
+    waterfall: Arc<AtomicBool>,
 }
 
 impl KiwiSDRScraper {
-    pub fn new(settings: KiwiSDRScraperSettings) -> KiwiSDRScraper {
+    pub fn new(
+        settings: KiwiSDRScraperSettings,
+        pool: &WriterPool,
+        events: &EventBus,
+    ) -> KiwiSDRScraper {
         KiwiSDRScraper {
             settings: settings.clone(),
             sdr: Arc::new(Mutex::new(Box::new(KiwiSDR::new(settings.endpoint)))),
             status: ScraperStatus::Stopped,
             token: CancellationToken::new(),
-            writer: Arc::new(Mutex::new(Writer::new(
-                settings.name.clone(),
-                std::path::Path::new("./RECORD"),
-            ))),
-            rssi: Arc::new(AtomicF64::new(0.0)),
+            outputs: ScraperOutputs::new(
+                &settings.name,
+                settings.station.clone(),
+                &settings.sinks,
+                pool,
+                events,
+            ),
+            waterfall: Arc::new(AtomicBool::new(false)),
         }
     }
 }
 
-impl KiwiSDRScraper {}
+/// Opens a new capture file for a connection, if captures are enabled.
+fn open_capture(settings: &KiwiSDRScraperSettings, tuning: &Tuning) -> Option<CaptureWriter> {
+    let template = settings.capture.as_ref()?;
+    let opened = render_template(template, &settings.name, tuning, Utc::now()).and_then(|path| {
+        log::info!("{}: capturing to {}", settings.name.green(), path);
+        CaptureWriter::create(Path::new(&path))
+    });
+    match opened {
+        Ok(writer) => Some(writer),
+        Err(e) => {
+            log::error!("{}: failed to open capture: {}", settings.name.red(), e);
+            None
+        }
+    }
+}
+
+/// Feeds the Kiwi's waterfall to `rows`, reconnecting whenever it drops, until cancelled.
+async fn run_waterfall(
+    settings: KiwiSDRScraperSettings,
+    config: WaterfallConfig,
+    rows: mpsc::Sender<SpectrumRow>,
+    connected: Arc<AtomicBool>,
+    token: CancellationToken,
+) {
+    let waterfall_settings = WaterfallSettings {
+        zoom: config.zoom,
+        center_frequency: config
+            .center_frequency
+            .unwrap_or(settings.station.frequency()),
+        speed: config.speed,
+        min_db: config.min_db,
+        max_db: config.max_db,
+    };
+    let mut waterfall = KiwiWaterfall::new(settings.endpoint.clone());
+
+    let run = async {
+        loop {
+            match waterfall
+                .connect(settings.password.clone(), &waterfall_settings)
+                .await
+            {
+                Ok(_) => {
+                    log::info!("{}: waterfall connected", settings.name.green());
+                    connected.store(true, Ordering::Relaxed);
+                    let mut keepalive = tokio::time::interval(Duration::from_secs(5));
+                    loop {
+                        tokio::select! {
+                            event = waterfall.read_event(Duration::from_secs(1)) => match event {
+                                Some(WaterfallEvent::Row(row)) => {
+                                    // The sinks only go away when the scraper stops
+                                    let sent = rows.send(row).await;
+                                    if sent.is_err() {
+                                        return;
+                                    }
+                                }
+                                Some(WaterfallEvent::Close(reason)) => {
+                                    log::error!(
+                                        "{}: waterfall closed: {:?}",
+                                        settings.name.red(),
+                                        reason
+                                    );
+                                    break;
+                                }
+                                _ => {}
+                            },
+                            _ = keepalive.tick() => {
+                                if waterfall.send_message(KiwiClientMessage::KeepAlive).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    connected.store(false, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    log::error!(
+                        "{}: failed to connect waterfall: {}",
+                        settings.name.red(),
+                        e
+                    );
+                }
+            }
+
+            log::info!("{}: reconnecting waterfall in 4...", settings.name.yellow());
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    };
+
+    tokio::select! {
+        _ = run => {}
+        _ = token.cancelled() => {
+            log::debug!("{}: waterfall loop cancelled", settings.name.yellow());
+        }
+    }
+    let _ = waterfall.shutdown();
+    connected.store(false, Ordering::Relaxed);
+}
 
 #[async_trait::async_trait]
 impl SDRScraper for KiwiSDRScraper {
     async fn start(&mut self) -> anyhow::Result<()> {
-        self.token = CancellationToken::new();
-
         match self.status {
             ScraperStatus::Running => {
                 log::warn!("SDR for {} is already running", self.settings.name);
@@ -103,32 +191,83 @@ impl SDRScraper for KiwiSDRScraper {
         }
 
         log::debug!("starting scraper for {}", self.settings.name.green());
+        self.token = CancellationToken::new();
 
-        let sdr = self.sdr.clone();
-        let password = self.settings.password.clone();
-        tokio::spawn(async move {
-            let mut sdr = sdr.lock().await;
-            sdr.connect(password).await.unwrap();
-        });
+        if let Some(config) = self.settings.waterfall.clone() {
+            let archive = Archive::new(
+                self.settings.name.clone(),
+                self.outputs.tuning(),
+                config.clone(),
+                self.outputs.events.clone(),
+            );
+            let rows = spawn_sinks(&self.settings.name, vec![Box::new(archive)]);
+            tokio::spawn(run_waterfall(
+                self.settings.clone(),
+                config,
+                rows,
+                self.waterfall.clone(),
+                self.token.clone(),
+            ));
+        }
 
         let settings = self.settings.clone();
         let sdr = self.sdr.clone();
         let token = self.token.clone();
-        let rssi_clone = self.rssi.clone();
-        let writer_clone = self.writer.clone();
+        let outputs = self.outputs.clone();
         tokio::spawn(async move {
-            let writer = writer_clone;
-            let rssi = rssi_clone;
+            let mut decoder = IMA_ADPCM_Decoder::new();
+            let mut sample_rate = 12000;
             let event_loop = async {
                 log::debug!("spawned event thread for {}", settings.name.green());
+                let mut reconnecting = false;
                 loop {
-                    if let Some(event) = {
-                        // Make sure SDR instance lock is dropped immediately after fetching the latest message
-                        let mut sdr = sdr.lock().await;
-                        sdr.read_event(std::time::Duration::from_secs(1)).await
-                    } {
+                    outputs.state.set(StationState::Connecting);
+                    let capture = open_capture(&settings, &outputs.tuning());
+                    let connected = sdr
+                        .lock()
+                        .await
+                        .connect(settings.password.clone(), capture)
+                        .await;
+                    if let Err(e) = connected {
+                        log::error!("{}: failed to connect: {}", settings.name.red(), e);
+                        if reconnecting {
+                            outputs.publish(EventKind::ReconnectFailed {
+                                error: e.to_string(),
+                            });
+                        } else {
+                            outputs.publish(EventKind::Disconnected {
+                                reason: e.to_string(),
+                            });
+                        }
+                        outputs.state.set(StationState::Disconnected);
+                        reconnecting = true;
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                        continue;
+                    }
+
+                    if reconnecting {
+                        log::info!("{}: reconnected", settings.name.green());
+                        outputs.publish(EventKind::Reconnected);
+                    }
+                    outputs.state.set(StationState::Connected);
+
+                    loop {
+                        let event = {
+                            // Make sure SDR instance lock is dropped immediately after fetching the latest message
+                            let mut sdr = sdr.lock().await;
+                            sdr.read_event(std::time::Duration::from_secs(1)).await
+                        };
+                        let Some(event) = event else {
+                            continue;
+                        };
+
                         match event {
                             KiwiEvent::Close(reason) => {
+                                outputs.publish(EventKind::Disconnected {
+                                    reason: format!("{:?}", reason),
+                                });
+                                outputs.state.set(StationState::Disconnected);
+
                                 match reason {
                                     KiwiCloseReason::ServerClosed => {
                                         log::error!(
@@ -144,27 +283,13 @@ impl SDRScraper for KiwiSDRScraper {
                                     }
                                 }
 
-                                writer.lock().await.close();
-
-                                log::info!("{}: reconnecting in 4...", settings.name.yellow());
-                                tokio::time::sleep(std::time::Duration::from_secs(4)).await;
-
-                                match sdr.lock().await.connect(settings.password.clone()).await {
-                                    Ok(_) => {
-                                        log::info!("{}: reconnected", settings.name.green());
-                                    }
-                                    Err(e) => {
-                                        log::error!(
-                                            "{}: failed to reconnect: {}",
-                                            settings.name.red(),
-                                            e
-                                        );
-                                    }
-                                };
+                                break;
                             }
                             KiwiEvent::Ready(rate) => {
                                 log::info!("{} is ready at {} Hz", settings.name.green(), rate);
-                                writer.lock().await.set_sample_rate(rate);
+                                outputs.state.set(StationState::Ready);
+                                sample_rate = rate;
+                                decoder = IMA_ADPCM_Decoder::new();
 
                                 {
                                     let sdr = sdr.lock().await;
@@ -192,11 +317,9 @@ impl SDRScraper for KiwiSDRScraper {
                                     .await
                                     .unwrap();
 
-                                    sdr.send_message(KiwiClientMessage::Tune(
-                                        settings.station.clone(),
-                                    ))
-                                    .await
-                                    .unwrap();
+                                    sdr.send_message(KiwiClientMessage::Tune(outputs.tuning()))
+                                        .await
+                                        .unwrap();
 
                                     sdr.send_message(KiwiClientMessage::SetIdentity(
                                         settings.identity.clone(),
@@ -226,7 +349,8 @@ impl SDRScraper for KiwiSDRScraper {
                                         .unwrap();
                                 }
 
-                                // Start keepalive loop
+                                // Start keepalive loop, for this connection only
+                                let connection = sdr.lock().await.cancellation_token();
                                 let sdr = sdr.clone();
                                 let token = token.clone();
                                 let settings = settings.clone();
@@ -235,11 +359,15 @@ impl SDRScraper for KiwiSDRScraper {
                                         loop {
                                             tokio::time::sleep(std::time::Duration::from_secs(5))
                                                 .await;
-                                            sdr.lock()
+                                            if sdr
+                                                .lock()
                                                 .await
                                                 .send_message(KiwiClientMessage::KeepAlive)
                                                 .await
-                                                .unwrap();
+                                                .is_err()
+                                            {
+                                                break;
+                                            }
                                         }
                                     };
 
@@ -248,6 +376,9 @@ impl SDRScraper for KiwiSDRScraper {
                                         _ = token.cancelled() => {
                                             log::debug!("{}: keepalive loop cancelled", settings.name.yellow());
                                         }
+                                        _ = connection.cancelled() => {
+                                            log::debug!("{}: connection gone, stopping keepalive loop", settings.name.yellow());
+                                        }
                                     };
                                 });
                             }
@@ -260,10 +391,17 @@ impl SDRScraper for KiwiSDRScraper {
                                     settings.name.blue(),
                                     data.len()
                                 );
-                                rssi.store(the_rssi, Ordering::Relaxed);
-                                writer.lock().await.write_samples(&data)
+                                outputs.set_rssi(the_rssi);
+
+                                let mut samples = vec![0; data.len() * 2];
+                                decoder.decode_block(&data, &mut samples);
+
+                                outputs.write(samples, sample_rate).await;
                             }
                             KiwiEvent::Message(msg) => {
+                                outputs.publish(EventKind::Message {
+                                    message: msg.clone(),
+                                });
                                 log::debug!(
                                     "{}: {}",
                                     settings.name.blue(),
@@ -277,6 +415,11 @@ impl SDRScraper for KiwiSDRScraper {
                             _ => {}
                         }
                     }
+
+                    outputs.writer.close();
+                    reconnecting = true;
+                    log::info!("{}: reconnecting in 4...", settings.name.yellow());
+                    tokio::time::sleep(RECONNECT_DELAY).await;
                 }
             };
 
@@ -301,8 +444,24 @@ impl SDRScraper for KiwiSDRScraper {
         let sdr = self.sdr.lock().await;
 
         sdr.shutdown()?;
+        self.outputs.writer.close();
         self.status = ScraperStatus::Stopped;
+        self.outputs.state.set(StationState::Stopped);
+
+        Ok(())
+    }
+
+    async fn tune(&mut self, tuning: Tuning) -> anyhow::Result<()> {
+        log::info!("{}: tuning to {}", self.settings.name.green(), tuning);
+        self.outputs.set_tuning(tuning.clone());
 
+        if self.outputs.state.get().0 == StationState::Ready {
+            self.sdr
+                .lock()
+                .await
+                .send_message(KiwiClientMessage::Tune(tuning))
+                .await?;
+        }
         Ok(())
     }
 
@@ -314,10 +473,19 @@ impl SDRScraper for KiwiSDRScraper {
         &self.settings.name
     }
 
-    fn get_stats(&self) -> KiwiScraperStats {
-        KiwiScraperStats {
-            rssi: self.rssi.load(Ordering::Relaxed) as f64,
-            name: self.settings.name.clone(),
-        }
+    fn get_stats(&self) -> ScraperStats {
+        self.outputs.stats(self.status.clone())
+    }
+
+    fn audio(&self) -> AudioStream {
+        self.outputs.audio.clone()
+    }
+
+    fn connections(&self) -> usize {
+        let audio = matches!(
+            self.outputs.state.get().0,
+            StationState::Connected | StationState::Ready
+        );
+        audio as usize + self.waterfall.load(Ordering::Relaxed) as usize
     }
 }
@@ -0,0 +1,593 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use colored::Colorize;
+use tokio::sync::{Mutex, Notify};
+use url::Url;
+
+use super::KiwiStatus;
+use crate::{
+    audio::AudioStream,
+    events::{EventBus, EventKind, StationState},
+    sdr::{SDRScraper, ScraperStats, ScraperStatus, Tuning},
+    spectrum::SurveyTable,
+};
+
+/// How often each Kiwi's `/status` is read for its channel count and other users.
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SlotState {
+    Stopped,
+    /// Started, but not given its channels yet, or had them taken away.
+    Waiting,
+    Running,
+}
+
+/// A station's claim on a Kiwi's channels, shared by its `SlottedScraper` and the allocator.
+struct Slot {
+    name: String,
+    priority: i32,
+    /// Channels the station takes while running.
+    channels: usize,
+    scraper: Mutex<Box<dyn SDRScraper>>,
+    state: std::sync::Mutex<SlotState>,
+    /// Whether the station has been given its channels since it was added.
+    granted: AtomicBool,
+}
+
+impl Slot {
+    fn state(&self) -> SlotState {
+        *self.state.lock().unwrap()
+    }
+
+    fn set_state(&self, state: SlotState) {
+        *self.state.lock().unwrap() = state;
+    }
+}
+
+/// A Kiwi and the stations that use it.
+struct Host {
+    endpoint: Url,
+    /// Channels the Kiwi has, once its `/status` has been read. Until then, there's no limit:
+    /// a Kiwi whose `/status` can't be read still gets all its stations started, and turns away
+    /// those it has no room for as busy, as it did before there was an allocator.
+    channels: Option<usize>,
+    /// Channels taken by other listeners.
+    others: usize,
+    slots: Vec<Arc<Slot>>,
+}
+
+/// Reads a Kiwi's `/status`, logging what went wrong if it can't.
+async fn read_status(key: &str, endpoint: &Url) -> Option<KiwiStatus> {
+    match super::status(endpoint).await {
+        Ok(status) => Some(status),
+        Err(e) => {
+            log::error!("{}: error reading status: {}", key.red(), e);
+            None
+        }
+    }
+}
+
+impl Host {
+    async fn refresh(&mut self, key: &str, status: KiwiStatus) {
+        if status.users_max.is_some() && status.users_max != self.channels {
+            log::info!(
+                "{} has {} channels",
+                key.green(),
+                status.users_max.unwrap_or_default()
+            );
+        }
+        self.channels = status.users_max.or(self.channels);
+        // What our stations have open, rather than what they may take: a trigger mostly has
+        // just its waterfall up, and a station still connecting has nothing
+        let mut ours = 0;
+        for slot in &self.slots {
+            if slot.state() == SlotState::Running {
+                ours += slot.scraper.lock().await.connections();
+            }
+        }
+        self.others = status.users.unwrap_or_default().saturating_sub(ours);
+    }
+
+    /// Gives the free channels to the waiting and running stations, highest priority first,
+    /// then stops those left out and starts those let in.
+    async fn rebalance(&mut self, events: &EventBus) {
+        let mut free = self
+            .channels
+            .map_or(usize::MAX, |channels| channels.saturating_sub(self.others));
+        let mut wanted = self
+            .slots
+            .iter()
+            .filter(|slot| slot.state() != SlotState::Stopped)
+            .collect::<Vec<&Arc<Slot>>>();
+        // Stable, so stations of the same priority go in the order they were configured
+        wanted.sort_by_key(|slot| Reverse(slot.priority));
+        let mut granted = Vec::new();
+        for slot in wanted {
+            if slot.channels <= free {
+                free -= slot.channels;
+                granted.push(slot.clone());
+            }
+        }
+
+        for slot in &self.slots {
+            if slot.state() != SlotState::Running
+                || granted.iter().any(|other| Arc::ptr_eq(other, slot))
+            {
+                continue;
+            }
+            log::warn!(
+                "{}: pre-empted by higher priority stations",
+                slot.name.yellow()
+            );
+            if let Err(e) = slot.scraper.lock().await.stop().await {
+                log::error!("{}: error stopping: {}", slot.name.red(), e);
+            }
+            slot.set_state(SlotState::Waiting);
+            events.publish(
+                &slot.name,
+                EventKind::StateChanged {
+                    state: StationState::Waiting,
+                },
+            );
+        }
+
+        for slot in granted {
+            if slot.state() != SlotState::Waiting {
+                continue;
+            }
+            match slot.scraper.lock().await.start().await {
+                Ok(()) => {
+                    slot.set_state(SlotState::Running);
+                    slot.granted.store(true, Ordering::Relaxed);
+                }
+                Err(e) => log::error!("{}: error starting: {}", slot.name.red(), e),
+            }
+        }
+    }
+}
+
+/// Shares out the channels of each Kiwi among the stations using it. A Kiwi has only a few,
+/// read from its `/status`, and connections beyond that are turned away as busy.
+///
+/// Stations go through a `SlottedScraper`. Starting one only asks for its channels; it's
+/// really started once they're free, highest priority first, and stopped again if a station of
+/// higher priority needs them.
+pub struct SlotAllocator {
+    hosts: Mutex<HashMap<String, Host>>,
+    changed: Notify,
+    events: EventBus,
+}
+
+impl SlotAllocator {
+    pub fn new(events: &EventBus) -> Arc<SlotAllocator> {
+        Arc::new(SlotAllocator {
+            hosts: Mutex::new(HashMap::new()),
+            changed: Notify::new(),
+            events: events.clone(),
+        })
+    }
+
+    /// Puts `scraper`, which takes `channels` of the Kiwi at `endpoint`, under the allocator.
+    pub async fn add(
+        self: &Arc<Self>,
+        endpoint: &Url,
+        priority: i32,
+        channels: usize,
+        scraper: Box<dyn SDRScraper>,
+    ) -> SlottedScraper {
+        let key = format!(
+            "{}:{}",
+            endpoint.host_str().unwrap_or_default(),
+            endpoint.port_or_known_default().unwrap_or_default()
+        );
+        let stats = scraper.get_stats();
+        let audio = scraper.audio();
+        let survey = scraper.survey();
+        let slot = Arc::new(Slot {
+            name: scraper.name().to_string(),
+            priority,
+            channels,
+            scraper: Mutex::new(scraper),
+            state: std::sync::Mutex::new(SlotState::Stopped),
+            granted: AtomicBool::new(false),
+        });
+
+        self.hosts
+            .lock()
+            .await
+            .entry(key)
+            .or_insert_with(|| Host {
+                endpoint: endpoint.clone(),
+                channels: None,
+                others: 0,
+                slots: Vec::new(),
+            })
+            .slots
+            .push(slot.clone());
+
+        SlottedScraper {
+            slot,
+            allocator: self.clone(),
+            stats: std::sync::Mutex::new(stats),
+            audio,
+            survey,
+        }
+    }
+
+    /// Gives up a stopped station's claim for good.
+    pub async fn remove(&self, name: &str) {
+        for host in self.hosts.lock().await.values_mut() {
+            host.slots
+                .retain(|slot| slot.name != name || slot.state() != SlotState::Stopped);
+        }
+    }
+
+    /// Hands out channels whenever stations start or stop, and keeps track of how many each
+    /// Kiwi has free.
+    pub async fn run(self: Arc<Self>) {
+        let mut refresh = tokio::time::interval(STATUS_INTERVAL);
+        loop {
+            let refreshing = tokio::select! {
+                _ = refresh.tick() => true,
+                _ = self.changed.notified() => false,
+            };
+            // Kiwis whose channel count isn't known yet are asked again every time round
+            let stale = self
+                .hosts
+                .lock()
+                .await
+                .iter()
+                .filter(|(_, host)| refreshing || host.channels.is_none())
+                .map(|(key, host)| (key.clone(), host.endpoint.clone()))
+                .collect::<Vec<(String, Url)>>();
+            // Read without the lock, so that a slow Kiwi doesn't hold up stopping stations
+            let mut statuses = Vec::new();
+            for (key, endpoint) in stale {
+                if let Some(status) = read_status(&key, &endpoint).await {
+                    statuses.push((key, status));
+                }
+            }
+
+            let mut hosts = self.hosts.lock().await;
+            for (key, status) in statuses {
+                if let Some(host) = hosts.get_mut(&key) {
+                    host.refresh(&key, status).await;
+                }
+            }
+            for host in hosts.values_mut() {
+                host.rebalance(&self.events).await;
+            }
+        }
+    }
+}
+
+/// A station that only runs while the allocator lets it have its channels. It counts as
+/// running from when it's started, even while waiting.
+pub struct SlottedScraper {
+    slot: Arc<Slot>,
+    allocator: Arc<SlotAllocator>,
+    /// The last stats read, for when the scraper is busy being started or stopped.
+    stats: std::sync::Mutex<ScraperStats>,
+    audio: AudioStream,
+    survey: Option<SurveyTable>,
+}
+
+impl SlottedScraper {
+    /// Whether the station was ever given its channels, rather than left waiting all along.
+    pub fn granted(&self) -> bool {
+        self.slot.granted.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait::async_trait]
+impl SDRScraper for SlottedScraper {
+    async fn start(&mut self) -> anyhow::Result<()> {
+        if self.slot.state() == SlotState::Stopped {
+            self.slot.set_state(SlotState::Waiting);
+            self.allocator.events.publish(
+                &self.slot.name,
+                EventKind::StateChanged {
+                    state: StationState::Waiting,
+                },
+            );
+            self.allocator.changed.notify_one();
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        // Not while the allocator is handing out channels
+        let _hosts = self.allocator.hosts.lock().await;
+        let state = self.slot.state();
+        self.slot.set_state(SlotState::Stopped);
+        let result = match state {
+            SlotState::Running => self.slot.scraper.lock().await.stop().await,
+            _ => Ok(()),
+        };
+        self.allocator.changed.notify_one();
+        result
+    }
+
+    async fn tune(&mut self, tuning: Tuning) -> anyhow::Result<()> {
+        self.slot.scraper.lock().await.tune(tuning).await
+    }
+
+    fn status(&self) -> ScraperStatus {
+        match self.slot.state() {
+            SlotState::Stopped => ScraperStatus::Stopped,
+            SlotState::Waiting | SlotState::Running => ScraperStatus::Running,
+        }
+    }
+
+    fn name(&self) -> &str {
+        &self.slot.name
+    }
+
+    fn get_stats(&self) -> ScraperStats {
+        let mut stats = self.stats.lock().unwrap();
+        if let Ok(scraper) = self.slot.scraper.try_lock() {
+            *stats = scraper.get_stats();
+        }
+        let mut stats = stats.clone();
+        stats.status = self.status();
+        if self.slot.state() == SlotState::Waiting {
+            stats.state = StationState::Waiting;
+        }
+        stats
+    }
+
+    fn audio(&self) -> AudioStream {
+        self.audio.clone()
+    }
+
+    fn survey(&self) -> Option<SurveyTable> {
+        self.survey.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    /// Keeps track of whether the allocator has it running.
+    struct Stub {
+        name: String,
+        running: Arc<AtomicBool>,
+        /// Connections it has open while running.
+        connections: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl SDRScraper for Stub {
+        async fn start(&mut self) -> anyhow::Result<()> {
+            self.running.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+
+        async fn stop(&mut self) -> anyhow::Result<()> {
+            self.running.store(false, Ordering::Relaxed);
+            Ok(())
+        }
+
+        async fn tune(&mut self, _tuning: Tuning) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn status(&self) -> ScraperStatus {
+            if self.running.load(Ordering::Relaxed) {
+                ScraperStatus::Running
+            } else {
+                ScraperStatus::Stopped
+            }
+        }
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn get_stats(&self) -> ScraperStats {
+            ScraperStats {
+                name: self.name.clone(),
+                status: self.status(),
+                state: StationState::Stopped,
+                since: Utc::now(),
+                tuning: Tuning::AM {
+                    bandwidth: 10000,
+                    frequency: 7.2e6,
+                },
+                rssi: 0.0,
+                queue_depth: 0,
+                queue_overflows: 0,
+            }
+        }
+
+        fn audio(&self) -> AudioStream {
+            AudioStream::new()
+        }
+
+        fn connections(&self) -> usize {
+            if self.running.load(Ordering::Relaxed) {
+                self.connections
+            } else {
+                0
+            }
+        }
+    }
+
+    /// A station that has been started, waiting for its channels.
+    fn waiting(name: &str, priority: i32, channels: usize) -> (Arc<Slot>, Arc<AtomicBool>) {
+        partly_connected(name, priority, channels, channels)
+    }
+
+    /// A station that, once running, has only `connections` of its `channels` open.
+    fn partly_connected(
+        name: &str,
+        priority: i32,
+        channels: usize,
+        connections: usize,
+    ) -> (Arc<Slot>, Arc<AtomicBool>) {
+        let running = Arc::new(AtomicBool::new(false));
+        let slot = Arc::new(Slot {
+            name: name.to_string(),
+            priority,
+            channels,
+            scraper: Mutex::new(Box::new(Stub {
+                name: name.to_string(),
+                running: running.clone(),
+                connections,
+            })),
+            state: std::sync::Mutex::new(SlotState::Waiting),
+            granted: AtomicBool::new(false),
+        });
+        (slot, running)
+    }
+
+    fn host(channels: Option<usize>, slots: &[&Arc<Slot>]) -> Host {
+        Host {
+            endpoint: Url::parse("http://kiwi.local:8073").unwrap(),
+            channels,
+            others: 0,
+            slots: slots.iter().map(|&slot| slot.clone()).collect(),
+        }
+    }
+
+    fn status(users: usize, users_max: usize) -> KiwiStatus {
+        KiwiStatus {
+            users: Some(users),
+            users_max: Some(users_max),
+            ..KiwiStatus::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn more_stations_than_channels() {
+        let events = EventBus::new();
+        let (a, a_running) = waiting("a", 0, 1);
+        let (b, b_running) = waiting("b", 0, 1);
+        let (c, c_running) = waiting("c", 1, 1);
+        let mut host = host(Some(2), &[&a, &b, &c]);
+        host.rebalance(&events).await;
+
+        // The higher priority first, then in the order they were added
+        assert_eq!(
+            [a.state(), b.state(), c.state()],
+            [SlotState::Running, SlotState::Waiting, SlotState::Running]
+        );
+        assert!(a_running.load(Ordering::Relaxed) && c_running.load(Ordering::Relaxed));
+        assert!(!b_running.load(Ordering::Relaxed));
+        assert!(a.granted.load(Ordering::Relaxed) && !b.granted.load(Ordering::Relaxed));
+
+        // Still no room on the next round
+        host.rebalance(&events).await;
+        assert_eq!(b.state(), SlotState::Waiting);
+    }
+
+    #[tokio::test]
+    async fn jobs_preempt_stations_until_they_stop() {
+        let events = EventBus::new();
+        let (station, station_running) = waiting("station", 0, 1);
+        let (other, _) = waiting("other", 0, 1);
+        let mut host = host(Some(2), &[&station, &other]);
+        host.rebalance(&events).await;
+        assert_eq!(station.state(), SlotState::Running);
+
+        let (job, job_running) = waiting("job", 10, 2);
+        host.slots.push(job.clone());
+        host.rebalance(&events).await;
+        assert_eq!(job.state(), SlotState::Running);
+        assert!(job_running.load(Ordering::Relaxed));
+        for slot in [&station, &other] {
+            assert_eq!(slot.state(), SlotState::Waiting);
+        }
+        assert!(!station_running.load(Ordering::Relaxed));
+
+        // Stopped the way SlottedScraper::stop does
+        job.set_state(SlotState::Stopped);
+        job.scraper.lock().await.stop().await.unwrap();
+        host.rebalance(&events).await;
+        for slot in [&station, &other] {
+            assert_eq!(slot.state(), SlotState::Running);
+        }
+        assert!(station_running.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn others_leave_out_our_running_stations() {
+        let events = EventBus::new();
+        let (a, _) = waiting("a", 0, 1);
+        let (b, _) = waiting("b", 0, 1);
+        let mut host = host(Some(4), &[&a, &b]);
+        host.rebalance(&events).await;
+
+        // Three in use, two of them ours
+        host.refresh("kiwi.local:8073", status(3, 4)).await;
+        assert_eq!(host.others, 1);
+        assert_eq!(host.channels, Some(4));
+
+        // One channel is left, not enough for two
+        let (c, _) = waiting("c", 0, 2);
+        host.slots.push(c.clone());
+        host.rebalance(&events).await;
+        assert_eq!(c.state(), SlotState::Waiting);
+
+        // Once the other listener goes, it fits
+        host.refresh("kiwi.local:8073", status(2, 4)).await;
+        assert_eq!(host.others, 0);
+        host.rebalance(&events).await;
+        assert_eq!(c.state(), SlotState::Running);
+
+        // A status without a channel count keeps the last one
+        host.refresh("kiwi.local:8073", KiwiStatus::default()).await;
+        assert_eq!((host.channels, host.others), (Some(4), 0));
+    }
+
+    #[tokio::test]
+    async fn starts_everything_while_the_status_is_unknown() {
+        let events = EventBus::new();
+        let slots = (0..5)
+            .map(|i| waiting(&format!("station{}", i), 0, 1).0)
+            .collect::<Vec<Arc<Slot>>>();
+        let mut host = host(None, &slots.iter().collect::<Vec<&Arc<Slot>>>());
+        host.rebalance(&events).await;
+        assert!(slots.iter().all(|slot| slot.state() == SlotState::Running));
+
+        // Once known, the channels are shared out from the next round. The Kiwi turned one of
+        // ours away, so it counts no one else.
+        host.refresh("kiwi.local:8073", status(4, 4)).await;
+        assert_eq!(host.others, 0);
+        host.rebalance(&events).await;
+        let running = slots
+            .iter()
+            .filter(|slot| slot.state() == SlotState::Running)
+            .count();
+        assert_eq!(running, 4);
+    }
+
+    #[tokio::test]
+    async fn others_leave_out_only_connections_we_have_open() {
+        let events = EventBus::new();
+        // A trigger that may take its waterfall and three channels, recording on one of them,
+        // and a station that hasn't got through yet
+        let (trigger, _) = partly_connected("trigger", 0, 4, 2);
+        let (connecting, _) = partly_connected("connecting", 0, 1, 0);
+        let mut host = host(Some(8), &[&trigger, &connecting]);
+        host.rebalance(&events).await;
+
+        // Five in use, two of them ours
+        host.refresh("kiwi.local:8073", status(5, 8)).await;
+        assert_eq!(host.others, 3);
+
+        // The trigger's channels stay reserved, so there's no room left
+        let (c, _) = waiting("c", 0, 1);
+        host.slots.push(c.clone());
+        host.rebalance(&events).await;
+        assert_eq!(c.state(), SlotState::Waiting);
+    }
+}
@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use colored::Colorize;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::{
+    audio::{AudioStream, WriterPool},
+    config::SurveyConfig,
+    events::{EventBus, EventKind, StationState},
+    sdr::{
+        kiwi::{
+            event::{SpectrumRow, WaterfallEvent},
+            message::KiwiClientMessage,
+            waterfall::ZOOM_MAX,
+            KiwiWaterfall, WaterfallSettings,
+        },
+        scraper::{SDRScraper, ScraperOutputs, ScraperStats, ScraperStatus},
+        Tuning,
+    },
+    spectrum::{spawn_sinks, Survey, SurveyTable},
+};
+
+/// The Kiwi's display range. The survey works from the dBm in the lines either way.
+const MIN_DB: i32 = -110;
+const MAX_DB: i32 = -10;
+
+/// How long a step may go without a line before the sweep starts over from the bottom.
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct KiwiSurveyScraperSettings {
+    pub name: String,
+    pub endpoint: Url,
+    pub password: Option<String>,
+    pub survey: SurveyConfig,
+}
+
+/// Sweeps a range of a Kiwi with its waterfall, a step of the range at a time, and hands the
+/// lines to a `spectrum::Survey`. Takes no audio.
+pub struct KiwiSurveyScraper {
+    settings: KiwiSurveyScraperSettings,
+    status: ScraperStatus,
+    token: CancellationToken,
+    outputs: ScraperOutputs,
+    table: SurveyTable,
+}
+
+impl KiwiSurveyScraper {
+    pub fn new(
+        settings: KiwiSurveyScraperSettings,
+        pool: &WriterPool,
+        events: &EventBus,
+    ) -> KiwiSurveyScraper {
+        let tuning = step_tuning(&settings.survey, settings.survey.start, 30e6);
+        KiwiSurveyScraper {
+            outputs: ScraperOutputs::new(&settings.name, tuning, &[], pool, events),
+            settings,
+            status: ScraperStatus::Stopped,
+            token: CancellationToken::new(),
+            table: SurveyTable::default(),
+        }
+    }
+}
+
+/// Makes sure the range can be split into channels and swept at the configured zoom.
+fn check_survey(config: &SurveyConfig) -> anyhow::Result<()> {
+    if config.end <= config.start {
+        anyhow::bail!("survey range ends before it starts");
+    }
+    if config.channel_width <= 0.0 || config.channel_width.is_nan() {
+        anyhow::bail!(
+            "channel width must be above 0, not {}",
+            config.channel_width
+        );
+    }
+    if config.zoom as u32 > ZOOM_MAX {
+        anyhow::bail!(
+            "zoom {} is deeper than the Kiwi's {}",
+            config.zoom,
+            ZOOM_MAX
+        );
+    }
+    Ok(())
+}
+
+/// The step starting at `position`, shown in the stats as AM over the step's width.
+fn step_tuning(config: &SurveyConfig, position: f64, bandwidth: f64) -> Tuning {
+    let span = bandwidth / 2f64.powi(config.zoom as i32);
+    Tuning::AM {
+        bandwidth: span as i32,
+        frequency: position + span / 2.0,
+    }
+}
+
+/// Steps the waterfall across the range, feeding the lines of each step to `rows` for `dwell`,
+/// and reconnecting whenever it drops, until cancelled.
+async fn run_survey(
+    settings: KiwiSurveyScraperSettings,
+    outputs: ScraperOutputs,
+    rows: mpsc::Sender<SpectrumRow>,
+    token: CancellationToken,
+) {
+    let config = settings.survey.clone();
+    let mut waterfall = KiwiWaterfall::new(settings.endpoint.clone());
+    // Lower edge of the step, moved up to where the last step's lines ended
+    let mut position = config.start;
+
+    let run = async {
+        loop {
+            outputs.state.set(StationState::Connecting);
+            let mut tuning = step_tuning(&config, position, 30e6);
+            let waterfall_settings = WaterfallSettings {
+                zoom: config.zoom,
+                center_frequency: tuning.frequency(),
+                speed: config.speed,
+                min_db: MIN_DB,
+                max_db: MAX_DB,
+            };
+            match waterfall
+                .connect(settings.password.clone(), &waterfall_settings)
+                .await
+            {
+                Ok(_) => {
+                    log::info!("{}: survey connected", settings.name.green());
+                    outputs.state.set(StationState::Ready);
+                    let mut keepalive = tokio::time::interval(Duration::from_secs(5));
+                    let mut stepped = Instant::now();
+                    let mut dwell_until: Option<Instant> = None;
+                    let mut bandwidth = 30e6;
+                    loop {
+                        tokio::select! {
+                            event = waterfall.read_event(Duration::from_secs(1)) => {
+                                match event {
+                                    Some(WaterfallEvent::Row(row)) => {
+                                        // Lines from before the last step are still coming in
+                                        let span = row.bins.len() as f64 * row.bin_width;
+                                        let end = row.start_frequency + span;
+                                        bandwidth = span * 2f64.powi(config.zoom as i32);
+                                        let center = tuning.frequency();
+                                        if center < row.start_frequency || center >= end {
+                                            if stepped.elapsed() > STEP_TIMEOUT {
+                                                log::warn!(
+                                                    "{}: no lines around {} Hz, starting the sweep over",
+                                                    settings.name.yellow(),
+                                                    center
+                                                );
+                                                position = config.start;
+                                            } else {
+                                                continue;
+                                            }
+                                        } else {
+                                            let until = *dwell_until.get_or_insert(Instant::now() + config.dwell);
+                                            let sent = rows.send(row).await;
+                                            if sent.is_err() {
+                                                return;
+                                            }
+                                            if Instant::now() < until {
+                                                continue;
+                                            }
+                                            // Start over once past the range or the top of the Kiwi's band
+                                            position = if end >= config.end || end >= bandwidth - 1.0 {
+                                                config.start
+                                            } else {
+                                                end
+                                            };
+                                        }
+                                    }
+                                    Some(WaterfallEvent::Close(reason)) => {
+                                        log::error!(
+                                            "{}: survey waterfall closed: {:?}",
+                                            settings.name.red(),
+                                            reason
+                                        );
+                                        outputs.publish(EventKind::Disconnected {
+                                            reason: format!("{:?}", reason),
+                                        });
+                                        break;
+                                    }
+                                    _ => {
+                                        // No lines at all since the step, or since the dwell ended
+                                        if dwell_until.unwrap_or(stepped).elapsed() <= STEP_TIMEOUT {
+                                            continue;
+                                        }
+                                        log::warn!(
+                                            "{}: no lines around {} Hz, starting the sweep over",
+                                            settings.name.yellow(),
+                                            tuning.frequency()
+                                        );
+                                        position = config.start;
+                                    }
+                                }
+
+                                tuning = step_tuning(&config, position, bandwidth);
+                                outputs.set_tuning(tuning.clone());
+                                stepped = Instant::now();
+                                dwell_until = None;
+                                if waterfall
+                                    .send_message(KiwiClientMessage::SetZoom {
+                                        zoom: config.zoom,
+                                        center: tuning.frequency(),
+                                    })
+                                    .await
+                                    .is_err()
+                                {
+                                    break;
+                                }
+                            }
+                            _ = keepalive.tick() => {
+                                if waterfall.send_message(KiwiClientMessage::KeepAlive).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::error!("{}: failed to connect survey: {}", settings.name.red(), e);
+                    outputs.publish(EventKind::ReconnectFailed {
+                        error: e.to_string(),
+                    });
+                }
+            }
+
+            outputs.state.set(StationState::Disconnected);
+            log::info!("{}: reconnecting survey in 4...", settings.name.yellow());
+            tokio::time::sleep(Duration::from_secs(4)).await;
+        }
+    };
+
+    tokio::select! {
+        _ = run => {}
+        _ = token.cancelled() => {
+            log::debug!("{}: survey loop cancelled", settings.name.yellow());
+        }
+    }
+    let _ = waterfall.shutdown();
+}
+
+#[async_trait::async_trait]
+impl SDRScraper for KiwiSurveyScraper {
+    async fn start(&mut self) -> anyhow::Result<()> {
+        if self.status == ScraperStatus::Running {
+            log::warn!("SDR for {} is already running", self.settings.name);
+            return Ok(());
+        }
+        check_survey(&self.settings.survey)?;
+
+        log::debug!("starting survey for {}", self.settings.name.green());
+        self.token = CancellationToken::new();
+        let survey = Survey::new(self.settings.survey.clone(), self.table.clone());
+        let rows = spawn_sinks(&self.settings.name, vec![Box::new(survey)]);
+        tokio::spawn(run_survey(
+            self.settings.clone(),
+            self.outputs.clone(),
+            rows,
+            self.token.clone(),
+        ));
+
+        self.status = ScraperStatus::Running;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        log::debug!("Stopping survey for {}", self.settings.name.green());
+
+        self.token.cancel();
+        self.status = ScraperStatus::Stopped;
+        self.outputs.state.set(StationState::Stopped);
+
+        Ok(())
+    }
+
+    async fn tune(&mut self, _tuning: Tuning) -> anyhow::Result<()> {
+        anyhow::bail!("surveys sweep their configured range and can't be tuned")
+    }
+
+    fn status(&self) -> ScraperStatus {
+        self.status.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    fn get_stats(&self) -> ScraperStats {
+        self.outputs.stats(self.status.clone())
+    }
+
+    fn audio(&self) -> AudioStream {
+        self.outputs.audio.clone()
+    }
+
+    fn survey(&self) -> Option<SurveyTable> {
+        Some(self.table.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unsweepable_surveys() {
+        let config = |start, end, channel_width, zoom| SurveyConfig {
+            start,
+            end,
+            channel_width,
+            zoom,
+            ..SurveyConfig::default()
+        };
+        assert!(check_survey(&config(7.0e6, 7.3e6, 5000.0, 14)).is_ok());
+        assert!(check_survey(&config(7.3e6, 7.0e6, 5000.0, 6)).is_err());
+        assert!(check_survey(&config(7.0e6, 7.3e6, 0.0, 6)).is_err());
+        assert!(check_survey(&config(7.0e6, 7.3e6, -5000.0, 6)).is_err());
+        assert!(check_survey(&config(7.0e6, 7.3e6, f64::NAN, 6)).is_err());
+        assert!(check_survey(&config(7.0e6, 7.3e6, 5000.0, 15)).is_err());
+    }
+}
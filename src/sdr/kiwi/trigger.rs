@@ -0,0 +1,598 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use serde::Serialize;
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::{
+    audio::{AudioStream, WriterPool},
+    config::{SinkConfig, TriggerConfig},
+    events::{EventBus, EventKind, StationEvent, StationState},
+    sdr::{
+        kiwi::{
+            event::{SpectrumRow, WaterfallEvent},
+            message::KiwiClientMessage,
+            KiwiSDRScraper, KiwiSDRScraperSettings, KiwiWaterfall, WaterfallSettings,
+        },
+        scraper::{SDRScraper, ScraperOutputs, ScraperStats, ScraperStatus},
+        Tuning,
+    },
+    spectrum::detect::{detect, Detection},
+};
+
+/// The Kiwi's display range. Detection works from the dBm in the lines either way.
+const MIN_DB: i32 = -110;
+const MAX_DB: i32 = -10;
+
+/// Width of a Kiwi's whole band, shown by the waterfall at zoom 0.
+const BAND_WIDTH: f64 = 30e6;
+
+#[derive(Clone)]
+pub struct KiwiTriggerScraperSettings {
+    pub name: String,
+    pub endpoint: Url,
+    pub password: Option<String>,
+    pub agc: bool,
+    pub location: String,
+    pub identity: String,
+    pub trigger: TriggerConfig,
+}
+
+/// Watches a Kiwi's waterfall for signals rising above the noise and records each on a channel
+/// of its own while it lasts, with the detection in a `.json` next to the recording.
+///
+/// Recordings are made by a `KiwiSDRScraper` per channel, started and stopped as signals come
+/// and go. The watching station itself takes no audio.
+pub struct KiwiTriggerScraper {
+    settings: KiwiTriggerScraperSettings,
+    status: ScraperStatus,
+    token: CancellationToken,
+    outputs: ScraperOutputs,
+    channels: Arc<Mutex<Vec<KiwiSDRScraper>>>,
+}
+
+/// What the waterfall is pointed at: the middle of the watched ranges.
+fn view_tuning(config: &TriggerConfig) -> Tuning {
+    let low = config
+        .ranges
+        .iter()
+        .map(|range| range.start)
+        .fold(f64::MAX, f64::min);
+    let high = config
+        .ranges
+        .iter()
+        .map(|range| range.end)
+        .fold(f64::MIN, f64::max);
+    Tuning::AM {
+        bandwidth: (BAND_WIDTH / 2f64.powi(config.zoom as i32)) as i32,
+        frequency: if low <= high { (low + high) / 2.0 } else { 0.0 },
+    }
+}
+
+/// Makes sure the waterfall, centered on the ranges at the configured zoom, takes all of them in.
+fn check_ranges(config: &TriggerConfig) -> anyhow::Result<()> {
+    if config.ranges.is_empty() {
+        anyhow::bail!("no ranges to watch");
+    }
+    if let Some(range) = config.ranges.iter().find(|range| range.end <= range.start) {
+        anyhow::bail!(
+            "range {:.0}-{:.0} kHz ends before it starts",
+            range.start / 1000.0,
+            range.end / 1000.0
+        );
+    }
+    if let Some(range) = config
+        .ranges
+        .iter()
+        .find(|range| range.start < 0.0 || range.end > BAND_WIDTH)
+    {
+        anyhow::bail!(
+            "range {:.0}-{:.0} kHz isn't within the Kiwi's 0-{:.0} kHz",
+            range.start / 1000.0,
+            range.end / 1000.0,
+            BAND_WIDTH / 1000.0
+        );
+    }
+
+    let low = config
+        .ranges
+        .iter()
+        .map(|range| range.start)
+        .fold(f64::MAX, f64::min);
+    let high = config
+        .ranges
+        .iter()
+        .map(|range| range.end)
+        .fold(f64::MIN, f64::max);
+    let span = BAND_WIDTH / 2f64.powi(config.zoom as i32);
+    if high - low > span {
+        anyhow::bail!(
+            "ranges cover {:.0} kHz, more than the {:.0} kHz the waterfall shows at zoom {}, \
+             which must be {} or less",
+            (high - low) / 1000.0,
+            span / 1000.0,
+            config.zoom,
+            (BAND_WIDTH / (high - low)).log2().floor()
+        );
+    }
+    Ok(())
+}
+
+impl KiwiTriggerScraper {
+    pub fn new(
+        settings: KiwiTriggerScraperSettings,
+        pool: &WriterPool,
+        events: &EventBus,
+    ) -> KiwiTriggerScraper {
+        let sinks = [SinkConfig::File {
+            dir: settings.trigger.dir.clone(),
+            template: settings.trigger.template.clone(),
+            // Recordings end well before this, so each is one file
+            rotate: settings.trigger.max_duration * 2,
+            squelch: None,
+        }];
+        let channels = (1..=settings.trigger.channels)
+            .map(|number| {
+                KiwiSDRScraper::new(
+                    KiwiSDRScraperSettings {
+                        name: format!("{}_{}", settings.name, number),
+                        endpoint: settings.endpoint.clone(),
+                        password: settings.password.clone(),
+                        station: view_tuning(&settings.trigger),
+                        agc: settings.agc,
+                        location: settings.location.clone(),
+                        identity: settings.identity.clone(),
+                        sinks: sinks.to_vec(),
+                        capture: None,
+                        waterfall: None,
+                    },
+                    pool,
+                    events,
+                )
+            })
+            .collect();
+
+        KiwiTriggerScraper {
+            outputs: ScraperOutputs::new(
+                &settings.name,
+                view_tuning(&settings.trigger),
+                &[],
+                pool,
+                events,
+            ),
+            settings,
+            status: ScraperStatus::Stopped,
+            token: CancellationToken::new(),
+            channels: Arc::new(Mutex::new(channels)),
+        }
+    }
+}
+
+/// What goes into the `.json` next to a recording.
+#[derive(Serialize)]
+struct Clip<'a> {
+    station: &'a str,
+    tuning: &'a Tuning,
+    /// When the signal was first seen.
+    detected: DateTime<Utc>,
+    ended: DateTime<Utc>,
+    /// Lines the signal was seen in.
+    lines: u32,
+    /// The signal at its strongest.
+    #[serde(flatten)]
+    detection: &'a Detection,
+}
+
+struct Recording {
+    channel: usize,
+    tuning: Tuning,
+    started: Instant,
+    /// The file the channel's writer opened, once audio has arrived.
+    file: Option<PathBuf>,
+}
+
+/// A signal seen over one or more lines.
+struct Track {
+    /// Extent of the latest sighting, with the strongest peak of any.
+    detection: Detection,
+    detected: DateTime<Utc>,
+    last_seen: Instant,
+    lines: u32,
+    recording: Option<Recording>,
+    /// Already recorded for `max_duration`, and left alone until it goes away.
+    done: bool,
+}
+
+impl Track {
+    fn update(&mut self, detection: Detection) {
+        let strongest = if detection.peak_dbm > self.detection.peak_dbm {
+            (detection.frequency, detection.peak_dbm)
+        } else {
+            (self.detection.frequency, self.detection.peak_dbm)
+        };
+        self.detection = Detection {
+            frequency: strongest.0,
+            peak_dbm: strongest.1,
+            ..detection
+        };
+        self.last_seen = Instant::now();
+        self.lines += 1;
+    }
+}
+
+/// Follows the signals on the waterfall and starts and stops the recording channels.
+struct Trigger {
+    settings: KiwiTriggerScraperSettings,
+    outputs: ScraperOutputs,
+    channels: Arc<Mutex<Vec<KiwiSDRScraper>>>,
+    tracks: Vec<Track>,
+}
+
+impl Trigger {
+    async fn row(&mut self, row: &SpectrumRow) {
+        let config = self.settings.trigger.clone();
+        for detection in detect(row, &config.ranges, config.margin) {
+            match self
+                .tracks
+                .iter_mut()
+                .find(|track| track.detection.overlaps(&detection))
+            {
+                Some(track) => track.update(detection),
+                None => self.tracks.push(Track {
+                    detection,
+                    detected: row.timestamp,
+                    last_seen: Instant::now(),
+                    lines: 1,
+                    recording: None,
+                    done: false,
+                }),
+            }
+        }
+
+        for index in 0..self.tracks.len() {
+            let track = &self.tracks[index];
+            let expired = track.last_seen.elapsed() > config.hold
+                || track
+                    .recording
+                    .as_ref()
+                    .is_some_and(|recording| recording.started.elapsed() > config.max_duration);
+            if expired {
+                self.finish(index).await;
+            }
+        }
+        self.tracks
+            .retain(|track| track.last_seen.elapsed() <= config.hold);
+
+        for index in 0..self.tracks.len() {
+            let track = &self.tracks[index];
+            if track.done || track.recording.is_some() || track.lines < config.confirm {
+                continue;
+            }
+            let Some(channel) = (0..config.channels).find(|channel| {
+                !self.tracks.iter().any(|track| {
+                    track
+                        .recording
+                        .as_ref()
+                        .is_some_and(|recording| recording.channel == *channel)
+                })
+            }) else {
+                break;
+            };
+            self.record(index, channel).await;
+        }
+    }
+
+    async fn record(&mut self, index: usize, channel: usize) {
+        let track = &mut self.tracks[index];
+        let tuning = track.detection.tuning();
+        log::info!(
+            "{}: {:.1} dBm signal at {:.0} Hz, recording {}",
+            self.settings.name.green(),
+            track.detection.peak_dbm,
+            track.detection.frequency,
+            tuning
+        );
+        self.outputs.publish(EventKind::SignalDetected {
+            frequency: track.detection.frequency,
+            peak_dbm: track.detection.peak_dbm,
+            noise_dbm: track.detection.noise_dbm,
+        });
+
+        let mut channels = self.channels.lock().await;
+        let scraper = &mut channels[channel];
+        let started = async {
+            scraper.tune(tuning.clone()).await?;
+            scraper.start().await
+        };
+        if let Err(e) = started.await {
+            log::error!(
+                "{}: failed to start recording: {}",
+                self.settings.name.red(),
+                e
+            );
+            track.done = true;
+            return;
+        }
+        track.recording = Some(Recording {
+            channel,
+            tuning,
+            started: Instant::now(),
+            file: None,
+        });
+    }
+
+    /// Stops the recording of a track, if any, and writes its `.json`.
+    async fn finish(&mut self, index: usize) {
+        let track = &mut self.tracks[index];
+        let Some(recording) = track.recording.take() else {
+            return;
+        };
+        track.done = true;
+        self.outputs.publish(EventKind::SignalLost {
+            frequency: track.detection.frequency,
+        });
+
+        if let Err(e) = self.channels.lock().await[recording.channel].stop().await {
+            log::error!(
+                "{}: failed to stop recording: {}",
+                self.settings.name.red(),
+                e
+            );
+        }
+
+        let Some(file) = recording.file else {
+            log::warn!(
+                "{}: no audio arrived for the signal at {} Hz",
+                self.settings.name.yellow(),
+                track.detection.frequency
+            );
+            return;
+        };
+        let clip = Clip {
+            station: &self.settings.name,
+            tuning: &recording.tuning,
+            detected: track.detected,
+            ended: Utc::now(),
+            lines: track.lines,
+            detection: &track.detection,
+        };
+        let written = serde_json::to_vec_pretty(&clip)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(file.with_extension("json"), json)?));
+        if let Err(e) = written {
+            log::error!(
+                "{}: failed to write detection for {}: {}",
+                self.settings.name.red(),
+                file.display(),
+                e
+            );
+        }
+    }
+
+    /// Notes which file a channel's writer opened for the recording it's making.
+    fn event(&mut self, event: &StationEvent) {
+        let EventKind::FileOpened { path } = &event.kind else {
+            return;
+        };
+        let Some(channel) = event
+            .station
+            .strip_prefix(&self.settings.name)
+            .and_then(|suffix| suffix.strip_prefix('_'))
+            .and_then(|number| number.parse::<usize>().ok())
+        else {
+            return;
+        };
+        let recording = self
+            .tracks
+            .iter_mut()
+            .filter_map(|track| track.recording.as_mut())
+            .find(|recording| recording.channel + 1 == channel);
+        if let Some(recording) = recording {
+            recording.file = Some(PathBuf::from(path));
+        }
+    }
+
+    async fn finish_all(&mut self) {
+        for index in 0..self.tracks.len() {
+            self.finish(index).await;
+        }
+        self.tracks.clear();
+    }
+}
+
+/// Watches the waterfall, reconnecting whenever it drops, until cancelled.
+async fn run_trigger(
+    mut trigger: Trigger,
+    mut events: broadcast::Receiver<StationEvent>,
+    token: CancellationToken,
+) {
+    let settings = trigger.settings.clone();
+    let config = settings.trigger.clone();
+    let waterfall_settings = WaterfallSettings {
+        zoom: config.zoom,
+        center_frequency: view_tuning(&config).frequency(),
+        speed: config.speed,
+        min_db: MIN_DB,
+        max_db: MAX_DB,
+    };
+    let mut waterfall = KiwiWaterfall::new(settings.endpoint.clone());
+
+    let run = async {
+        loop {
+            trigger.outputs.state.set(StationState::Connecting);
+            match waterfall
+                .connect(settings.password.clone(), &waterfall_settings)
+                .await
+            {
+                Ok(_) => {
+                    log::info!("{}: watching the waterfall", settings.name.green());
+                    trigger.outputs.state.set(StationState::Ready);
+                    let mut keepalive = tokio::time::interval(Duration::from_secs(5));
+                    loop {
+                        tokio::select! {
+                            event = waterfall.read_event(Duration::from_secs(1)) => match event {
+                                Some(WaterfallEvent::Row(row)) => trigger.row(&row).await,
+                                Some(WaterfallEvent::Close(reason)) => {
+                                    log::error!(
+                                        "{}: waterfall closed: {:?}",
+                                        settings.name.red(),
+                                        reason
+                                    );
+                                    trigger.outputs.publish(EventKind::Disconnected {
+                                        reason: format!("{:?}", reason),
+                                    });
+                                    break;
+                                }
+                                _ => {}
+                            },
+                            event = events.recv() => match event {
+                                Ok(event) => trigger.event(&event),
+                                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                    log::debug!("{}: skipped {} events", settings.name, skipped);
+                                }
+                                Err(broadcast::error::RecvError::Closed) => {}
+                            },
+                            _ = keepalive.tick() => {
+                                if waterfall.send_message(KiwiClientMessage::KeepAlive).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::error!(
+                        "{}: failed to connect waterfall: {}",
+                        settings.name.red(),
+                        e
+                    );
+                    trigger.outputs.publish(EventKind::ReconnectFailed {
+                        error: e.to_string(),
+                    });
+                }
+            }
+
+            // Signals can't be followed without the waterfall
+            trigger.finish_all().await;
+            trigger.outputs.state.set(StationState::Disconnected);
+            log::info!("{}: reconnecting waterfall in 4...", settings.name.yellow());
+            tokio::time::sleep(Duration::from_secs(4)).await;
+        }
+    };
+
+    tokio::select! {
+        _ = run => {}
+        _ = token.cancelled() => {
+            log::debug!("{}: trigger loop cancelled", settings.name.yellow());
+        }
+    }
+    let _ = waterfall.shutdown();
+    trigger.finish_all().await;
+}
+
+#[async_trait::async_trait]
+impl SDRScraper for KiwiTriggerScraper {
+    async fn start(&mut self) -> anyhow::Result<()> {
+        if self.status == ScraperStatus::Running {
+            log::warn!("SDR for {} is already running", self.settings.name);
+            return Ok(());
+        }
+        check_ranges(&self.settings.trigger)?;
+
+        log::debug!("starting trigger for {}", self.settings.name.green());
+        self.token = CancellationToken::new();
+        let trigger = Trigger {
+            settings: self.settings.clone(),
+            outputs: self.outputs.clone(),
+            channels: self.channels.clone(),
+            tracks: Vec::new(),
+        };
+        tokio::spawn(run_trigger(
+            trigger,
+            self.outputs.events.subscribe(),
+            self.token.clone(),
+        ));
+
+        self.status = ScraperStatus::Running;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        log::debug!("Stopping trigger for {}", self.settings.name.green());
+
+        self.token.cancel();
+        self.status = ScraperStatus::Stopped;
+        self.outputs.state.set(StationState::Stopped);
+
+        Ok(())
+    }
+
+    async fn tune(&mut self, _tuning: Tuning) -> anyhow::Result<()> {
+        anyhow::bail!("triggers watch their configured ranges and can't be tuned")
+    }
+
+    fn status(&self) -> ScraperStatus {
+        self.status.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    fn get_stats(&self) -> ScraperStats {
+        self.outputs.stats(self.status.clone())
+    }
+
+    fn audio(&self) -> AudioStream {
+        self.outputs.audio.clone()
+    }
+
+    fn connections(&self) -> usize {
+        // The waterfall, and the channels recording. One being started or stopped holds the
+        // lock, and is left out until the next look.
+        let waterfall = self.outputs.state.get().0 == StationState::Ready;
+        let channels = self.channels.try_lock().map_or(0, |channels| {
+            channels.iter().map(SDRScraper::connections).sum()
+        });
+        waterfall as usize + channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FrequencyRange;
+
+    fn config(zoom: u8, ranges: &[(f64, f64)]) -> TriggerConfig {
+        TriggerConfig {
+            ranges: ranges
+                .iter()
+                .map(|&(start, end)| FrequencyRange { start, end })
+                .collect(),
+            zoom,
+            ..TriggerConfig::default()
+        }
+    }
+
+    #[test]
+    fn ranges_must_fit_the_view() {
+        // 30 MHz over 2^8 is about 117 kHz, over 2^7 about 234 kHz
+        let error = check_ranges(&config(8, &[(7.0e6, 7.1e6), (7.15e6, 7.2e6)])).unwrap_err();
+        assert!(error.to_string().contains("7 or less"), "{}", error);
+        assert!(check_ranges(&config(7, &[(7.0e6, 7.1e6), (7.15e6, 7.2e6)])).is_ok());
+        assert!(check_ranges(&config(1, &[(3.5e6, 3.8e6), (14.0e6, 14.35e6)])).is_ok());
+    }
+
+    #[test]
+    fn ranges_must_be_on_the_band() {
+        assert!(check_ranges(&config(0, &[])).is_err());
+        assert!(check_ranges(&config(0, &[(7.2e6, 7.0e6)])).is_err());
+        assert!(check_ranges(&config(0, &[(29.0e6, 31.0e6)])).is_err());
+    }
+}
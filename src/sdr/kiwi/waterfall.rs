@@ -0,0 +1,332 @@
+use std::time::Duration;
+
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::audio::ima_adpcm::IMA_ADPCM_Decoder;
+use crate::sdr::kiwi::{
+    connection_number,
+    event::{KiwiCloseReason, SpectrumRow, WaterfallEvent},
+    message::{KiwiClientMessage, KiwiServerMessage},
+};
+
+/// Deepest zoom a Kiwi offers, until it says otherwise.
+pub const ZOOM_MAX: u32 = 14;
+
+/// Samples the Kiwi appends to each compressed line to flush its encoder.
+const ADPCM_PAD: usize = 10;
+
+/// What the waterfall should show.
+#[derive(Clone, Debug)]
+pub struct WaterfallSettings {
+    /// The band's full width is shown over 2^zoom, from 0 up to the Kiwi's `zoom_max`.
+    pub zoom: u8,
+    pub center_frequency: f64,
+    /// Line rate, from 1 (about one a second) through 4 (fastest).
+    pub speed: u8,
+    pub min_db: i32,
+    pub max_db: i32,
+}
+
+/// The part of the band the Kiwi covers, from the `MSG`s it sends after login.
+struct Band {
+    bandwidth: f64,
+    zoom_max: u32,
+}
+
+impl Band {
+    fn update(&mut self, msg: &str) {
+        for (key, value) in msg.split_whitespace().filter_map(|p| p.split_once('=')) {
+            match key {
+                "bandwidth" => self.bandwidth = value.parse().unwrap_or(self.bandwidth),
+                "zoom_max" => self.zoom_max = value.parse().unwrap_or(self.zoom_max),
+                _ => {}
+            }
+        }
+    }
+
+    /// Decodes a `W/F` frame: a byte of padding, the line's first bin at full zoom, its zoom
+    /// level, its sequence number and the compressed line.
+    fn parse_row(&self, bin: &[u8]) -> Option<SpectrumRow> {
+        let header = bin.get(4..16)?;
+        let x_bin = LittleEndian::read_u32(&header[0..4]);
+        let zoom = LittleEndian::read_u32(&header[4..8]) & 0xFFFF;
+        let sequence = LittleEndian::read_u32(&header[8..12]);
+
+        // Every line is compressed on its own
+        let data = &bin[16..];
+        let mut samples = vec![0; data.len() * 2];
+        IMA_ADPCM_Decoder::new().decode_block(data, &mut samples);
+        samples.truncate(samples.len().checked_sub(ADPCM_PAD)?);
+        if samples.is_empty() {
+            return None;
+        }
+
+        let full_zoom_bins = (samples.len() as f64) * 2f64.powi(self.zoom_max as i32);
+        Some(SpectrumRow {
+            timestamp: Utc::now(),
+            sequence,
+            start_frequency: x_bin as f64 * self.bandwidth / full_zoom_bins,
+            bin_width: self.bandwidth / 2f64.powi(zoom as i32) / samples.len() as f64,
+            bins: samples
+                .iter()
+                .map(|&sample| sample.clamp(0, 255) as f32 - 255.0)
+                .collect(),
+        })
+    }
+}
+
+/// A client for a Kiwi's `/W/F` socket, alongside the audio from `KiwiSDR`.
+pub struct KiwiWaterfall {
+    cancellation_token: CancellationToken,
+    event_channel_rx: Option<tokio::sync::mpsc::Receiver<WaterfallEvent>>,
+    message_channel_tx: Option<tokio::sync::mpsc::Sender<KiwiClientMessage>>,
+    endpoint: Url,
+}
+
+impl KiwiWaterfall {
+    pub fn new(endpoint: Url) -> Self {
+        Self {
+            cancellation_token: CancellationToken::new(),
+            event_channel_rx: None,
+            message_channel_tx: None,
+            endpoint,
+        }
+    }
+
+    pub async fn connect(
+        &mut self,
+        password: Option<String>,
+        settings: &WaterfallSettings,
+    ) -> anyhow::Result<()> {
+        log::debug!("Connecting to KiwiSDR waterfall at {}", self.endpoint);
+
+        let number = connection_number(&self.endpoint).await?;
+        let url = self.endpoint.join(&format!("kiwi/{}/W/F", number))?;
+        let (ws_socket, _) = tokio::time::timeout(
+            Duration::from_secs(2),
+            tokio_tungstenite::connect_async(url),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("Connection timeout"))??;
+
+        let (mut write, mut read) = ws_socket.split();
+        write
+            .send(KiwiClientMessage::Login(password).into())
+            .await?;
+
+        let (event_tx, event_rx) = tokio::sync::mpsc::channel::<WaterfallEvent>(100);
+        let (msg_tx, mut msg_rx) = tokio::sync::mpsc::channel::<KiwiClientMessage>(100);
+        self.event_channel_rx = Some(event_rx);
+        self.message_channel_tx = Some(msg_tx);
+
+        // Whatever the previous connection left running goes with it
+        self.cancellation_token.cancel();
+        self.cancellation_token = CancellationToken::new();
+        let token = self.cancellation_token.clone();
+        tokio::spawn(async move {
+            let mut band = Band {
+                bandwidth: 30e6,
+                zoom_max: ZOOM_MAX,
+            };
+            let read_loop = async {
+                while let Some(msg) = read.next().await {
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            log::error!("Error reading waterfall message: {:?}", e);
+                            break;
+                        }
+                    };
+
+                    let event = match msg {
+                        Message::Binary(bin) => match bin.get(..3) {
+                            Some(b"W/F") => band.parse_row(&bin).map(WaterfallEvent::Row),
+                            Some(b"MSG") => {
+                                let Ok(text) = String::from_utf8(bin[3..].to_vec()) else {
+                                    continue;
+                                };
+                                let text = text.trim_start().to_string();
+                                band.update(&text);
+                                match KiwiServerMessage::from(text) {
+                                    KiwiServerMessage::AuthenticationResult(false) => {
+                                        Some(WaterfallEvent::Close(
+                                            KiwiCloseReason::AuthenticationFailed,
+                                        ))
+                                    }
+                                    KiwiServerMessage::Unknown(msg) => {
+                                        Some(WaterfallEvent::Message(msg))
+                                    }
+                                    _ => None,
+                                }
+                            }
+                            _ => None,
+                        },
+                        Message::Close(_) => {
+                            Some(WaterfallEvent::Close(KiwiCloseReason::ServerClosed))
+                        }
+                        _ => None,
+                    };
+
+                    if let Some(event) = event {
+                        let closed = matches!(event, WaterfallEvent::Close(_));
+                        if closed {
+                            token.cancel();
+                        }
+                        // Nobody is reading once the waterfall is reconnected or dropped
+                        if event_tx.send(event).await.is_err() || closed {
+                            return;
+                        }
+                    }
+                }
+
+                // The connection dropped without a close frame
+                token.cancel();
+                let _ = event_tx
+                    .send(WaterfallEvent::Close(KiwiCloseReason::ServerClosed))
+                    .await;
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = read_loop => {}
+            };
+        });
+
+        let token = self.cancellation_token.clone();
+        tokio::spawn(async move {
+            let write_loop = async {
+                while let Some(msg) = msg_rx.recv().await {
+                    let msg: Message = msg.into();
+                    log::debug!("Sending waterfall message: {:?}", msg);
+                    if let Err(e) = write.send(msg).await {
+                        log::error!("Error sending waterfall message: {:?}", e);
+                        return;
+                    }
+                }
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = write_loop => {}
+            };
+        });
+
+        self.configure(settings).await
+    }
+
+    /// Applies `settings` to the open waterfall.
+    pub async fn configure(&self, settings: &WaterfallSettings) -> anyhow::Result<()> {
+        self.send_message(KiwiClientMessage::SetWaterfallCompression(true))
+            .await?;
+        self.send_message(KiwiClientMessage::SetDbRange {
+            min: settings.min_db,
+            max: settings.max_db,
+        })
+        .await?;
+        self.send_message(KiwiClientMessage::SetWaterfallSpeed(settings.speed))
+            .await?;
+        self.send_message(KiwiClientMessage::SetZoom {
+            zoom: settings.zoom,
+            center: settings.center_frequency,
+        })
+        .await
+    }
+
+    pub async fn read_event(&mut self, timeout: Duration) -> Option<WaterfallEvent> {
+        let rx = self.event_channel_rx.as_mut()?;
+
+        tokio::time::timeout(timeout, rx.recv())
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn send_message(&self, message: KiwiClientMessage) -> anyhow::Result<()> {
+        self.message_channel_tx
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("not connected"))?
+            .send(message)
+            .await?;
+        Ok(())
+    }
+
+    pub fn shutdown(&self) -> anyhow::Result<()> {
+        log::debug!("Shutting down KiwiSDR waterfall");
+        self.cancellation_token.cancel();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::ima_adpcm::IMA_ADPCM_Encoder;
+
+    const WIDTH: usize = 1024;
+
+    fn band() -> Band {
+        Band {
+            bandwidth: 30e6,
+            zoom_max: ZOOM_MAX,
+        }
+    }
+
+    /// Builds a `W/F` frame the way kiwi-sim does, padding the line before compressing it.
+    fn frame(x_bin: u32, zoom: u32, sequence: u32, line: &[i16]) -> Vec<u8> {
+        let mut samples = line.to_vec();
+        if let Some(&last) = line.last() {
+            samples.resize(line.len() + ADPCM_PAD, last);
+        }
+        let mut data = vec![0; samples.len() / 2];
+        IMA_ADPCM_Encoder::new().encode_block(&samples, &mut data);
+
+        let mut frame = b"W/F\0".to_vec();
+        frame.extend(x_bin.to_le_bytes());
+        frame.extend(zoom.to_le_bytes());
+        frame.extend(sequence.to_le_bytes());
+        frame.extend(data);
+        frame
+    }
+
+    #[test]
+    fn parses_a_line() {
+        // Zoom 3 over a quarter of the way into the band, 7.5 to 11.25 MHz
+        let x_bin = (WIDTH << 14) as u32 / 4;
+        let line = (0..WIDTH)
+            .map(|bin| if bin < WIDTH / 2 { 40 } else { 120 })
+            .collect::<Vec<i16>>();
+        let row = band().parse_row(&frame(x_bin, 3, 42, &line)).unwrap();
+
+        assert_eq!(row.sequence, 42);
+        assert!((row.start_frequency - 7.5e6).abs() < 1e-6);
+        assert!((row.bin_width - 30e6 / 8.0 / WIDTH as f64).abs() < 1e-9);
+        assert_eq!(row.bins.len(), WIDTH);
+        // Levels come back as dB below full scale, once the decoder has caught up
+        assert!((row.bins[WIDTH / 2 - 1] - (40.0 - 255.0)).abs() < 4.0);
+        assert!((row.bins[WIDTH - 1] - (120.0 - 255.0)).abs() < 4.0);
+    }
+
+    #[test]
+    fn ignores_the_zoom_high_bits() {
+        let line = vec![100; WIDTH];
+        let row = band()
+            .parse_row(&frame(0, 0x0001_0000 | 14, 0, &line))
+            .unwrap();
+        assert!((row.bin_width - 30e6 / (WIDTH << 14) as f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_short_and_empty_frames() {
+        let band = band();
+        let whole = frame(0, 0, 0, &[100; WIDTH]);
+        assert!(band.parse_row(&whole[..12]).is_none());
+        // A header alone, a line shorter than the padding, and nothing but padding
+        assert!(band.parse_row(&whole[..16]).is_none());
+        assert!(band.parse_row(&whole[..18]).is_none());
+        assert!(band.parse_row(&whole[..16 + ADPCM_PAD / 2]).is_none());
+    }
+}
@@ -1,13 +1,21 @@
+pub mod file;
+pub mod http_stream;
 pub mod kiwi;
+pub mod openwebrx;
+pub mod rtl_tcp;
 mod scraper;
+pub mod spyserver;
+pub mod websdr;
 
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
-pub use scraper::{SDRScraper, ScraperStatus};
+pub use scraper::{SDRScraper, ScraperStats, ScraperStatus};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "mode")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Tuning {
     AM {
         bandwidth: i32,
@@ -30,6 +38,86 @@ pub enum Tuning {
     },
 }
 
+impl Tuning {
+    pub fn frequency(&self) -> f64 {
+        match self {
+            Tuning::AM { frequency, .. }
+            | Tuning::FM { frequency, .. }
+            | Tuning::LSB { frequency, .. }
+            | Tuning::USB { frequency, .. } => *frequency,
+        }
+    }
+
+    pub fn mode(&self) -> &'static str {
+        match self {
+            Tuning::AM { .. } => "am",
+            Tuning::FM { .. } => "fm",
+            Tuning::LSB { .. } => "lsb",
+            Tuning::USB { .. } => "usb",
+        }
+    }
+}
+
+/// A demodulation mode on its own, for configs and requests that leave the passband out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Mode {
+    AM,
+    FM,
+    LSB,
+    #[default]
+    USB,
+}
+
+impl Mode {
+    /// Tunes to `frequency` in this mode. The passband edges, in Hz from `frequency`, default to
+    /// the usual ones for the mode; AM takes the width between them as its bandwidth.
+    pub fn tuning(self, frequency: f64, low_cut: Option<i32>, high_cut: Option<i32>) -> Tuning {
+        let (low, high) = match self {
+            Mode::AM => (-4500, 4500),
+            Mode::FM => (-6000, 6000),
+            Mode::LSB => (-2700, -300),
+            Mode::USB => (300, 2700),
+        };
+        let (low_cut, high_cut) = (low_cut.unwrap_or(low), high_cut.unwrap_or(high));
+        match self {
+            Mode::AM => Tuning::AM {
+                bandwidth: high_cut - low_cut,
+                frequency,
+            },
+            Mode::FM => Tuning::FM {
+                low_cut,
+                high_cut,
+                frequency,
+            },
+            Mode::LSB => Tuning::LSB {
+                low_cut,
+                high_cut,
+                frequency,
+            },
+            Mode::USB => Tuning::USB {
+                low_cut,
+                high_cut,
+                frequency,
+            },
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Mode> {
+        Ok(match text.to_lowercase().as_str() {
+            "am" => Mode::AM,
+            "fm" => Mode::FM,
+            "lsb" => Mode::LSB,
+            "usb" => Mode::USB,
+            other => anyhow::bail!("unknown mode {}", other),
+        })
+    }
+}
+
 impl Display for Tuning {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
@@ -0,0 +1,129 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::audio::ima_adpcm::IMA_ADPCM_Decoder;
+
+const SYNC_WORD: &[u8; 4] = b"SYNC";
+
+/// Bytes of ADPCM between two sync points.
+const SYNC_INTERVAL: usize = 1000;
+
+enum SyncState {
+    /// Looking for the sync word, holding how many of its bytes matched so far.
+    Searching(usize),
+    /// Collecting the codec state that follows the sync word.
+    ReadingState(Vec<u8>),
+    /// Decoding, holding how many bytes are left until the next sync point.
+    Decoding(usize),
+}
+
+/// Decodes OpenWebRX's ADPCM audio, which is plain IMA ADPCM interleaved with a `SYNC` word followed by
+/// the encoder's step index and predictor every `SYNC_INTERVAL` bytes. Sync points may straddle packets.
+pub struct SyncedAdpcmDecoder {
+    decoder: IMA_ADPCM_Decoder,
+    state: SyncState,
+}
+
+impl SyncedAdpcmDecoder {
+    pub fn new() -> Self {
+        SyncedAdpcmDecoder {
+            decoder: IMA_ADPCM_Decoder::new(),
+            state: SyncState::Searching(0),
+        }
+    }
+
+    pub fn decode(&mut self, data: &[u8]) -> Vec<i16> {
+        let mut samples = Vec::with_capacity(data.len() * 2);
+
+        for &byte in data {
+            self.state = match std::mem::replace(&mut self.state, SyncState::Searching(0)) {
+                SyncState::Searching(matched) => {
+                    if byte == SYNC_WORD[matched] {
+                        if matched + 1 == SYNC_WORD.len() {
+                            SyncState::ReadingState(Vec::with_capacity(4))
+                        } else {
+                            SyncState::Searching(matched + 1)
+                        }
+                    } else if byte == SYNC_WORD[0] {
+                        SyncState::Searching(1)
+                    } else {
+                        SyncState::Searching(0)
+                    }
+                }
+                SyncState::ReadingState(mut state) => {
+                    state.push(byte);
+                    if state.len() == 4 {
+                        self.decoder.set_state(
+                            LittleEndian::read_i16(&state[0..2]),
+                            LittleEndian::read_i16(&state[2..4]),
+                        );
+                        SyncState::Decoding(SYNC_INTERVAL)
+                    } else {
+                        SyncState::ReadingState(state)
+                    }
+                }
+                SyncState::Decoding(remaining) => {
+                    samples.push(self.decoder.decode((byte & 0x0F) as u16));
+                    samples.push(self.decoder.decode((byte >> 4) as u16));
+                    if remaining > 1 {
+                        SyncState::Decoding(remaining - 1)
+                    } else {
+                        SyncState::Searching(0)
+                    }
+                }
+            };
+        }
+
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three sync intervals of ADPCM, each behind a sync word and the encoder's state, and the
+    /// samples a decoder taking up that state at each sync point gets back.
+    fn stream() -> (Vec<u8>, Vec<i16>) {
+        let mut stream = Vec::new();
+        let mut expected = Vec::new();
+        for interval in 0..3 {
+            let (step_index, prev_sample) = (20 + 10 * interval as i16, -1000 * interval as i16);
+            stream.extend(SYNC_WORD);
+            stream.extend(step_index.to_le_bytes());
+            stream.extend(prev_sample.to_le_bytes());
+
+            let mut decoder = IMA_ADPCM_Decoder::new();
+            decoder.set_state(step_index, prev_sample);
+            for i in 0..SYNC_INTERVAL {
+                let byte = (i * 37 + interval * 11) as u8;
+                stream.push(byte);
+                expected.push(decoder.decode((byte & 0x0F) as u16));
+                expected.push(decoder.decode((byte >> 4) as u16));
+            }
+        }
+        (stream, expected)
+    }
+
+    #[test]
+    fn decodes_across_packets_and_sync_points() {
+        let (stream, expected) = stream();
+        let mut decoder = SyncedAdpcmDecoder::new();
+        // Packets that split sync words and states
+        let samples = stream
+            .chunks(333)
+            .flat_map(|packet| decoder.decode(packet))
+            .collect::<Vec<i16>>();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn resyncs_at_the_next_sync_word() {
+        let (stream, expected) = stream();
+        let mut decoder = SyncedAdpcmDecoder::new();
+        // Joined partway through the first interval, after the start of what looks like a sync word
+        let mut joined = b"SYSY".to_vec();
+        joined.extend(&stream[500..]);
+        let samples = decoder.decode(&joined);
+        assert_eq!(samples, &expected[2 * SYNC_INTERVAL..]);
+    }
+}
@@ -0,0 +1,17 @@
+#[derive(Debug)]
+pub enum OpenWebRxCloseReason {
+    ServerClosed,
+    ConnectionLost,
+}
+
+#[derive(Debug)]
+pub enum OpenWebRxEvent {
+    Close(OpenWebRxCloseReason),
+    Message(String),
+    Config {
+        center_frequency: Option<f64>,
+        sample_rate: Option<f64>,
+    },
+    Audio(Vec<i16>),
+    Smeter(f64),
+}
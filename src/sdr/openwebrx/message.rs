@@ -0,0 +1,122 @@
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::sdr::Tuning;
+
+#[derive(Debug)]
+pub enum OpenWebRxClientMessage {
+    Handshake,
+    ConnectionProperties {
+        output_rate: u32,
+        hd_output_rate: u32,
+    },
+    StartDsp,
+    /// Demodulates `tuning` from the receiver's current band, whose center is at `center_frequency`.
+    Tune {
+        tuning: Tuning,
+        center_frequency: f64,
+    },
+    /// Moves the receiver's center frequency, which servers only allow with the magic key.
+    SetCenterFrequency {
+        frequency: f64,
+        key: Option<String>,
+    },
+}
+
+impl From<OpenWebRxClientMessage> for Message {
+    fn from(msg: OpenWebRxClientMessage) -> Message {
+        let json = match msg {
+            OpenWebRxClientMessage::Handshake => {
+                return Message::Text(
+                    "SERVER DE CLIENT client=sdr-scraper type=receiver".to_string(),
+                )
+            }
+            OpenWebRxClientMessage::ConnectionProperties {
+                output_rate,
+                hd_output_rate,
+            } => json!({
+                "type": "connectionproperties",
+                "params": {
+                    "output_rate": output_rate,
+                    "hd_output_rate": hd_output_rate,
+                },
+            }),
+            OpenWebRxClientMessage::StartDsp => json!({
+                "type": "dspcontrol",
+                "action": "start",
+            }),
+            OpenWebRxClientMessage::Tune {
+                tuning,
+                center_frequency,
+            } => {
+                let (modulation, low_cut, high_cut) = match tuning {
+                    Tuning::AM { bandwidth, .. } => ("am", -(bandwidth / 2), bandwidth / 2),
+                    Tuning::FM {
+                        low_cut, high_cut, ..
+                    } => ("nfm", low_cut, high_cut),
+                    Tuning::LSB {
+                        low_cut, high_cut, ..
+                    } => ("lsb", low_cut, high_cut),
+                    Tuning::USB {
+                        low_cut, high_cut, ..
+                    } => ("usb", low_cut, high_cut),
+                };
+                json!({
+                    "type": "dspcontrol",
+                    "params": {
+                        "mod": modulation,
+                        "low_cut": low_cut,
+                        "high_cut": high_cut,
+                        "offset_freq": (tuning.frequency() - center_frequency).round() as i64,
+                        "squelch_level": -150,
+                    },
+                })
+            }
+            OpenWebRxClientMessage::SetCenterFrequency { frequency, key } => json!({
+                "type": "setfrequency",
+                "params": {
+                    "frequency": frequency.round() as i64,
+                    "key": key,
+                },
+            }),
+        };
+        Message::Text(json.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub enum OpenWebRxServerMessage {
+    /// Receiver settings. Servers send partial updates, so any field may be missing.
+    Config {
+        center_frequency: Option<f64>,
+        sample_rate: Option<f64>,
+        audio_compression: Option<String>,
+    },
+    /// Signal level in dB.
+    Smeter(f64),
+    Unknown(String),
+}
+
+impl From<String> for OpenWebRxServerMessage {
+    fn from(msg: String) -> OpenWebRxServerMessage {
+        let Ok(json) = serde_json::from_str::<Value>(&msg) else {
+            return OpenWebRxServerMessage::Unknown(msg);
+        };
+
+        match json["type"].as_str() {
+            Some("config") => {
+                let value = &json["value"];
+                OpenWebRxServerMessage::Config {
+                    center_frequency: value["center_freq"].as_f64(),
+                    sample_rate: value["samp_rate"].as_f64(),
+                    audio_compression: value["audio_compression"].as_str().map(str::to_string),
+                }
+            }
+            Some("smeter") => match json["value"].as_f64() {
+                Some(level) => OpenWebRxServerMessage::Smeter(10.0 * level.max(1e-20).log10()),
+                None => OpenWebRxServerMessage::Unknown(msg),
+            },
+            _ => OpenWebRxServerMessage::Unknown(msg),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+mod audio;
+pub mod event;
+mod message;
+mod scraper;
+
+use std::time::Duration;
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+pub use scraper::{OpenWebRxScraper, OpenWebRxScraperSettings};
+
+use self::{
+    audio::SyncedAdpcmDecoder,
+    event::{OpenWebRxCloseReason, OpenWebRxEvent},
+    message::OpenWebRxServerMessage,
+};
+
+pub use self::message::OpenWebRxClientMessage;
+
+/// Audio sample rate requested from the server.
+pub const OUTPUT_RATE: u32 = 12000;
+
+/// Binary message types, given by the first byte.
+const AUDIO: u8 = 0x02;
+
+pub struct OpenWebRx {
+    cancellation_token: CancellationToken,
+    event_channel_rx: Option<tokio::sync::mpsc::Receiver<OpenWebRxEvent>>,
+    message_channel_tx: Option<tokio::sync::mpsc::Sender<OpenWebRxClientMessage>>,
+    endpoint: Url,
+}
+
+impl OpenWebRx {
+    pub fn new(endpoint: Url) -> Self {
+        Self {
+            cancellation_token: CancellationToken::new(),
+            event_channel_rx: None,
+            message_channel_tx: None,
+            endpoint,
+        }
+    }
+
+    pub async fn connect(&mut self) -> anyhow::Result<()> {
+        let url = self.endpoint.join("ws/")?;
+        log::debug!("Connecting to OpenWebRX at {}", url);
+
+        let connect = tokio_tungstenite::connect_async(url.as_str());
+        let (ws_socket, _) = tokio::time::timeout(Duration::from_secs(2), connect)
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection timeout"))??;
+
+        let (mut write, mut read) = ws_socket.split();
+        for msg in [
+            OpenWebRxClientMessage::Handshake,
+            OpenWebRxClientMessage::ConnectionProperties {
+                output_rate: OUTPUT_RATE,
+                hd_output_rate: 4 * OUTPUT_RATE,
+            },
+            OpenWebRxClientMessage::StartDsp,
+        ] {
+            write.send(msg.into()).await?;
+        }
+
+        // Create event channels
+        let (event_tx, event_rx) = tokio::sync::mpsc::channel::<OpenWebRxEvent>(100);
+        let (msg_tx, mut msg_rx) = tokio::sync::mpsc::channel::<OpenWebRxClientMessage>(100);
+        self.event_channel_rx = Some(event_rx);
+        self.message_channel_tx = Some(msg_tx);
+
+        self.cancellation_token = CancellationToken::new();
+        let token = self.cancellation_token.clone();
+        let endpoint = self.endpoint.clone();
+        tokio::spawn(async move {
+            log::debug!("starting event loop for OpenWebRX at {}", endpoint);
+            let mut decoder = SyncedAdpcmDecoder::new();
+            let mut compressed = true;
+
+            let read_loop = async {
+                while let Some(msg) = read.next().await {
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            log::error!("Error reading message: {:?}", e);
+                            break;
+                        }
+                    };
+
+                    let event = match msg {
+                        Message::Text(text) => match OpenWebRxServerMessage::from(text) {
+                            OpenWebRxServerMessage::Config {
+                                center_frequency,
+                                sample_rate,
+                                audio_compression,
+                            } => {
+                                if let Some(compression) = audio_compression {
+                                    compressed = compression == "adpcm";
+                                }
+                                OpenWebRxEvent::Config {
+                                    center_frequency,
+                                    sample_rate,
+                                }
+                            }
+                            OpenWebRxServerMessage::Smeter(level) => OpenWebRxEvent::Smeter(level),
+                            OpenWebRxServerMessage::Unknown(msg) => OpenWebRxEvent::Message(msg),
+                        },
+                        Message::Binary(bin) if bin.first() == Some(&AUDIO) => {
+                            let data = &bin[1..];
+                            let samples = if compressed {
+                                decoder.decode(data)
+                            } else {
+                                data.chunks_exact(2).map(LittleEndian::read_i16).collect()
+                            };
+                            OpenWebRxEvent::Audio(samples)
+                        }
+                        Message::Close(_close) => {
+                            let _ = event_tx
+                                .send(OpenWebRxEvent::Close(OpenWebRxCloseReason::ServerClosed))
+                                .await;
+                            return;
+                        }
+                        _ => continue,
+                    };
+
+                    if event_tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+
+                let _ = event_tx
+                    .send(OpenWebRxEvent::Close(OpenWebRxCloseReason::ConnectionLost))
+                    .await;
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = read_loop => {
+                    token.cancel();
+                }
+            };
+        });
+
+        let token = self.cancellation_token.clone();
+        let endpoint = self.endpoint.clone();
+        tokio::spawn(async move {
+            log::debug!("starting message loop for OpenWebRX at {}", endpoint);
+            let write_loop = async {
+                while let Some(msg) = msg_rx.recv().await {
+                    log::debug!("Sending message: {:?}", msg);
+                    if let Err(e) = write.send(msg.into()).await {
+                        log::error!("Error sending message: {:?}", e);
+                        break;
+                    }
+                }
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = write_loop => {}
+            };
+        });
+
+        Ok(())
+    }
+
+    pub async fn read_event(&mut self, timeout: Duration) -> Option<OpenWebRxEvent> {
+        let rx = self.event_channel_rx.as_mut()?;
+
+        tokio::time::timeout(timeout, rx.recv())
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn send_message(&self, message: OpenWebRxClientMessage) -> anyhow::Result<()> {
+        self.message_channel_tx
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("not connected"))?
+            .send(message)
+            .await?;
+        Ok(())
+    }
+
+    fn shutdown(&self) -> anyhow::Result<()> {
+        log::debug!("Shutting down OpenWebRX");
+        self.cancellation_token.cancel();
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use colored::Colorize;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::{
+    audio::{AudioStream, WriterPool},
+    config::SinkConfig,
+    events::{EventBus, EventKind, StationState},
+    sdr::{
+        openwebrx::{
+            event::OpenWebRxEvent, message::OpenWebRxClientMessage, OpenWebRx, OUTPUT_RATE,
+        },
+        scraper::{SDRScraper, ScraperOutputs, ScraperStats, ScraperStatus},
+        Tuning,
+    },
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(4);
+
+#[derive(Clone)]
+pub struct OpenWebRxScraperSettings {
+    pub name: String,
+    pub endpoint: Url,
+    /// Magic key, needed to move the receiver's center frequency.
+    pub key: Option<String>,
+    pub station: Tuning,
+    pub sinks: Vec<SinkConfig>,
+}
+
+/// The slice of spectrum the receiver is currently serving.
+#[derive(Clone, Copy, Default)]
+struct Band {
+    center_frequency: Option<f64>,
+    sample_rate: Option<f64>,
+}
+
+pub struct OpenWebRxScraper {
+    settings: OpenWebRxScraperSettings,
+    sdr: Arc<Mutex<OpenWebRx>>,
+    status: ScraperStatus,
+    token: CancellationToken,
+    band: Arc<std::sync::Mutex<Band>>,
+    outputs: ScraperOutputs,
+}
+
+impl OpenWebRxScraper {
+    pub fn new(
+        settings: OpenWebRxScraperSettings,
+        pool: &WriterPool,
+        events: &EventBus,
+    ) -> OpenWebRxScraper {
+        OpenWebRxScraper {
+            settings: settings.clone(),
+            sdr: Arc::new(Mutex::new(OpenWebRx::new(settings.endpoint))),
+            status: ScraperStatus::Stopped,
+            token: CancellationToken::new(),
+            band: Arc::new(std::sync::Mutex::new(Band::default())),
+            outputs: ScraperOutputs::new(
+                &settings.name,
+                settings.station,
+                &settings.sinks,
+                pool,
+                events,
+            ),
+        }
+    }
+
+    /// Sends `tuning` to the receiver, moving its center frequency first if the target is out of band.
+    async fn apply_tuning(
+        sdr: &OpenWebRx,
+        tuning: Tuning,
+        band: Band,
+        key: &Option<String>,
+    ) -> anyhow::Result<()> {
+        let (Some(center_frequency), Some(sample_rate)) = (band.center_frequency, band.sample_rate)
+        else {
+            // The tuning is applied once the receiver's config arrives
+            return Ok(());
+        };
+
+        if (tuning.frequency() - center_frequency).abs() > sample_rate / 2.0 {
+            sdr.send_message(OpenWebRxClientMessage::SetCenterFrequency {
+                frequency: tuning.frequency(),
+                key: key.clone(),
+            })
+            .await
+        } else {
+            sdr.send_message(OpenWebRxClientMessage::Tune {
+                tuning,
+                center_frequency,
+            })
+            .await
+        }
+    }
+
+    async fn run(
+        settings: OpenWebRxScraperSettings,
+        sdr: Arc<Mutex<OpenWebRx>>,
+        band: Arc<std::sync::Mutex<Band>>,
+        outputs: ScraperOutputs,
+    ) {
+        let mut reconnecting = false;
+        loop {
+            outputs.state.set(StationState::Connecting);
+            if let Err(e) = sdr.lock().await.connect().await {
+                log::error!("{}: failed to connect: {}", settings.name.red(), e);
+                if reconnecting {
+                    outputs.publish(EventKind::ReconnectFailed {
+                        error: e.to_string(),
+                    });
+                } else {
+                    outputs.publish(EventKind::Disconnected {
+                        reason: e.to_string(),
+                    });
+                }
+                outputs.state.set(StationState::Disconnected);
+                reconnecting = true;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+
+            if reconnecting {
+                log::info!("{}: reconnected", settings.name.green());
+                outputs.publish(EventKind::Reconnected);
+            }
+            outputs.state.set(StationState::Connected);
+            *band.lock().unwrap() = Band::default();
+
+            loop {
+                // Make sure SDR instance lock is dropped immediately after fetching the latest message
+                let event = sdr.lock().await.read_event(Duration::from_secs(1)).await;
+                let Some(event) = event else {
+                    continue;
+                };
+
+                match event {
+                    OpenWebRxEvent::Close(reason) => {
+                        log::error!("{}: connection closed: {:?}", settings.name.red(), reason);
+                        outputs.publish(EventKind::Disconnected {
+                            reason: format!("{:?}", reason),
+                        });
+                        outputs.state.set(StationState::Disconnected);
+                        break;
+                    }
+                    OpenWebRxEvent::Config {
+                        center_frequency,
+                        sample_rate,
+                    } => {
+                        let current = {
+                            let mut band = band.lock().unwrap();
+                            band.center_frequency = center_frequency.or(band.center_frequency);
+                            band.sample_rate = sample_rate.or(band.sample_rate);
+                            *band
+                        };
+                        if center_frequency.is_none() && sample_rate.is_none() {
+                            continue;
+                        }
+
+                        let sdr = sdr.lock().await;
+                        if let Err(e) =
+                            Self::apply_tuning(&sdr, outputs.tuning(), current, &settings.key).await
+                        {
+                            log::error!("{}: failed to tune: {}", settings.name.red(), e);
+                        }
+                    }
+                    OpenWebRxEvent::Audio(samples) => {
+                        if outputs.state.get().0 != StationState::Ready {
+                            log::info!("{} is ready at {} Hz", settings.name.green(), OUTPUT_RATE);
+                            outputs.state.set(StationState::Ready);
+                        }
+                        outputs.write(samples, OUTPUT_RATE).await;
+                    }
+                    OpenWebRxEvent::Smeter(level) => outputs.set_rssi(level),
+                    OpenWebRxEvent::Message(msg) => {
+                        log::debug!("{}: {:.100}", settings.name.blue(), msg);
+                        outputs.publish(EventKind::Message { message: msg });
+                    }
+                }
+            }
+
+            outputs.writer.close();
+            reconnecting = true;
+            log::info!("{}: reconnecting in 4...", settings.name.yellow());
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SDRScraper for OpenWebRxScraper {
+    async fn start(&mut self) -> anyhow::Result<()> {
+        if self.status == ScraperStatus::Running {
+            log::warn!("SDR for {} is already running", self.settings.name);
+            return Ok(());
+        }
+
+        log::debug!("starting scraper for {}", self.settings.name.green());
+        self.token = CancellationToken::new();
+
+        let token = self.token.clone();
+        let run = Self::run(
+            self.settings.clone(),
+            self.sdr.clone(),
+            self.band.clone(),
+            self.outputs.clone(),
+        );
+        let name = self.settings.name.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = run => {}
+                _ = token.cancelled() => {
+                    log::debug!("{}: event loop cancelled", name.yellow());
+                }
+            }
+        });
+
+        self.status = ScraperStatus::Running;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        log::debug!("Stopping scraper for {}", self.settings.name.green());
+
+        self.token.cancel();
+        self.sdr.lock().await.shutdown()?;
+        self.outputs.writer.close();
+        self.status = ScraperStatus::Stopped;
+        self.outputs.state.set(StationState::Stopped);
+
+        Ok(())
+    }
+
+    async fn tune(&mut self, tuning: Tuning) -> anyhow::Result<()> {
+        log::info!("{}: tuning to {}", self.settings.name.green(), tuning);
+        self.outputs.set_tuning(tuning.clone());
+
+        if self.status == ScraperStatus::Running {
+            let band = *self.band.lock().unwrap();
+            let sdr = self.sdr.lock().await;
+            Self::apply_tuning(&sdr, tuning, band, &self.settings.key).await?;
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> ScraperStatus {
+        self.status.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    fn get_stats(&self) -> ScraperStats {
+        self.outputs.stats(self.status.clone())
+    }
+
+    fn audio(&self) -> AudioStream {
+        self.outputs.audio.clone()
+    }
+}
@@ -0,0 +1,29 @@
+/// Commands understood by `rtl_tcp`, sent as a command byte followed by a big endian parameter.
+#[derive(Debug)]
+pub enum RtlTcpCommand {
+    Frequency(u32),
+    SampleRate(u32),
+    /// Manual gain if true, otherwise the tuner picks its own.
+    ManualGain(bool),
+    /// Tuner gain in tenths of a dB.
+    Gain(i32),
+    /// The RTL2832's digital AGC.
+    Agc(bool),
+}
+
+impl From<RtlTcpCommand> for [u8; 5] {
+    fn from(command: RtlTcpCommand) -> [u8; 5] {
+        let (code, param): (u8, u32) = match command {
+            RtlTcpCommand::Frequency(frequency) => (0x01, frequency),
+            RtlTcpCommand::SampleRate(rate) => (0x02, rate),
+            RtlTcpCommand::ManualGain(manual) => (0x03, manual as u32),
+            RtlTcpCommand::Gain(gain) => (0x04, gain as u32),
+            RtlTcpCommand::Agc(enabled) => (0x08, enabled as u32),
+        };
+
+        let mut bytes = [0; 5];
+        bytes[0] = code;
+        bytes[1..].copy_from_slice(&param.to_be_bytes());
+        bytes
+    }
+}
@@ -0,0 +1,145 @@
+mod message;
+mod scraper;
+
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+pub use message::RtlTcpCommand;
+pub use scraper::{RtlTcpScraper, RtlTcpScraperSettings};
+
+/// Bytes of IQ handed to the scraper at once, 25 ms at the default sample rate.
+const CHUNK_SIZE: usize = 48000;
+
+#[derive(Debug)]
+pub enum RtlTcpEvent {
+    Close,
+    /// Unsigned 8-bit interleaved IQ.
+    Samples(Vec<u8>),
+}
+
+pub struct RtlTcp {
+    cancellation_token: CancellationToken,
+    event_channel_rx: Option<tokio::sync::mpsc::Receiver<RtlTcpEvent>>,
+    message_channel_tx: Option<tokio::sync::mpsc::Sender<RtlTcpCommand>>,
+    endpoint: Url,
+}
+
+impl RtlTcp {
+    pub fn new(endpoint: Url) -> Self {
+        Self {
+            cancellation_token: CancellationToken::new(),
+            event_channel_rx: None,
+            message_channel_tx: None,
+            endpoint,
+        }
+    }
+
+    pub async fn connect(&mut self) -> anyhow::Result<()> {
+        let host = self
+            .endpoint
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("no host in {}", self.endpoint))?
+            .to_string();
+        let port = self.endpoint.port().unwrap_or(1234);
+        log::debug!("Connecting to rtl_tcp at {}:{}", host, port);
+
+        let connect = TcpStream::connect((host, port));
+        let mut socket = tokio::time::timeout(Duration::from_secs(2), connect)
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection timeout"))??;
+
+        // Dongle info: magic, tuner type and number of gain steps
+        let mut header = [0; 12];
+        socket.read_exact(&mut header).await?;
+        if &header[..4] != b"RTL0" {
+            anyhow::bail!("not an rtl_tcp server");
+        }
+        log::info!(
+            "rtl_tcp tuner type {}, {} gain steps",
+            BigEndian::read_u32(&header[4..8]),
+            BigEndian::read_u32(&header[8..12])
+        );
+
+        let (mut read, mut write) = socket.into_split();
+
+        // Create event channels
+        let (event_tx, event_rx) = tokio::sync::mpsc::channel::<RtlTcpEvent>(100);
+        let (msg_tx, mut msg_rx) = tokio::sync::mpsc::channel::<RtlTcpCommand>(100);
+        self.event_channel_rx = Some(event_rx);
+        self.message_channel_tx = Some(msg_tx);
+
+        self.cancellation_token = CancellationToken::new();
+        let token = self.cancellation_token.clone();
+        tokio::spawn(async move {
+            let read_loop = async {
+                loop {
+                    let mut chunk = vec![0; CHUNK_SIZE];
+                    if let Err(e) = read.read_exact(&mut chunk).await {
+                        log::error!("Error reading samples: {:?}", e);
+                        break;
+                    }
+                    if event_tx.send(RtlTcpEvent::Samples(chunk)).await.is_err() {
+                        return;
+                    }
+                }
+                let _ = event_tx.send(RtlTcpEvent::Close).await;
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = read_loop => {
+                    token.cancel();
+                }
+            };
+        });
+
+        let token = self.cancellation_token.clone();
+        tokio::spawn(async move {
+            let write_loop = async {
+                while let Some(command) = msg_rx.recv().await {
+                    log::debug!("Sending command: {:?}", command);
+                    let bytes: [u8; 5] = command.into();
+                    if let Err(e) = write.write_all(&bytes).await {
+                        log::error!("Error sending command: {:?}", e);
+                        break;
+                    }
+                }
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = write_loop => {}
+            };
+        });
+
+        Ok(())
+    }
+
+    pub async fn read_event(&mut self, timeout: Duration) -> Option<RtlTcpEvent> {
+        let rx = self.event_channel_rx.as_mut()?;
+
+        tokio::time::timeout(timeout, rx.recv())
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn send_command(&self, command: RtlTcpCommand) -> anyhow::Result<()> {
+        self.message_channel_tx
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("not connected"))?
+            .send(command)
+            .await?;
+        Ok(())
+    }
+
+    fn shutdown(&self) -> anyhow::Result<()> {
+        log::debug!("Shutting down rtl_tcp");
+        self.cancellation_token.cancel();
+        Ok(())
+    }
+}
@@ -0,0 +1,397 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use colored::Colorize;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::{
+    audio::{AudioStream, WriterPool},
+    config::SinkConfig,
+    dsp::{iq_from_u8, Demodulator, AUDIO_RATE},
+    events::{EventBus, EventKind, StationState},
+    sdr::{
+        rtl_tcp::{RtlTcp, RtlTcpCommand, RtlTcpEvent},
+        scraper::{SDRScraper, ScraperOutputs, ScraperStats, ScraperStatus},
+        Tuning,
+    },
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(4);
+
+const SAMPLE_RATE: u32 = 960_000;
+
+/// The dongle is tuned this far above the wanted frequency, keeping it clear of the DC spike.
+const TUNING_OFFSET: f64 = 100_000.0;
+
+/// Audio is collected into frames of at least this many samples before going to the sinks.
+const FRAME_SIZE: usize = AUDIO_RATE as usize / 10;
+
+#[derive(Clone)]
+pub struct RtlTcpScraperSettings {
+    pub name: String,
+    pub endpoint: Url,
+    /// Tuner gain in tenths of a dB, or automatic if unset.
+    pub gain: Option<i32>,
+    pub agc: bool,
+    pub station: Tuning,
+    pub sinks: Vec<SinkConfig>,
+}
+
+pub struct RtlTcpScraper {
+    settings: RtlTcpScraperSettings,
+    sdr: Arc<Mutex<RtlTcp>>,
+    status: ScraperStatus,
+    token: CancellationToken,
+    outputs: ScraperOutputs,
+}
+
+impl RtlTcpScraper {
+    pub fn new(
+        settings: RtlTcpScraperSettings,
+        pool: &WriterPool,
+        events: &EventBus,
+    ) -> RtlTcpScraper {
+        RtlTcpScraper {
+            settings: settings.clone(),
+            sdr: Arc::new(Mutex::new(RtlTcp::new(settings.endpoint))),
+            status: ScraperStatus::Stopped,
+            token: CancellationToken::new(),
+            outputs: ScraperOutputs::new(
+                &settings.name,
+                settings.station,
+                &settings.sinks,
+                pool,
+                events,
+            ),
+        }
+    }
+
+    async fn configure(
+        sdr: &RtlTcp,
+        settings: &RtlTcpScraperSettings,
+        tuning: &Tuning,
+    ) -> anyhow::Result<()> {
+        sdr.send_command(RtlTcpCommand::SampleRate(SAMPLE_RATE))
+            .await?;
+        sdr.send_command(RtlTcpCommand::Frequency(
+            (tuning.frequency() + TUNING_OFFSET) as u32,
+        ))
+        .await?;
+        sdr.send_command(RtlTcpCommand::ManualGain(settings.gain.is_some()))
+            .await?;
+        if let Some(gain) = settings.gain {
+            sdr.send_command(RtlTcpCommand::Gain(gain)).await?;
+        }
+        sdr.send_command(RtlTcpCommand::Agc(settings.agc)).await?;
+        Ok(())
+    }
+
+    async fn run(
+        settings: RtlTcpScraperSettings,
+        sdr: Arc<Mutex<RtlTcp>>,
+        outputs: ScraperOutputs,
+    ) {
+        let mut reconnecting = false;
+        loop {
+            outputs.state.set(StationState::Connecting);
+            let mut tuning = outputs.tuning();
+            let connected = async {
+                let mut sdr = sdr.lock().await;
+                sdr.connect().await?;
+                Self::configure(&sdr, &settings, &tuning).await
+            };
+            if let Err(e) = connected.await {
+                log::error!("{}: failed to connect: {}", settings.name.red(), e);
+                if reconnecting {
+                    outputs.publish(EventKind::ReconnectFailed {
+                        error: e.to_string(),
+                    });
+                } else {
+                    outputs.publish(EventKind::Disconnected {
+                        reason: e.to_string(),
+                    });
+                }
+                outputs.state.set(StationState::Disconnected);
+                reconnecting = true;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+
+            if reconnecting {
+                log::info!("{}: reconnected", settings.name.green());
+                outputs.publish(EventKind::Reconnected);
+            }
+            log::info!("{} is ready at {} Hz", settings.name.green(), AUDIO_RATE);
+            outputs.state.set(StationState::Ready);
+
+            let mut demodulator = Demodulator::new(SAMPLE_RATE as f64, -TUNING_OFFSET, &tuning);
+            let mut audio = Vec::with_capacity(2 * FRAME_SIZE);
+            loop {
+                // Make sure SDR instance lock is dropped immediately after fetching the latest message
+                let event = sdr.lock().await.read_event(Duration::from_secs(1)).await;
+                let Some(event) = event else {
+                    continue;
+                };
+
+                match event {
+                    RtlTcpEvent::Close => {
+                        log::error!("{}: server closed connection", settings.name.red());
+                        outputs.publish(EventKind::Disconnected {
+                            reason: "ServerClosed".to_string(),
+                        });
+                        outputs.state.set(StationState::Disconnected);
+                        break;
+                    }
+                    RtlTcpEvent::Samples(data) => {
+                        let current = outputs.tuning();
+                        if current != tuning {
+                            tuning = current;
+                            demodulator =
+                                Demodulator::new(SAMPLE_RATE as f64, -TUNING_OFFSET, &tuning);
+                            let sent = sdr
+                                .lock()
+                                .await
+                                .send_command(RtlTcpCommand::Frequency(
+                                    (tuning.frequency() + TUNING_OFFSET) as u32,
+                                ))
+                                .await;
+                            if let Err(e) = sent {
+                                log::error!("{}: failed to tune: {}", settings.name.red(), e);
+                            }
+                        }
+
+                        // Demodulation is too heavy for the async runtime
+                        let demodulated = tokio::task::spawn_blocking(move || {
+                            let result = demodulator.process(&iq_from_u8(&data));
+                            (demodulator, result)
+                        })
+                        .await;
+                        let (samples, power);
+                        (demodulator, (samples, power)) = demodulated.unwrap();
+
+                        outputs.set_rssi(power);
+                        audio.extend(samples);
+                        if audio.len() >= FRAME_SIZE {
+                            outputs.write(std::mem::take(&mut audio), AUDIO_RATE).await;
+                        }
+                    }
+                }
+            }
+
+            outputs.writer.close();
+            reconnecting = true;
+            log::info!("{}: reconnecting in 4...", settings.name.yellow());
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SDRScraper for RtlTcpScraper {
+    async fn start(&mut self) -> anyhow::Result<()> {
+        if self.status == ScraperStatus::Running {
+            log::warn!("SDR for {} is already running", self.settings.name);
+            return Ok(());
+        }
+
+        log::debug!("starting scraper for {}", self.settings.name.green());
+        self.token = CancellationToken::new();
+
+        let token = self.token.clone();
+        let run = Self::run(
+            self.settings.clone(),
+            self.sdr.clone(),
+            self.outputs.clone(),
+        );
+        let name = self.settings.name.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = run => {}
+                _ = token.cancelled() => {
+                    log::debug!("{}: event loop cancelled", name.yellow());
+                }
+            }
+        });
+
+        self.status = ScraperStatus::Running;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        log::debug!("Stopping scraper for {}", self.settings.name.green());
+
+        self.token.cancel();
+        self.sdr.lock().await.shutdown()?;
+        self.outputs.writer.close();
+        self.status = ScraperStatus::Stopped;
+        self.outputs.state.set(StationState::Stopped);
+
+        Ok(())
+    }
+
+    /// Picked up by the running scraper with the next block of samples.
+    async fn tune(&mut self, tuning: Tuning) -> anyhow::Result<()> {
+        log::info!("{}: tuning to {}", self.settings.name.green(), tuning);
+        self.outputs.set_tuning(tuning);
+        Ok(())
+    }
+
+    fn status(&self) -> ScraperStatus {
+        self.status.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    fn get_stats(&self) -> ScraperStats {
+        self.outputs.stats(self.status.clone())
+    }
+
+    fn audio(&self) -> AudioStream {
+        self.outputs.audio.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::audio::OverflowPolicy;
+    use crate::sdr::Mode;
+
+    const FREQUENCY: f64 = 7_100_000.0;
+    /// The carrier the server sends, in Hz above `FREQUENCY`.
+    const TONE: f64 = 1000.0;
+    /// IQ samples the server sends at once.
+    const CHUNK_SAMPLES: usize = 24_000;
+
+    /// Serves one client a dongle header, then a carrier at `FREQUENCY + TONE` for as long as it
+    /// reads, reporting the commands it sends.
+    async fn serve(listener: TcpListener, commands: mpsc::UnboundedSender<[u8; 5]>) {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (mut read, mut write) = socket.into_split();
+        tokio::spawn(async move {
+            let mut command = [0; 5];
+            while read.read_exact(&mut command).await.is_ok() {
+                let _ = commands.send(command);
+            }
+        });
+
+        let mut header = b"RTL0".to_vec();
+        header.extend_from_slice(&5u32.to_be_bytes());
+        header.extend_from_slice(&29u32.to_be_bytes());
+        write.write_all(&header).await.unwrap();
+
+        // The dongle is tuned `TUNING_OFFSET` above, so the carrier is below the middle
+        let offset = TONE - TUNING_OFFSET;
+        let mut n = 0u64;
+        loop {
+            let chunk = (0..CHUNK_SAMPLES)
+                .flat_map(|_| {
+                    let phase = std::f64::consts::TAU * offset * n as f64 / SAMPLE_RATE as f64;
+                    n += 1;
+                    [
+                        (127.5 + 100.0 * phase.cos()) as u8,
+                        (127.5 + 100.0 * phase.sin()) as u8,
+                    ]
+                })
+                .collect::<Vec<u8>>();
+            if write.write_all(&chunk).await.is_err() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    async fn next_command(commands: &mut mpsc::UnboundedReceiver<[u8; 5]>) -> [u8; 5] {
+        tokio::time::timeout(Duration::from_secs(5), commands.recv())
+            .await
+            .expect("no command")
+            .unwrap()
+    }
+
+    fn command(code: u8, param: u32) -> [u8; 5] {
+        let mut bytes = [code, 0, 0, 0, 0];
+        bytes[1..].copy_from_slice(&param.to_be_bytes());
+        bytes
+    }
+
+    #[tokio::test]
+    async fn tunes_and_demodulates() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (commands_tx, mut commands) = mpsc::unbounded_channel();
+        tokio::spawn(serve(listener, commands_tx));
+
+        let pool = WriterPool::new(1, 64, OverflowPolicy::Block, 4096);
+        let events = EventBus::new();
+        let mut scraper = RtlTcpScraper::new(
+            RtlTcpScraperSettings {
+                name: "test".to_string(),
+                endpoint: Url::parse(&format!("rtl-tcp://127.0.0.1:{}", port)).unwrap(),
+                gain: Some(150),
+                agc: false,
+                station: Mode::USB.tuning(FREQUENCY, None, None),
+                sinks: Vec::new(),
+            },
+            &pool,
+            &events,
+        );
+        let mut audio = scraper.audio().subscribe();
+        scraper.start().await.unwrap();
+
+        let tuned = (FREQUENCY + TUNING_OFFSET) as u32;
+        assert_eq!(
+            next_command(&mut commands).await,
+            command(0x02, SAMPLE_RATE)
+        );
+        assert_eq!(next_command(&mut commands).await, command(0x01, tuned));
+        assert_eq!(next_command(&mut commands).await, command(0x03, 1));
+        assert_eq!(next_command(&mut commands).await, command(0x04, 150));
+        assert_eq!(next_command(&mut commands).await, command(0x08, 0));
+
+        // Skips the filters settling, then counts zero crossings over half a second
+        let mut samples = Vec::new();
+        while samples.len() < AUDIO_RATE as usize * 3 / 5 {
+            let frame = tokio::time::timeout(Duration::from_secs(5), audio.recv())
+                .await
+                .expect("no audio")
+                .unwrap();
+            assert_eq!(frame.sample_rate, AUDIO_RATE);
+            samples.extend_from_slice(&frame.samples);
+        }
+        let settled = &samples[AUDIO_RATE as usize / 10..];
+        let crossings = settled
+            .windows(2)
+            .filter(|pair| (pair[0] < 0) != (pair[1] < 0))
+            .count();
+        let frequency = crossings as f64 / 2.0 / (settled.len() as f64 / AUDIO_RATE as f64);
+        assert!(
+            (frequency - TONE).abs() < 20.0,
+            "demodulated a {} Hz tone",
+            frequency
+        );
+        let peak = settled.iter().map(|sample| sample.unsigned_abs()).max();
+        assert!(peak.unwrap() > 1000);
+
+        scraper
+            .tune(Mode::USB.tuning(FREQUENCY + 5000.0, None, None))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_command(&mut commands).await,
+            command(0x01, tuned + 5000)
+        );
+
+        scraper.stop().await.unwrap();
+        tokio::task::spawn_blocking(move || pool.shutdown())
+            .await
+            .unwrap();
+    }
+}
@@ -1,16 +1,162 @@
-use super::kiwi::KiwiScraperStats;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
+};
 
-#[derive(Eq, PartialEq, Clone)]
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::{build_sinks, AudioFrame, AudioStream, WriterHandle, WriterPool},
+    config::SinkConfig,
+    events::{EventBus, EventKind, StateTracker, StationState},
+    sdr::Tuning,
+    spectrum::SurveyTable,
+};
+
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
 pub enum ScraperStatus {
     Running,
     Stopped,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScraperStats {
+    pub name: String,
+    pub status: ScraperStatus,
+    pub state: StationState,
+    /// When the station entered `state`.
+    pub since: DateTime<Utc>,
+    pub tuning: Tuning,
+    pub rssi: f64,
+    pub queue_depth: usize,
+    pub queue_overflows: u64,
+}
+
 #[async_trait::async_trait]
-pub trait SDRScraper {
+pub trait SDRScraper: Send {
     async fn start(&mut self) -> anyhow::Result<()>;
     async fn stop(&mut self) -> anyhow::Result<()>;
+    /// Changes the frequency and mode, taking effect immediately if the scraper is running.
+    async fn tune(&mut self, tuning: Tuning) -> anyhow::Result<()>;
     fn status(&self) -> ScraperStatus;
     fn name(&self) -> &str;
-    fn get_stats(&self) -> KiwiScraperStats;
+    fn get_stats(&self) -> ScraperStats;
+    /// Live feed of the frames going to the station's sinks.
+    fn audio(&self) -> AudioStream;
+    /// Results of the band survey, for stations that run one.
+    fn survey(&self) -> Option<SurveyTable> {
+        None
+    }
+    /// Connections open to the receiver right now, each of which a Kiwi counts as a user.
+    fn connections(&self) -> usize {
+        matches!(
+            self.get_stats().state,
+            StationState::Connected | StationState::Ready
+        ) as usize
+    }
+}
+
+#[derive(Debug)]
+pub struct AtomicF64 {
+    storage: AtomicU64,
+}
+impl AtomicF64 {
+    pub fn new(value: f64) -> Self {
+        let as_u64 = value.to_bits();
+        Self {
+            storage: AtomicU64::new(as_u64),
+        }
+    }
+    pub fn store(&self, value: f64, ordering: Ordering) {
+        let as_u64 = value.to_bits();
+        self.storage.store(as_u64, ordering)
+    }
+    pub fn load(&self, ordering: Ordering) -> f64 {
+        let as_u64 = self.storage.load(ordering);
+        f64::from_bits(as_u64)
+    }
+}
+
+/// The parts every scraper shares regardless of the receiver it talks to: the station's sinks, its live
+/// audio and events, and the numbers behind its stats.
+#[derive(Clone)]
+pub struct ScraperOutputs {
+    pub name: String,
+    pub writer: WriterHandle,
+    pub audio: AudioStream,
+    pub events: EventBus,
+    pub state: StateTracker,
+    tuning: Arc<RwLock<Tuning>>,
+    /// NaN until the receiver has given an S-meter reading.
+    rssi: Arc<AtomicF64>,
+}
+
+impl ScraperOutputs {
+    pub fn new(
+        name: &str,
+        tuning: Tuning,
+        sinks: &[SinkConfig],
+        pool: &WriterPool,
+        events: &EventBus,
+    ) -> Self {
+        let audio = AudioStream::new();
+        let mut sinks = build_sinks(name, sinks, events);
+        sinks.push(Box::new(audio.clone()));
+
+        ScraperOutputs {
+            name: name.to_string(),
+            writer: pool.register(name, sinks),
+            audio,
+            events: events.clone(),
+            state: StateTracker::new(name, events),
+            tuning: Arc::new(RwLock::new(tuning)),
+            rssi: Arc::new(AtomicF64::new(f64::NAN)),
+        }
+    }
+
+    pub fn tuning(&self) -> Tuning {
+        self.tuning.read().unwrap().clone()
+    }
+
+    pub fn set_tuning(&self, tuning: Tuning) {
+        *self.tuning.write().unwrap() = tuning;
+    }
+
+    pub fn set_rssi(&self, rssi: f64) {
+        self.rssi.store(rssi, Ordering::Relaxed);
+        self.publish(EventKind::Rssi { rssi });
+    }
+
+    pub fn publish(&self, kind: EventKind) {
+        self.events.publish(&self.name, kind);
+    }
+
+    /// Hands freshly received audio to the station's sinks, tagged with the current tuning.
+    pub async fn write(&self, samples: Vec<i16>, sample_rate: u32) {
+        let frame = AudioFrame {
+            samples: samples.into(),
+            sample_rate,
+            timestamp: Utc::now(),
+            tuning: self.tuning(),
+            rssi: Some(self.rssi.load(Ordering::Relaxed)).filter(|rssi| !rssi.is_nan()),
+        };
+        self.writer.write(frame).await;
+    }
+
+    pub fn stats(&self, status: ScraperStatus) -> ScraperStats {
+        let (state, since) = self.state.get();
+        ScraperStats {
+            name: self.name.clone(),
+            status,
+            state,
+            since,
+            tuning: self.tuning(),
+            rssi: Some(self.rssi.load(Ordering::Relaxed))
+                .filter(|rssi| !rssi.is_nan())
+                .unwrap_or_default(),
+            queue_depth: self.writer.queue_depth(),
+            queue_overflows: self.writer.overflows(),
+        }
+    }
 }
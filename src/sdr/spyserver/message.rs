@@ -0,0 +1,143 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Protocol 2.0.1700, the version current SpyServer releases speak.
+pub const PROTOCOL_VERSION: u32 = (2 << 24) | 1700;
+
+/// Size of the header in front of every server message.
+pub const HEADER_SIZE: usize = 20;
+
+const CMD_HELLO: u32 = 0;
+const CMD_SET_SETTING: u32 = 2;
+
+const MSG_TYPE_DEVICE_INFO: u32 = 0;
+const MSG_TYPE_CLIENT_SYNC: u32 = 1;
+const MSG_TYPE_UINT8_IQ: u32 = 100;
+const MSG_TYPE_INT16_IQ: u32 = 101;
+
+/// Value of `Setting::StreamingMode` that streams IQ only.
+pub const STREAM_MODE_IQ_ONLY: u32 = 1;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Setting {
+    StreamingMode = 0,
+    StreamingEnabled = 1,
+    /// Index into the device's gain table, only honoured for clients that can control the device.
+    Gain = 2,
+    IqFormat = 100,
+    IqFrequency = 101,
+    /// Power of two the device's maximum sample rate is divided by.
+    IqDecimation = 102,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamFormat {
+    Uint8 = 1,
+    Int16 = 2,
+}
+
+#[derive(Debug)]
+pub enum SpyServerCommand {
+    Hello,
+    SetSetting(Setting, u32),
+}
+
+impl From<SpyServerCommand> for Vec<u8> {
+    fn from(command: SpyServerCommand) -> Vec<u8> {
+        let (code, body) = match command {
+            SpyServerCommand::Hello => {
+                let mut body = PROTOCOL_VERSION.to_le_bytes().to_vec();
+                body.extend_from_slice(b"sdr-scraper");
+                (CMD_HELLO, body)
+            }
+            SpyServerCommand::SetSetting(setting, value) => {
+                let mut body = (setting as u32).to_le_bytes().to_vec();
+                body.extend_from_slice(&value.to_le_bytes());
+                (CMD_SET_SETTING, body)
+            }
+        };
+
+        let mut bytes = Vec::with_capacity(8 + body.len());
+        bytes.extend_from_slice(&code.to_le_bytes());
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+}
+
+/// Fixed capabilities of the receiver, sent once after the hello.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceInfo {
+    pub device_type: u32,
+    pub serial: u32,
+    pub maximum_sample_rate: u32,
+    pub decimation_stages: u32,
+    pub maximum_gain_index: u32,
+    pub minimum_frequency: u32,
+    pub maximum_frequency: u32,
+    pub minimum_iq_decimation: u32,
+    /// Non-zero if the server only streams IQ in this format.
+    pub forced_iq_format: u32,
+}
+
+/// The device's shared state, sent after the hello and whenever it changes.
+#[derive(Debug, Clone, Default)]
+pub struct ClientSync {
+    pub can_control: bool,
+    pub device_center_frequency: u32,
+    pub minimum_iq_center_frequency: u32,
+    pub maximum_iq_center_frequency: u32,
+}
+
+#[derive(Debug)]
+pub enum SpyServerMessage {
+    DeviceInfo(DeviceInfo),
+    ClientSync(ClientSync),
+    Samples { format: StreamFormat, data: Vec<u8> },
+    Other(u32),
+}
+
+/// Body size announced by a message header.
+pub fn body_size(header: &[u8; HEADER_SIZE]) -> usize {
+    LittleEndian::read_u32(&header[16..20]) as usize
+}
+
+impl SpyServerMessage {
+    pub fn parse(header: &[u8; HEADER_SIZE], body: Vec<u8>) -> anyhow::Result<SpyServerMessage> {
+        // The upper half of the message type carries flags
+        let message_type = LittleEndian::read_u32(&header[4..8]) & 0xffff;
+        let field = |index: usize| -> anyhow::Result<u32> {
+            body.get(index * 4..index * 4 + 4)
+                .map(LittleEndian::read_u32)
+                .ok_or_else(|| anyhow::anyhow!("message {} is too short", message_type))
+        };
+
+        Ok(match message_type {
+            MSG_TYPE_DEVICE_INFO => SpyServerMessage::DeviceInfo(DeviceInfo {
+                device_type: field(0)?,
+                serial: field(1)?,
+                maximum_sample_rate: field(2)?,
+                decimation_stages: field(4)?,
+                maximum_gain_index: field(6)?,
+                minimum_frequency: field(7)?,
+                maximum_frequency: field(8)?,
+                minimum_iq_decimation: field(10)?,
+                forced_iq_format: field(11)?,
+            }),
+            MSG_TYPE_CLIENT_SYNC => SpyServerMessage::ClientSync(ClientSync {
+                can_control: field(0)? != 0,
+                device_center_frequency: field(2)?,
+                minimum_iq_center_frequency: field(5)?,
+                maximum_iq_center_frequency: field(6)?,
+            }),
+            MSG_TYPE_UINT8_IQ => SpyServerMessage::Samples {
+                format: StreamFormat::Uint8,
+                data: body,
+            },
+            MSG_TYPE_INT16_IQ => SpyServerMessage::Samples {
+                format: StreamFormat::Int16,
+                data: body,
+            },
+            other => SpyServerMessage::Other(other),
+        })
+    }
+}
@@ -0,0 +1,196 @@
+mod message;
+mod scraper;
+
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+pub use message::{
+    ClientSync, DeviceInfo, Setting, SpyServerCommand, StreamFormat, STREAM_MODE_IQ_ONLY,
+};
+pub use scraper::{SpyServerScraper, SpyServerScraperSettings};
+
+use message::{body_size, SpyServerMessage, HEADER_SIZE};
+
+/// Largest message body accepted, well above the biggest IQ block a server sends.
+const MAX_BODY_SIZE: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum SpyServerEvent {
+    Close,
+    /// The device's shared state changed, e.g. another client moved its center frequency.
+    Sync(ClientSync),
+    Samples {
+        format: StreamFormat,
+        data: Vec<u8>,
+    },
+}
+
+pub struct SpyServer {
+    cancellation_token: CancellationToken,
+    event_channel_rx: Option<tokio::sync::mpsc::Receiver<SpyServerEvent>>,
+    message_channel_tx: Option<tokio::sync::mpsc::Sender<SpyServerCommand>>,
+    endpoint: Url,
+}
+
+async fn read_message(read: &mut OwnedReadHalf) -> anyhow::Result<SpyServerMessage> {
+    let mut header = [0; HEADER_SIZE];
+    read.read_exact(&mut header).await?;
+    let size = body_size(&header);
+    if size > MAX_BODY_SIZE {
+        anyhow::bail!("message body of {} bytes is too large", size);
+    }
+
+    let mut body = vec![0; size];
+    read.read_exact(&mut body).await?;
+    SpyServerMessage::parse(&header, body)
+}
+
+impl SpyServer {
+    pub fn new(endpoint: Url) -> Self {
+        Self {
+            cancellation_token: CancellationToken::new(),
+            event_channel_rx: None,
+            message_channel_tx: None,
+            endpoint,
+        }
+    }
+
+    /// Connects and says hello, returning the device info and initial sync the server answers with.
+    pub async fn connect(&mut self) -> anyhow::Result<(DeviceInfo, ClientSync)> {
+        let host = self
+            .endpoint
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("no host in {}", self.endpoint))?
+            .to_string();
+        let port = self.endpoint.port().unwrap_or(5555);
+        log::debug!("Connecting to SpyServer at {}:{}", host, port);
+
+        let connect = TcpStream::connect((host, port));
+        let mut socket = tokio::time::timeout(Duration::from_secs(2), connect)
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection timeout"))??;
+
+        let hello: Vec<u8> = SpyServerCommand::Hello.into();
+        socket.write_all(&hello).await?;
+
+        let (mut read, mut write) = socket.into_split();
+
+        let handshake = async {
+            let (mut device, mut sync) = (None, None);
+            while device.is_none() || sync.is_none() {
+                match read_message(&mut read).await? {
+                    SpyServerMessage::DeviceInfo(info) => device = Some(info),
+                    SpyServerMessage::ClientSync(state) => sync = Some(state),
+                    _ => {}
+                }
+            }
+            anyhow::Ok((device.unwrap(), sync.unwrap()))
+        };
+        let (device, sync) = tokio::time::timeout(Duration::from_secs(5), handshake)
+            .await
+            .map_err(|_| anyhow::anyhow!("no device info from server"))??;
+        log::info!(
+            "SpyServer device type {} serial {:08X}, {} S/s, {}-{} Hz, {}",
+            device.device_type,
+            device.serial,
+            device.maximum_sample_rate,
+            device.minimum_frequency,
+            device.maximum_frequency,
+            if sync.can_control {
+                "with control"
+            } else {
+                "without control"
+            }
+        );
+
+        // Create event channels
+        let (event_tx, event_rx) = tokio::sync::mpsc::channel::<SpyServerEvent>(100);
+        let (msg_tx, mut msg_rx) = tokio::sync::mpsc::channel::<SpyServerCommand>(100);
+        self.event_channel_rx = Some(event_rx);
+        self.message_channel_tx = Some(msg_tx);
+
+        self.cancellation_token = CancellationToken::new();
+        let token = self.cancellation_token.clone();
+        tokio::spawn(async move {
+            let read_loop = async {
+                loop {
+                    let event = match read_message(&mut read).await {
+                        Ok(SpyServerMessage::ClientSync(sync)) => SpyServerEvent::Sync(sync),
+                        Ok(SpyServerMessage::Samples { format, data }) => {
+                            SpyServerEvent::Samples { format, data }
+                        }
+                        Ok(SpyServerMessage::DeviceInfo(_)) => continue,
+                        Ok(SpyServerMessage::Other(message_type)) => {
+                            log::trace!("Ignoring message type {}", message_type);
+                            continue;
+                        }
+                        Err(e) => {
+                            log::error!("Error reading message: {:?}", e);
+                            break;
+                        }
+                    };
+                    if event_tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                let _ = event_tx.send(SpyServerEvent::Close).await;
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = read_loop => {
+                    token.cancel();
+                }
+            };
+        });
+
+        let token = self.cancellation_token.clone();
+        tokio::spawn(async move {
+            let write_loop = async {
+                while let Some(command) = msg_rx.recv().await {
+                    log::debug!("Sending command: {:?}", command);
+                    let bytes: Vec<u8> = command.into();
+                    if let Err(e) = write.write_all(&bytes).await {
+                        log::error!("Error sending command: {:?}", e);
+                        break;
+                    }
+                }
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = write_loop => {}
+            };
+        });
+
+        Ok((device, sync))
+    }
+
+    pub async fn read_event(&mut self, timeout: Duration) -> Option<SpyServerEvent> {
+        let rx = self.event_channel_rx.as_mut()?;
+
+        tokio::time::timeout(timeout, rx.recv())
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn send_command(&self, command: SpyServerCommand) -> anyhow::Result<()> {
+        self.message_channel_tx
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("not connected"))?
+            .send(command)
+            .await?;
+        Ok(())
+    }
+
+    fn shutdown(&self) -> anyhow::Result<()> {
+        log::debug!("Shutting down SpyServer");
+        self.cancellation_token.cancel();
+        Ok(())
+    }
+}
@@ -0,0 +1,609 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use colored::Colorize;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::{
+    audio::{AudioStream, WriterPool},
+    config::SinkConfig,
+    dsp::{iq_from_i16, iq_from_u8, Demodulator, AUDIO_RATE},
+    events::{EventBus, EventKind, StationState},
+    sdr::{
+        scraper::{SDRScraper, ScraperOutputs, ScraperStats, ScraperStatus},
+        spyserver::{
+            ClientSync, DeviceInfo, Setting, SpyServer, SpyServerCommand, SpyServerEvent,
+            StreamFormat, STREAM_MODE_IQ_ONLY,
+        },
+        Tuning,
+    },
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(4);
+
+/// Narrowest IQ bandwidth requested from the server, enough for any channel.
+const CHANNEL_WIDTH: f64 = 48000.0;
+
+/// Servers send small IQ blocks, so audio is batched into 100 ms frames for the sinks.
+const FRAME_SIZE: usize = AUDIO_RATE as usize / 10;
+
+#[derive(Clone)]
+pub struct SpyServerScraperSettings {
+    pub name: String,
+    pub endpoint: Url,
+    /// Gain index, only applied if the server lets us control the device.
+    pub gain: Option<i32>,
+    pub station: Tuning,
+    pub sinks: Vec<SinkConfig>,
+}
+
+/// The IQ stream requested from the server and the local demodulator for it.
+struct Channel {
+    tuning: Tuning,
+    center_frequency: u32,
+    decimation: u32,
+    demodulator: Demodulator,
+}
+
+impl Channel {
+    /// Picks the narrowest IQ stream around `tuning` that still holds its whole channel.
+    fn new(device: &DeviceInfo, sync: &ClientSync, tuning: Tuning) -> Channel {
+        let frequency = tuning.frequency();
+        let center_frequency = if sync.can_control {
+            frequency
+        } else {
+            // Without control the IQ has to stay within the band the device is tuned to
+            frequency
+                .max(sync.minimum_iq_center_frequency as f64)
+                .min(sync.maximum_iq_center_frequency as f64)
+        };
+        let offset = frequency - center_frequency;
+
+        let sample_rate = |stage: u32| device.maximum_sample_rate as f64 / 2f64.powi(stage as i32);
+        let decimation = (device.minimum_iq_decimation..device.decimation_stages)
+            .rev()
+            .find(|&stage| {
+                sample_rate(stage) >= CHANNEL_WIDTH
+                    && 0.4 * sample_rate(stage) >= offset.abs() + CHANNEL_WIDTH / 2.0
+            })
+            .unwrap_or(device.minimum_iq_decimation);
+
+        Channel {
+            demodulator: Demodulator::new(sample_rate(decimation), offset, &tuning),
+            tuning,
+            center_frequency: center_frequency as u32,
+            decimation,
+        }
+    }
+
+    async fn apply(&self, sdr: &SpyServer) -> anyhow::Result<()> {
+        sdr.send_command(SpyServerCommand::SetSetting(
+            Setting::IqDecimation,
+            self.decimation,
+        ))
+        .await?;
+        sdr.send_command(SpyServerCommand::SetSetting(
+            Setting::IqFrequency,
+            self.center_frequency,
+        ))
+        .await
+    }
+}
+
+pub struct SpyServerScraper {
+    settings: SpyServerScraperSettings,
+    sdr: Arc<Mutex<SpyServer>>,
+    status: ScraperStatus,
+    token: CancellationToken,
+    outputs: ScraperOutputs,
+}
+
+impl SpyServerScraper {
+    pub fn new(
+        settings: SpyServerScraperSettings,
+        pool: &WriterPool,
+        events: &EventBus,
+    ) -> SpyServerScraper {
+        SpyServerScraper {
+            settings: settings.clone(),
+            sdr: Arc::new(Mutex::new(SpyServer::new(settings.endpoint))),
+            status: ScraperStatus::Stopped,
+            token: CancellationToken::new(),
+            outputs: ScraperOutputs::new(
+                &settings.name,
+                settings.station,
+                &settings.sinks,
+                pool,
+                events,
+            ),
+        }
+    }
+
+    /// Sets up IQ streaming for `channel` and starts it.
+    async fn configure(
+        sdr: &SpyServer,
+        settings: &SpyServerScraperSettings,
+        device: &DeviceInfo,
+        sync: &ClientSync,
+        channel: &Channel,
+    ) -> anyhow::Result<()> {
+        let format = match device.forced_iq_format {
+            1 => StreamFormat::Uint8,
+            _ => StreamFormat::Int16,
+        };
+        sdr.send_command(SpyServerCommand::SetSetting(
+            Setting::StreamingMode,
+            STREAM_MODE_IQ_ONLY,
+        ))
+        .await?;
+        sdr.send_command(SpyServerCommand::SetSetting(
+            Setting::IqFormat,
+            format as u32,
+        ))
+        .await?;
+        channel.apply(sdr).await?;
+        match settings.gain {
+            Some(gain) if sync.can_control => {
+                let gain = (gain.max(0) as u32).min(device.maximum_gain_index);
+                sdr.send_command(SpyServerCommand::SetSetting(Setting::Gain, gain))
+                    .await?;
+            }
+            Some(_) => log::warn!(
+                "{}: server does not allow control, ignoring gain",
+                settings.name.yellow()
+            ),
+            None => {}
+        }
+        sdr.send_command(SpyServerCommand::SetSetting(Setting::StreamingEnabled, 1))
+            .await
+    }
+
+    async fn run(
+        settings: SpyServerScraperSettings,
+        sdr: Arc<Mutex<SpyServer>>,
+        outputs: ScraperOutputs,
+    ) {
+        let mut reconnecting = false;
+        loop {
+            outputs.state.set(StationState::Connecting);
+            let connected = async {
+                let mut sdr = sdr.lock().await;
+                let (device, sync) = sdr.connect().await?;
+                let channel = Channel::new(&device, &sync, outputs.tuning());
+                Self::configure(&sdr, &settings, &device, &sync, &channel).await?;
+                anyhow::Ok((device, sync, channel))
+            };
+            let (device, mut sync, mut channel) = match connected.await {
+                Ok(connected) => connected,
+                Err(e) => {
+                    log::error!("{}: failed to connect: {}", settings.name.red(), e);
+                    if reconnecting {
+                        outputs.publish(EventKind::ReconnectFailed {
+                            error: e.to_string(),
+                        });
+                    } else {
+                        outputs.publish(EventKind::Disconnected {
+                            reason: e.to_string(),
+                        });
+                    }
+                    outputs.state.set(StationState::Disconnected);
+                    reconnecting = true;
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+
+            if reconnecting {
+                log::info!("{}: reconnected", settings.name.green());
+                outputs.publish(EventKind::Reconnected);
+            }
+            outputs.state.set(StationState::Connected);
+
+            let mut audio = Vec::with_capacity(2 * FRAME_SIZE);
+            loop {
+                // Make sure SDR instance lock is dropped immediately after fetching the latest message
+                let event = sdr.lock().await.read_event(Duration::from_secs(1)).await;
+                let Some(event) = event else {
+                    continue;
+                };
+
+                // Move the IQ stream if we were retuned, or if another client moved the device under us
+                let next = match &event {
+                    SpyServerEvent::Sync(state) => {
+                        log::debug!(
+                            "{}: device center now {} Hz",
+                            settings.name.blue(),
+                            state.device_center_frequency
+                        );
+                        sync = state.clone();
+                        Some(Channel::new(&device, &sync, outputs.tuning()))
+                    }
+                    _ if outputs.tuning() != channel.tuning => {
+                        Some(Channel::new(&device, &sync, outputs.tuning()))
+                    }
+                    _ => None,
+                };
+                if let Some(next) = next.filter(|next| {
+                    next.tuning != channel.tuning
+                        || next.center_frequency != channel.center_frequency
+                        || next.decimation != channel.decimation
+                }) {
+                    channel = next;
+                    if let Err(e) = channel.apply(&*sdr.lock().await).await {
+                        log::error!("{}: failed to tune: {}", settings.name.red(), e);
+                    }
+                }
+
+                match event {
+                    SpyServerEvent::Close => {
+                        log::error!("{}: server closed connection", settings.name.red());
+                        outputs.publish(EventKind::Disconnected {
+                            reason: "ServerClosed".to_string(),
+                        });
+                        outputs.state.set(StationState::Disconnected);
+                        break;
+                    }
+                    SpyServerEvent::Sync(_) => {}
+                    SpyServerEvent::Samples { format, data } => {
+                        if outputs.state.get().0 != StationState::Ready {
+                            log::info!("{} is ready at {} Hz", settings.name.green(), AUDIO_RATE);
+                            outputs.state.set(StationState::Ready);
+                        }
+
+                        // Demodulation is too heavy for the async runtime
+                        let demodulated = tokio::task::spawn_blocking(move || {
+                            let iq = match format {
+                                StreamFormat::Uint8 => iq_from_u8(&data),
+                                StreamFormat::Int16 => iq_from_i16(&data),
+                            };
+                            let result = channel.demodulator.process(&iq);
+                            (channel, result)
+                        })
+                        .await;
+                        let (samples, power);
+                        (channel, (samples, power)) = demodulated.unwrap();
+
+                        outputs.set_rssi(power);
+                        audio.extend(samples);
+                        if audio.len() >= FRAME_SIZE {
+                            outputs.write(std::mem::take(&mut audio), AUDIO_RATE).await;
+                        }
+                    }
+                }
+            }
+
+            outputs.writer.close();
+            reconnecting = true;
+            log::info!("{}: reconnecting in 4...", settings.name.yellow());
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SDRScraper for SpyServerScraper {
+    async fn start(&mut self) -> anyhow::Result<()> {
+        if self.status == ScraperStatus::Running {
+            log::warn!("SDR for {} is already running", self.settings.name);
+            return Ok(());
+        }
+
+        log::debug!("starting scraper for {}", self.settings.name.green());
+        self.token = CancellationToken::new();
+
+        let token = self.token.clone();
+        let run = Self::run(
+            self.settings.clone(),
+            self.sdr.clone(),
+            self.outputs.clone(),
+        );
+        let name = self.settings.name.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = run => {}
+                _ = token.cancelled() => {
+                    log::debug!("{}: event loop cancelled", name.yellow());
+                }
+            }
+        });
+
+        self.status = ScraperStatus::Running;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        log::debug!("Stopping scraper for {}", self.settings.name.green());
+
+        self.token.cancel();
+        self.sdr.lock().await.shutdown()?;
+        self.outputs.writer.close();
+        self.status = ScraperStatus::Stopped;
+        self.outputs.state.set(StationState::Stopped);
+
+        Ok(())
+    }
+
+    /// Picked up by the running scraper with the next message from the server.
+    async fn tune(&mut self, tuning: Tuning) -> anyhow::Result<()> {
+        log::info!("{}: tuning to {}", self.settings.name.green(), tuning);
+        self.outputs.set_tuning(tuning);
+        Ok(())
+    }
+
+    fn status(&self) -> ScraperStatus {
+        self.status.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    fn get_stats(&self) -> ScraperStats {
+        self.outputs.stats(self.status.clone())
+    }
+
+    fn audio(&self) -> AudioStream {
+        self.outputs.audio.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::audio::OverflowPolicy;
+    use crate::sdr::spyserver::message::PROTOCOL_VERSION;
+    use crate::sdr::Mode;
+
+    const FREQUENCY: f64 = 7_100_000.0;
+    /// The carrier the server sends, in Hz above `FREQUENCY`.
+    const TONE: f64 = 1000.0;
+    const MAXIMUM_SAMPLE_RATE: u32 = 6_000_000;
+    /// 6 MS/s over 2^6, the narrowest stream at least `CHANNEL_WIDTH` wide.
+    const DECIMATION: u32 = 6;
+    const IQ_RATE: f64 = MAXIMUM_SAMPLE_RATE as f64 / 64.0;
+    /// IQ samples the server sends at once.
+    const BLOCK_SAMPLES: usize = 4096;
+
+    fn message(message_type: u32, fields: &[u32]) -> Vec<u8> {
+        let body = fields
+            .iter()
+            .flat_map(|field| field.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let mut message = Vec::new();
+        message.extend(PROTOCOL_VERSION.to_le_bytes());
+        message.extend(message_type.to_le_bytes());
+        message.extend([0; 8]);
+        message.extend((body.len() as u32).to_le_bytes());
+        message.extend(body);
+        message
+    }
+
+    fn device_info() -> Vec<u8> {
+        // Type, serial, maximum rate, bandwidth, stages, unused, gains, frequency range,
+        // resolution, minimum decimation, forced format
+        message(
+            0,
+            &[
+                1,
+                0x1234_5678,
+                MAXIMUM_SAMPLE_RATE,
+                0,
+                9,
+                0,
+                21,
+                24_000_000,
+                1_800_000_000,
+                0,
+                0,
+                0,
+            ],
+        )
+    }
+
+    fn client_sync(can_control: bool) -> Vec<u8> {
+        message(
+            1,
+            &[
+                can_control as u32,
+                5,
+                100_000_000,
+                0,
+                0,
+                24_000_000,
+                1_800_000_000,
+                0,
+                0,
+            ],
+        )
+    }
+
+    /// Serves one client the device info and sync, then a carrier at `FREQUENCY + TONE` in 16 bit
+    /// IQ blocks for as long as it reads, reporting the commands it sends.
+    async fn serve(listener: TcpListener, commands: mpsc::UnboundedSender<(u32, Vec<u8>)>) {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (mut read, mut write) = socket.into_split();
+        tokio::spawn(async move {
+            let mut header = [0; 8];
+            while read.read_exact(&mut header).await.is_ok() {
+                let code = u32::from_le_bytes(header[0..4].try_into().unwrap());
+                let length = u32::from_le_bytes(header[4..8].try_into().unwrap());
+                let mut body = vec![0; length as usize];
+                if read.read_exact(&mut body).await.is_err() {
+                    return;
+                }
+                let _ = commands.send((code, body));
+            }
+        });
+
+        write.write_all(&device_info()).await.unwrap();
+        write.write_all(&client_sync(true)).await.unwrap();
+
+        let mut n = 0u64;
+        loop {
+            let block = (0..BLOCK_SAMPLES)
+                .flat_map(|_| {
+                    let phase = std::f64::consts::TAU * TONE * n as f64 / IQ_RATE;
+                    n += 1;
+                    let i = (phase.cos() * 16000.0) as i16;
+                    let q = (phase.sin() * 16000.0) as i16;
+                    [i.to_le_bytes(), q.to_le_bytes()].concat()
+                })
+                .collect::<Vec<u8>>();
+            let mut samples = message(101, &[]);
+            // Flags in the upper half of the type, and the real body size
+            samples[6] = 0x01;
+            samples[16..20].copy_from_slice(&(block.len() as u32).to_le_bytes());
+            samples.extend(block);
+            if write.write_all(&samples).await.is_err() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    async fn next_command(
+        commands: &mut mpsc::UnboundedReceiver<(u32, Vec<u8>)>,
+    ) -> (u32, Vec<u8>) {
+        tokio::time::timeout(Duration::from_secs(5), commands.recv())
+            .await
+            .expect("no command")
+            .unwrap()
+    }
+
+    fn setting(setting: Setting, value: u32) -> (u32, Vec<u8>) {
+        let mut body = (setting as u32).to_le_bytes().to_vec();
+        body.extend(value.to_le_bytes());
+        (2, body)
+    }
+
+    #[tokio::test]
+    async fn configures_the_stream_and_demodulates() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (commands_tx, mut commands) = mpsc::unbounded_channel();
+        tokio::spawn(serve(listener, commands_tx));
+
+        let pool = WriterPool::new(1, 64, OverflowPolicy::Block, 4096);
+        let events = EventBus::new();
+        let mut scraper = SpyServerScraper::new(
+            SpyServerScraperSettings {
+                name: "test".to_string(),
+                endpoint: Url::parse(&format!("sdr://127.0.0.1:{}", port)).unwrap(),
+                gain: Some(50),
+                station: Mode::USB.tuning(FREQUENCY, None, None),
+                sinks: Vec::new(),
+            },
+            &pool,
+            &events,
+        );
+        let mut audio = scraper.audio().subscribe();
+        scraper.start().await.unwrap();
+
+        let (code, hello) = next_command(&mut commands).await;
+        assert_eq!(code, 0);
+        assert_eq!(&hello[..4], &PROTOCOL_VERSION.to_le_bytes());
+        assert_eq!(
+            next_command(&mut commands).await,
+            setting(Setting::StreamingMode, STREAM_MODE_IQ_ONLY)
+        );
+        assert_eq!(
+            next_command(&mut commands).await,
+            setting(Setting::IqFormat, StreamFormat::Int16 as u32)
+        );
+        assert_eq!(
+            next_command(&mut commands).await,
+            setting(Setting::IqDecimation, DECIMATION)
+        );
+        assert_eq!(
+            next_command(&mut commands).await,
+            setting(Setting::IqFrequency, FREQUENCY as u32)
+        );
+        // Capped to the device's gain table
+        assert_eq!(
+            next_command(&mut commands).await,
+            setting(Setting::Gain, 21)
+        );
+        assert_eq!(
+            next_command(&mut commands).await,
+            setting(Setting::StreamingEnabled, 1)
+        );
+
+        // Skips the filters settling, then counts zero crossings
+        let mut samples = Vec::new();
+        while samples.len() < AUDIO_RATE as usize * 3 / 5 {
+            let frame = tokio::time::timeout(Duration::from_secs(5), audio.recv())
+                .await
+                .expect("no audio")
+                .unwrap();
+            assert_eq!(frame.sample_rate, AUDIO_RATE);
+            samples.extend_from_slice(&frame.samples);
+        }
+        let settled = &samples[AUDIO_RATE as usize / 10..];
+        let crossings = settled
+            .windows(2)
+            .filter(|pair| (pair[0] < 0) != (pair[1] < 0))
+            .count();
+        let frequency = crossings as f64 / 2.0 / (settled.len() as f64 / AUDIO_RATE as f64);
+        assert!(
+            (frequency - TONE).abs() < 20.0,
+            "demodulated a {} Hz tone",
+            frequency
+        );
+
+        // Retuning moves the IQ stream with the next block
+        scraper
+            .tune(Mode::USB.tuning(FREQUENCY + 5000.0, None, None))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_command(&mut commands).await,
+            setting(Setting::IqDecimation, DECIMATION)
+        );
+        assert_eq!(
+            next_command(&mut commands).await,
+            setting(Setting::IqFrequency, FREQUENCY as u32 + 5000)
+        );
+
+        scraper.stop().await.unwrap();
+        tokio::task::spawn_blocking(move || pool.shutdown())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn widens_the_stream_for_channels_off_the_device_center() {
+        let device = DeviceInfo {
+            maximum_sample_rate: MAXIMUM_SAMPLE_RATE,
+            decimation_stages: 9,
+            minimum_iq_decimation: 1,
+            ..DeviceInfo::default()
+        };
+        // Someone else controls the device, its IQ can only be taken from 7.0-7.2 MHz
+        let sync = ClientSync {
+            can_control: false,
+            device_center_frequency: 7_100_000,
+            minimum_iq_center_frequency: 7_000_000,
+            maximum_iq_center_frequency: 7_200_000,
+        };
+
+        let inside = Channel::new(&device, &sync, Mode::USB.tuning(7_150_000.0, None, None));
+        assert_eq!((inside.center_frequency, inside.decimation), (7_150_000, 6));
+
+        // 100 kHz above the highest center needs 0.4 * rate >= 124 kHz, so 375 kS/s
+        let outside = Channel::new(&device, &sync, Mode::USB.tuning(7_300_000.0, None, None));
+        assert_eq!(
+            (outside.center_frequency, outside.decimation),
+            (7_200_000, 4)
+        );
+
+        // Nothing wide enough, so the widest the server allows
+        let far = Channel::new(&device, &sync, Mode::USB.tuning(14_000_000.0, None, None));
+        assert_eq!(far.decimation, 1);
+    }
+}
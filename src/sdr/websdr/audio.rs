@@ -0,0 +1,107 @@
+/// 128 samples of 8-bit mu-law follow.
+const MULAW_BLOCK: u8 = 0x80;
+/// The audio sample rate follows, as a big endian u16.
+const SAMPLE_RATE: u8 = 0x81;
+/// 128 samples of silence.
+const SILENCE_BLOCK: u8 = 0x84;
+/// Tags from here up carry the S-meter in their low nibble and the next byte.
+const SMETER: u8 = 0xf0;
+
+const BLOCK_SIZE: usize = 128;
+
+/// Sample rate WebSDR servers stream at unless they say otherwise.
+pub const DEFAULT_SAMPLE_RATE: u32 = 8000;
+
+fn mulaw(byte: u8) -> i16 {
+    let byte = !byte;
+    let exponent = (byte >> 4) & 0x07;
+    let mantissa = (byte & 0x0f) as i32;
+    let magnitude = (((mantissa << 3) + 0x84) << exponent) - 0x84;
+    if byte & 0x80 != 0 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+/// Decodes the tagged blocks of the `~~stream` socket. Only the uncompressed mu-law stream is understood,
+/// which is asked for when tuning. Anything else, like the compressed audio most servers send by default,
+/// is an error: its length can't be known, so nothing after it in the stream can be read either.
+pub struct StreamDecoder {
+    pub sample_rate: u32,
+    pub smeter: Option<f64>,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        StreamDecoder {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            smeter: None,
+        }
+    }
+
+    /// Decodes one message, failing on the first block that isn't understood.
+    pub fn decode(&mut self, data: &[u8]) -> anyhow::Result<Vec<i16>> {
+        let mut samples = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            let tag = data[i];
+            let payload = &data[i + 1..];
+            let used = match tag {
+                MULAW_BLOCK if payload.len() >= BLOCK_SIZE => {
+                    samples.extend(payload[..BLOCK_SIZE].iter().map(|&byte| mulaw(byte)));
+                    BLOCK_SIZE
+                }
+                SAMPLE_RATE if payload.len() >= 2 => {
+                    self.sample_rate = u16::from_be_bytes([payload[0], payload[1]]) as u32;
+                    2
+                }
+                SILENCE_BLOCK => {
+                    samples.extend([0; BLOCK_SIZE]);
+                    0
+                }
+                SMETER.. if !payload.is_empty() => {
+                    // Tenths of a dB above -127 dBm
+                    let level = (((tag & 0x0f) as u16) << 8) | payload[0] as u16;
+                    self.smeter = Some(level as f64 / 10.0 - 127.0);
+                    1
+                }
+                _ => anyhow::bail!(
+                    "unsupported stream block {:#04x}, the server is likely sending compressed audio",
+                    tag
+                ),
+            };
+            i += 1 + used;
+        }
+
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_mulaw_silence_and_smeter() {
+        let mut message = vec![SAMPLE_RATE, 0x1f, 0x40, SMETER | 0x03, 0x84];
+        message.push(MULAW_BLOCK);
+        // 0xff and 0x7f are the two mu-law zeros, 0x00 and 0x80 the loudest samples
+        message.extend([0xff, 0x7f, 0x00, 0x80].repeat(BLOCK_SIZE / 4));
+        message.push(SILENCE_BLOCK);
+
+        let mut decoder = StreamDecoder::new();
+        let samples = decoder.decode(&message).unwrap();
+        assert_eq!(decoder.sample_rate, 8000);
+        assert_eq!(decoder.smeter, Some(90.0 - 127.0));
+        assert_eq!(samples.len(), 2 * BLOCK_SIZE);
+        assert_eq!(&samples[..4], &[0, 0, -32124, 32124]);
+        assert!(samples[BLOCK_SIZE..].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn fails_on_compressed_audio() {
+        let mut decoder = StreamDecoder::new();
+        assert!(decoder.decode(&[SILENCE_BLOCK, 0x12, 0x34]).is_err());
+    }
+}
@@ -0,0 +1,14 @@
+#[derive(Debug)]
+pub enum WebSdrCloseReason {
+    ServerClosed,
+    ConnectionLost,
+    /// The server sent audio in a format that can't be decoded.
+    UnsupportedAudio(String),
+}
+
+#[derive(Debug)]
+pub enum WebSdrEvent {
+    Close(WebSdrCloseReason),
+    Audio { samples: Vec<i16>, sample_rate: u32 },
+    Smeter(f64),
+}
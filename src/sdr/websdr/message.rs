@@ -0,0 +1,40 @@
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::sdr::Tuning;
+
+#[derive(Debug)]
+pub enum WebSdrClientMessage {
+    /// Tunes to `tuning` on the receiver's band with index `band`.
+    Tune { tuning: Tuning, band: usize },
+}
+
+impl From<WebSdrClientMessage> for Message {
+    fn from(msg: WebSdrClientMessage) -> Message {
+        match msg {
+            WebSdrClientMessage::Tune { tuning, band } => {
+                // Frequency and passband go in kHz, the sideband follows from the passband's sign
+                let (mode, low_cut, high_cut) = match tuning {
+                    Tuning::AM { bandwidth, .. } => (1, -(bandwidth / 2), bandwidth / 2),
+                    Tuning::FM {
+                        low_cut, high_cut, ..
+                    } => (4, low_cut, high_cut),
+                    Tuning::LSB {
+                        low_cut, high_cut, ..
+                    }
+                    | Tuning::USB {
+                        low_cut, high_cut, ..
+                    } => (0, low_cut, high_cut),
+                };
+                // Uncompressed mu-law, the only audio `StreamDecoder` understands
+                Message::Text(format!(
+                    "GET /~~param?f={:.3}&band={}&lo={:.3}&hi={:.3}&mode={}&compress=0&name=sdr-scraper",
+                    tuning.frequency() / 1000.0,
+                    band,
+                    low_cut as f64 / 1000.0,
+                    high_cut as f64 / 1000.0,
+                    mode
+                ))
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+mod audio;
+pub mod event;
+mod message;
+mod scraper;
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use regex::Regex;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+pub use scraper::{WebSdrScraper, WebSdrScraperSettings};
+
+use self::{
+    audio::StreamDecoder,
+    event::{WebSdrCloseReason, WebSdrEvent},
+};
+
+pub use self::message::WebSdrClientMessage;
+
+/// A slice of spectrum served by the receiver, in kHz as listed in its `bandinfo.js`.
+#[derive(Debug, Clone, Copy)]
+pub struct Band {
+    pub center_frequency: f64,
+    pub sample_rate: f64,
+}
+
+impl Band {
+    pub fn contains(&self, frequency: f64) -> bool {
+        (frequency / 1000.0 - self.center_frequency).abs() <= self.sample_rate / 2.0
+    }
+}
+
+pub struct WebSdr {
+    cancellation_token: CancellationToken,
+    event_channel_rx: Option<tokio::sync::mpsc::Receiver<WebSdrEvent>>,
+    message_channel_tx: Option<tokio::sync::mpsc::Sender<WebSdrClientMessage>>,
+    endpoint: Url,
+}
+
+impl WebSdr {
+    pub fn new(endpoint: Url) -> Self {
+        Self {
+            cancellation_token: CancellationToken::new(),
+            event_channel_rx: None,
+            message_channel_tx: None,
+            endpoint,
+        }
+    }
+
+    /// Fetches the receiver's bands, in the order the `band` parameter indexes them.
+    pub async fn bands(&self) -> anyhow::Result<Vec<Band>> {
+        let mut url = self.endpoint.clone();
+        url.set_scheme("http").unwrap();
+        url = url.join("tmp/bandinfo.js")?;
+        log::debug!("getting bands from {}", url);
+
+        let script = reqwest::get(url).await?.error_for_status()?.text().await?;
+        let center = Regex::new(r"centerfreq\s*:\s*([\d.]+)").unwrap();
+        let rate = Regex::new(r"samplerate\s*:\s*([\d.]+)").unwrap();
+        Ok(center
+            .captures_iter(&script)
+            .zip(rate.captures_iter(&script))
+            .filter_map(|(center, rate)| {
+                Some(Band {
+                    center_frequency: center[1].parse().ok()?,
+                    sample_rate: rate[1].parse().ok()?,
+                })
+            })
+            .collect())
+    }
+
+    pub async fn connect(&mut self) -> anyhow::Result<()> {
+        let url = self.endpoint.join("~~stream")?;
+        log::debug!("Connecting to WebSDR at {}", url);
+
+        let connect = tokio_tungstenite::connect_async(url.as_str());
+        let (ws_socket, _) = tokio::time::timeout(Duration::from_secs(2), connect)
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection timeout"))??;
+
+        let (mut write, mut read) = ws_socket.split();
+
+        // Create event channels
+        let (event_tx, event_rx) = tokio::sync::mpsc::channel::<WebSdrEvent>(100);
+        let (msg_tx, mut msg_rx) = tokio::sync::mpsc::channel::<WebSdrClientMessage>(100);
+        self.event_channel_rx = Some(event_rx);
+        self.message_channel_tx = Some(msg_tx);
+
+        self.cancellation_token = CancellationToken::new();
+        let token = self.cancellation_token.clone();
+        let endpoint = self.endpoint.clone();
+        tokio::spawn(async move {
+            log::debug!("starting event loop for WebSDR at {}", endpoint);
+            let mut decoder = StreamDecoder::new();
+
+            let read_loop = async {
+                while let Some(msg) = read.next().await {
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            log::error!("Error reading message: {:?}", e);
+                            break;
+                        }
+                    };
+
+                    match msg {
+                        Message::Binary(bin) => {
+                            let samples = match decoder.decode(&bin) {
+                                Ok(samples) => samples,
+                                Err(e) => {
+                                    log::error!("WebSDR at {}: {}", endpoint, e);
+                                    let reason = WebSdrCloseReason::UnsupportedAudio(e.to_string());
+                                    let _ = event_tx.send(WebSdrEvent::Close(reason)).await;
+                                    return;
+                                }
+                            };
+                            if let Some(level) = decoder.smeter.take() {
+                                if event_tx.send(WebSdrEvent::Smeter(level)).await.is_err() {
+                                    return;
+                                }
+                            }
+                            if samples.is_empty() {
+                                continue;
+                            }
+                            let event = WebSdrEvent::Audio {
+                                samples,
+                                sample_rate: decoder.sample_rate,
+                            };
+                            if event_tx.send(event).await.is_err() {
+                                return;
+                            }
+                        }
+                        Message::Close(_close) => {
+                            let _ = event_tx
+                                .send(WebSdrEvent::Close(WebSdrCloseReason::ServerClosed))
+                                .await;
+                            return;
+                        }
+                        _ => {}
+                    }
+                }
+
+                let _ = event_tx
+                    .send(WebSdrEvent::Close(WebSdrCloseReason::ConnectionLost))
+                    .await;
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = read_loop => {
+                    token.cancel();
+                }
+            };
+        });
+
+        let token = self.cancellation_token.clone();
+        let endpoint = self.endpoint.clone();
+        tokio::spawn(async move {
+            log::debug!("starting message loop for WebSDR at {}", endpoint);
+            let write_loop = async {
+                while let Some(msg) = msg_rx.recv().await {
+                    log::debug!("Sending message: {:?}", msg);
+                    if let Err(e) = write.send(msg.into()).await {
+                        log::error!("Error sending message: {:?}", e);
+                        break;
+                    }
+                }
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = write_loop => {}
+            };
+        });
+
+        Ok(())
+    }
+
+    pub async fn read_event(&mut self, timeout: Duration) -> Option<WebSdrEvent> {
+        let rx = self.event_channel_rx.as_mut()?;
+
+        tokio::time::timeout(timeout, rx.recv())
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn send_message(&self, message: WebSdrClientMessage) -> anyhow::Result<()> {
+        self.message_channel_tx
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("not connected"))?
+            .send(message)
+            .await?;
+        Ok(())
+    }
+
+    fn shutdown(&self) -> anyhow::Result<()> {
+        log::debug!("Shutting down WebSDR");
+        self.cancellation_token.cancel();
+        Ok(())
+    }
+}
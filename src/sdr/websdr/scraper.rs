@@ -0,0 +1,232 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use colored::Colorize;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::{
+    audio::{AudioStream, WriterPool},
+    config::SinkConfig,
+    events::{EventBus, EventKind, StationState},
+    sdr::{
+        scraper::{SDRScraper, ScraperOutputs, ScraperStats, ScraperStatus},
+        websdr::{
+            event::{WebSdrCloseReason, WebSdrEvent},
+            Band, WebSdr, WebSdrClientMessage,
+        },
+        Tuning,
+    },
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(4);
+
+#[derive(Clone)]
+pub struct WebSdrScraperSettings {
+    pub name: String,
+    pub endpoint: Url,
+    pub station: Tuning,
+    pub sinks: Vec<SinkConfig>,
+}
+
+pub struct WebSdrScraper {
+    settings: WebSdrScraperSettings,
+    sdr: Arc<Mutex<WebSdr>>,
+    status: ScraperStatus,
+    token: CancellationToken,
+    bands: Arc<std::sync::Mutex<Vec<Band>>>,
+    outputs: ScraperOutputs,
+}
+
+impl WebSdrScraper {
+    pub fn new(
+        settings: WebSdrScraperSettings,
+        pool: &WriterPool,
+        events: &EventBus,
+    ) -> WebSdrScraper {
+        WebSdrScraper {
+            settings: settings.clone(),
+            sdr: Arc::new(Mutex::new(WebSdr::new(settings.endpoint))),
+            status: ScraperStatus::Stopped,
+            token: CancellationToken::new(),
+            bands: Arc::new(std::sync::Mutex::new(Vec::new())),
+            outputs: ScraperOutputs::new(
+                &settings.name,
+                settings.station,
+                &settings.sinks,
+                pool,
+                events,
+            ),
+        }
+    }
+
+    /// Sends `tuning` on the first band that covers it, or band 0 if the band list is unknown.
+    async fn apply_tuning(sdr: &WebSdr, tuning: Tuning, bands: &[Band]) -> anyhow::Result<()> {
+        let band = match bands
+            .iter()
+            .position(|band| band.contains(tuning.frequency()))
+        {
+            Some(band) => band,
+            None if bands.is_empty() => 0,
+            None => anyhow::bail!("no band covers {}", tuning),
+        };
+        sdr.send_message(WebSdrClientMessage::Tune { tuning, band })
+            .await
+    }
+
+    async fn run(
+        settings: WebSdrScraperSettings,
+        sdr: Arc<Mutex<WebSdr>>,
+        bands: Arc<std::sync::Mutex<Vec<Band>>>,
+        outputs: ScraperOutputs,
+    ) {
+        let mut reconnecting = false;
+        loop {
+            outputs.state.set(StationState::Connecting);
+            let connected = async {
+                let mut sdr = sdr.lock().await;
+                let list = sdr.bands().await.unwrap_or_else(|e| {
+                    log::warn!("{}: failed to get bands: {}", settings.name.yellow(), e);
+                    Vec::new()
+                });
+                *bands.lock().unwrap() = list.clone();
+
+                sdr.connect().await?;
+                Self::apply_tuning(&sdr, outputs.tuning(), &list).await
+            };
+            if let Err(e) = connected.await {
+                log::error!("{}: failed to connect: {}", settings.name.red(), e);
+                if reconnecting {
+                    outputs.publish(EventKind::ReconnectFailed {
+                        error: e.to_string(),
+                    });
+                } else {
+                    outputs.publish(EventKind::Disconnected {
+                        reason: e.to_string(),
+                    });
+                }
+                outputs.state.set(StationState::Disconnected);
+                reconnecting = true;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+
+            if reconnecting {
+                log::info!("{}: reconnected", settings.name.green());
+                outputs.publish(EventKind::Reconnected);
+            }
+            outputs.state.set(StationState::Connected);
+
+            loop {
+                // Make sure SDR instance lock is dropped immediately after fetching the latest message
+                let event = sdr.lock().await.read_event(Duration::from_secs(1)).await;
+                let Some(event) = event else {
+                    continue;
+                };
+
+                match event {
+                    WebSdrEvent::Close(reason) => {
+                        log::error!("{}: connection closed: {:?}", settings.name.red(), reason);
+                        let reason = match reason {
+                            WebSdrCloseReason::UnsupportedAudio(error) => error,
+                            reason => format!("{:?}", reason),
+                        };
+                        outputs.publish(EventKind::Disconnected { reason });
+                        outputs.state.set(StationState::Disconnected);
+                        break;
+                    }
+                    WebSdrEvent::Audio {
+                        samples,
+                        sample_rate,
+                    } => {
+                        if outputs.state.get().0 != StationState::Ready {
+                            log::info!("{} is ready at {} Hz", settings.name.green(), sample_rate);
+                            outputs.state.set(StationState::Ready);
+                        }
+                        outputs.write(samples, sample_rate).await;
+                    }
+                    WebSdrEvent::Smeter(level) => outputs.set_rssi(level),
+                }
+            }
+
+            outputs.writer.close();
+            reconnecting = true;
+            log::info!("{}: reconnecting in 4...", settings.name.yellow());
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SDRScraper for WebSdrScraper {
+    async fn start(&mut self) -> anyhow::Result<()> {
+        if self.status == ScraperStatus::Running {
+            log::warn!("SDR for {} is already running", self.settings.name);
+            return Ok(());
+        }
+
+        log::debug!("starting scraper for {}", self.settings.name.green());
+        self.token = CancellationToken::new();
+
+        let token = self.token.clone();
+        let run = Self::run(
+            self.settings.clone(),
+            self.sdr.clone(),
+            self.bands.clone(),
+            self.outputs.clone(),
+        );
+        let name = self.settings.name.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = run => {}
+                _ = token.cancelled() => {
+                    log::debug!("{}: event loop cancelled", name.yellow());
+                }
+            }
+        });
+
+        self.status = ScraperStatus::Running;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        log::debug!("Stopping scraper for {}", self.settings.name.green());
+
+        self.token.cancel();
+        self.sdr.lock().await.shutdown()?;
+        self.outputs.writer.close();
+        self.status = ScraperStatus::Stopped;
+        self.outputs.state.set(StationState::Stopped);
+
+        Ok(())
+    }
+
+    async fn tune(&mut self, tuning: Tuning) -> anyhow::Result<()> {
+        log::info!("{}: tuning to {}", self.settings.name.green(), tuning);
+
+        if self.status == ScraperStatus::Running {
+            let bands = self.bands.lock().unwrap().clone();
+            let sdr = self.sdr.lock().await;
+            Self::apply_tuning(&sdr, tuning.clone(), &bands).await?;
+        }
+        self.outputs.set_tuning(tuning);
+        Ok(())
+    }
+
+    fn status(&self) -> ScraperStatus {
+        self.status.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    fn get_stats(&self) -> ScraperStats {
+        self.outputs.stats(self.status.clone())
+    }
+
+    fn audio(&self) -> AudioStream {
+        self.outputs.audio.clone()
+    }
+}
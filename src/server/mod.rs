@@ -0,0 +1,296 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::body::{Body, Bytes};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::header;
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tokio::sync::{broadcast, Mutex};
+
+use crate::audio::{pcm_bytes, wav_stream_header};
+use crate::events::{EventBus, StationEvent};
+use crate::jobs::{Job, JobQueue, JobRequest};
+use crate::sdr::{SDRScraper, ScraperStats, Tuning};
+
+pub struct AppState {
+    pub stations: Vec<Box<dyn SDRScraper>>,
+    pub events: EventBus,
+    pub jobs: JobQueue,
+}
+
+impl AppState {
+    fn station(&mut self, name: &str) -> Result<&mut Box<dyn SDRScraper>, StatusCode> {
+        self.stations
+            .iter_mut()
+            .find(|station| station.name() == name)
+            .ok_or(StatusCode::NOT_FOUND)
+    }
+}
+
+pub fn router(state: Arc<Mutex<AppState>>) -> Router {
+    Router::new()
+        // `GET /` goes to `root`
+        .route("/", get(root))
+        .route("/dashboard", get(dashboard))
+        .route("/events", get(recent_events))
+        .route("/ws", get(live_events))
+        .route("/stations/:name/audio", get(station_audio))
+        .route("/stations/:name/start", post(start_station))
+        .route("/stations/:name/stop", post(stop_station))
+        .route("/stations/:name/tuning", put(tune_station))
+        .route("/stations/:name/survey", get(station_survey))
+        .route("/jobs", get(list_jobs).post(submit_job))
+        .route("/jobs/:id", get(get_job).delete(cancel_job))
+        .with_state(state)
+}
+
+async fn root(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+) -> Result<Json<Vec<ScraperStats>>, StatusCode> {
+    let state = app_state.lock().await;
+    Ok(Json(
+        state
+            .stations
+            .iter()
+            .map(|station| station.get_stats())
+            .collect(),
+    ))
+}
+
+async fn dashboard() -> Html<&'static str> {
+    Html(include_str!("dashboard.html"))
+}
+
+async fn start_station(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut state = app_state.lock().await;
+    let station = state.station(&name).map_err(|code| (code, name))?;
+    station
+        .start()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn stop_station(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut state = app_state.lock().await;
+    let station = state.station(&name).map_err(|code| (code, name))?;
+    station
+        .stop()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn tune_station(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+    Path(name): Path<String>,
+    Json(tuning): Json<Tuning>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut state = app_state.lock().await;
+    let station = state.station(&name).map_err(|code| (code, name))?;
+    station
+        .tune(tuning)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct SurveyQuery {
+    /// Only intervals starting at or after this time.
+    since: Option<DateTime<Utc>>,
+    /// Only channels within this range, in Hz.
+    from: Option<f64>,
+    to: Option<f64>,
+    /// `csv` for a spreadsheet, JSON otherwise.
+    format: Option<String>,
+}
+
+/// A survey station's results, one row per channel and interval.
+async fn station_survey(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+    Path(name): Path<String>,
+    Query(query): Query<SurveyQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let table = {
+        let mut state = app_state.lock().await;
+        let station = state.station(&name).map_err(|code| (code, name.clone()))?;
+        station
+            .survey()
+            .ok_or_else(|| (StatusCode::NOT_FOUND, format!("{} runs no survey", name)))?
+    };
+    let records = table.query(query.since, query.from, query.to);
+
+    if query.format.as_deref() != Some("csv") {
+        return Ok(Json(records).into_response());
+    }
+    let mut csv = "time,frequency,noise_floor,occupancy,lines\n".to_string();
+    for record in records {
+        csv += &format!(
+            "{},{:.0},{:.1},{:.1},{}\n",
+            record.time.to_rfc3339(),
+            record.frequency,
+            record.noise_floor,
+            record.occupancy,
+            record.lines
+        );
+    }
+    Ok(([(header::CONTENT_TYPE, "text/csv")], csv).into_response())
+}
+
+async fn list_jobs(State(app_state): State<Arc<Mutex<AppState>>>) -> Json<Vec<Job>> {
+    Json(app_state.lock().await.jobs.jobs().to_vec())
+}
+
+async fn submit_job(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+    Json(request): Json<JobRequest>,
+) -> Result<(StatusCode, Json<Job>), (StatusCode, String)> {
+    let mut state = app_state.lock().await;
+    let job = state
+        .jobs
+        .submit(request)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok((StatusCode::CREATED, Json(job)))
+}
+
+async fn get_job(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Job>, StatusCode> {
+    let state = app_state.lock().await;
+    state
+        .jobs
+        .get(id)
+        .cloned()
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Cancels a job, stopping its recording if it's in progress. Finished jobs can't be.
+async fn cancel_job(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Job>, (StatusCode, String)> {
+    let mut state = app_state.lock().await;
+    if state.jobs.get(id).is_none() {
+        return Err((StatusCode::NOT_FOUND, format!("no job {}", id)));
+    }
+    let job = state
+        .jobs
+        .cancel(id)
+        .map_err(|e| (StatusCode::CONFLICT, e.to_string()))?;
+    Ok(Json(job))
+}
+
+async fn recent_events(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+) -> Result<Json<Vec<StationEvent>>, StatusCode> {
+    let state = app_state.lock().await;
+    Ok(Json(state.events.history()))
+}
+
+#[derive(Deserialize)]
+struct LiveEventsQuery {
+    /// Only forward events from this station.
+    station: Option<String>,
+}
+
+/// Pushes every station event to the client as JSON text messages, as they happen.
+async fn live_events(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+    Query(query): Query<LiveEventsQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let receiver = app_state.lock().await.events.subscribe();
+    upgrade.on_upgrade(move |socket| forward_events(socket, receiver, query.station))
+}
+
+async fn forward_events(
+    mut socket: WebSocket,
+    mut receiver: broadcast::Receiver<StationEvent>,
+    station: Option<String>,
+) {
+    loop {
+        tokio::select! {
+            event = receiver.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::debug!("event listener skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if station.as_ref().is_some_and(|station| *station != event.station) {
+                    continue;
+                }
+
+                let json = serde_json::to_string(&event).unwrap();
+                if socket.send(Message::Text(json)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                // Anything the client sends is ignored, we only care about it going away
+                match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
+
+/// Streams a station's live audio as a never-ending WAV file. The stream ends if the sample rate changes,
+/// since it cannot be expressed mid-file; players are expected to reconnect.
+async fn station_audio(
+    State(app_state): State<Arc<Mutex<AppState>>>,
+    Path(name): Path<String>,
+) -> Result<Response, StatusCode> {
+    let receiver = app_state.lock().await.station(&name)?.audio().subscribe();
+    log::info!("{}: new audio listener", name);
+
+    let chunks = futures_util::stream::unfold(
+        (receiver, None::<u32>),
+        |(mut receiver, sample_rate)| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(frame) => {
+                        let mut chunk = match sample_rate {
+                            None => wav_stream_header(frame.sample_rate),
+                            Some(rate) if rate != frame.sample_rate => return None,
+                            Some(_) => Vec::new(),
+                        };
+                        chunk.extend(pcm_bytes(&frame.samples));
+
+                        let state = (receiver, Some(frame.sample_rate));
+                        return Some((Ok::<_, Infallible>(Bytes::from(chunk)), state));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::debug!("audio listener skipped {} frames", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        },
+    );
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "audio/wav")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::from_stream(chunks))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -0,0 +1,110 @@
+use serde::Serialize;
+
+use crate::{config::FrequencyRange, sdr::kiwi::SpectrumRow, sdr::Tuning};
+
+/// Bins a signal may dip below the margin for and still count as one, e.g. between the
+/// syllables of an SSB voice.
+const MERGE_GAP: usize = 2;
+
+/// A signal standing out of a waterfall line.
+#[derive(Clone, Debug, Serialize)]
+pub struct Detection {
+    /// Frequency of the strongest bin, in Hz.
+    pub frequency: f64,
+    /// Lower and upper edge of the bins above the margin, in Hz.
+    pub low: f64,
+    pub high: f64,
+    pub peak_dbm: f32,
+    /// The line's median, standing in for the noise floor.
+    pub noise_dbm: f32,
+}
+
+impl Detection {
+    pub fn width(&self) -> f64 {
+        self.high - self.low
+    }
+
+    /// Whether `other` is (part of) the same signal.
+    pub fn overlaps(&self, other: &Detection) -> bool {
+        self.low <= other.high && other.low <= self.high
+    }
+
+    /// A guess at how to listen to the signal, going by its width and, for SSB, by the usual
+    /// sideband below and above 10 MHz. Narrow signals are put at a 1 kHz tone.
+    pub fn tuning(&self) -> Tuning {
+        let width = self.width();
+        if width >= 12_000.0 {
+            Tuning::FM {
+                low_cut: -6000,
+                high_cut: 6000,
+                frequency: (self.low + self.high) / 2.0,
+            }
+        } else if width >= 4_000.0 {
+            Tuning::AM {
+                bandwidth: 9000,
+                frequency: (self.low + self.high) / 2.0,
+            }
+        } else if width < 1_000.0 {
+            Tuning::USB {
+                low_cut: 300,
+                high_cut: 2700,
+                frequency: self.frequency - 1000.0,
+            }
+        } else if self.frequency < 10e6 {
+            Tuning::LSB {
+                low_cut: -2700,
+                high_cut: -300,
+                frequency: self.high + 300.0,
+            }
+        } else {
+            Tuning::USB {
+                low_cut: 300,
+                high_cut: 2700,
+                frequency: self.low - 300.0,
+            }
+        }
+    }
+}
+
+/// Finds the signals in `row` standing `margin` dB above the line's median, within `ranges`
+/// or anywhere if there are none.
+pub fn detect(row: &SpectrumRow, ranges: &[FrequencyRange], margin: f32) -> Vec<Detection> {
+    let mut sorted = row.bins.clone();
+    sorted.sort_by(f32::total_cmp);
+    let Some(&noise) = sorted.get(sorted.len() / 2) else {
+        return Vec::new();
+    };
+
+    let mut detections: Vec<Detection> = Vec::new();
+    // Bin the last detection ended at, to merge across short gaps
+    let mut last_bin = None;
+    for (index, &dbm) in row.bins.iter().enumerate() {
+        let frequency = row.frequency(index);
+        if dbm < noise + margin
+            || !(ranges.is_empty() || ranges.iter().any(|range| range.contains(frequency)))
+        {
+            continue;
+        }
+
+        let low = row.start_frequency + index as f64 * row.bin_width;
+        let high = low + row.bin_width;
+        match detections.last_mut() {
+            Some(detection) if last_bin.is_some_and(|last| index - last <= MERGE_GAP + 1) => {
+                detection.high = high;
+                if dbm > detection.peak_dbm {
+                    detection.peak_dbm = dbm;
+                    detection.frequency = frequency;
+                }
+            }
+            _ => detections.push(Detection {
+                frequency,
+                low,
+                high,
+                peak_dbm: dbm,
+                noise_dbm: noise,
+            }),
+        }
+        last_bin = Some(index);
+    }
+    detections
+}
@@ -0,0 +1,301 @@
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use tokio::sync::mpsc;
+
+use crate::{
+    audio::render_template,
+    config::WaterfallConfig,
+    events::{EventBus, EventKind},
+    sdr::{kiwi::SpectrumRow, Tuning},
+};
+
+pub mod detect;
+mod font;
+pub mod npy;
+mod render;
+mod survey;
+
+use npy::NpyWriter;
+pub use survey::{Survey, SurveyRecord, SurveyTable};
+
+/// Rows a station may have queued for its spectrum sinks before the feed waits on them.
+const SINK_BACKLOG: usize = 64;
+
+/// A consumer of waterfall rows. Sinks run on a blocking thread, so they are free to block.
+pub trait SpectrumSink: Send {
+    fn write(&mut self, row: &SpectrumRow) -> anyhow::Result<()>;
+
+    /// Called when the feed ends, e.g. when the scraper stops.
+    fn close(&mut self) -> anyhow::Result<()>;
+}
+
+/// Runs `sinks` on a blocking thread, returning the sender to feed them through. They are
+/// closed once every sender has been dropped.
+pub fn spawn_sinks(name: &str, mut sinks: Vec<Box<dyn SpectrumSink>>) -> mpsc::Sender<SpectrumRow> {
+    let (tx, mut rx) = mpsc::channel::<SpectrumRow>(SINK_BACKLOG);
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || {
+        while let Some(row) = rx.blocking_recv() {
+            for sink in sinks.iter_mut() {
+                if let Err(e) = sink.write(&row) {
+                    log::error!("{}: spectrum sink failed: {}", name, e);
+                }
+            }
+        }
+        for sink in sinks.iter_mut() {
+            if let Err(e) = sink.close() {
+                log::error!("{}: failed to close spectrum sink: {}", name, e);
+            }
+        }
+    });
+    tx
+}
+
+struct ArchiveFile {
+    /// Path without the extension, shared by the NPY and PNG.
+    stem: PathBuf,
+    npy: NpyWriter,
+    start_frequency: f64,
+    bin_width: f64,
+    opened: Instant,
+}
+
+/// Archives waterfall rows to an NPY file, starting a new one every `rotate` like
+/// `audio::Writer`. Closed files are drawn to a PNG next to them.
+pub struct Archive {
+    name: String,
+    tuning: Tuning,
+    config: WaterfallConfig,
+    events: EventBus,
+    file: Option<ArchiveFile>,
+}
+
+fn with_extension(stem: &Path, extension: &str) -> PathBuf {
+    let mut path = stem.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+impl Archive {
+    pub fn new(name: String, tuning: Tuning, config: WaterfallConfig, events: EventBus) -> Self {
+        Archive {
+            name,
+            tuning,
+            config,
+            events,
+            file: None,
+        }
+    }
+
+    fn open(&mut self, row: &SpectrumRow) -> anyhow::Result<()> {
+        let stem = self.config.dir.join(render_template(
+            &self.config.template,
+            &self.name,
+            &self.tuning,
+            row.timestamp,
+        )?);
+        let npy_path = with_extension(&stem, "npy");
+        let npy = NpyWriter::create(&npy_path, row.bins.len())?;
+        self.events.publish(
+            &self.name,
+            EventKind::FileOpened {
+                path: npy_path.display().to_string(),
+            },
+        );
+
+        self.file = Some(ArchiveFile {
+            stem,
+            npy,
+            start_frequency: row.start_frequency,
+            bin_width: row.bin_width,
+            opened: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// Whether `row` can go into the open file, which needs the same bins at the same frequencies.
+    fn fits(file: &ArchiveFile, row: &SpectrumRow) -> bool {
+        file.npy.bins() == row.bins.len()
+            && file.start_frequency == row.start_frequency
+            && file.bin_width == row.bin_width
+    }
+}
+
+impl SpectrumSink for Archive {
+    fn write(&mut self, row: &SpectrumRow) -> anyhow::Result<()> {
+        if self
+            .file
+            .as_ref()
+            .is_some_and(|file| !Self::fits(file, row))
+        {
+            self.close()?;
+        }
+        if self.file.is_none() {
+            self.open(row)?;
+        }
+
+        let file = self.file.as_mut().unwrap();
+        file.npy.write(row)?;
+        if file.opened.elapsed() > self.config.rotate {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> anyhow::Result<()> {
+        let Some(file) = self.file.take() else {
+            return Ok(());
+        };
+        file.npy.finish()?;
+
+        let npy_path = with_extension(&file.stem, "npy");
+        self.events.publish(
+            &self.name,
+            EventKind::FileClosed {
+                path: npy_path.display().to_string(),
+            },
+        );
+
+        let png_path = with_extension(&file.stem, "png");
+        render::render(
+            &npy_path,
+            &png_path,
+            &self.name,
+            self.config.min_db,
+            self.config.max_db,
+        )?;
+        self.events.publish(
+            &self.name,
+            EventKind::FileClosed {
+                path: png_path.display().to_string(),
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::sdr::Mode;
+    use npy::NpyReader;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("sdr-scraper-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        /// Files with `extension`, by name.
+        fn files(&self, extension: &str) -> Vec<PathBuf> {
+            let mut files = std::fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|e| e == extension))
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn archive(dir: &TempDir, rotate: Duration) -> Archive {
+        Archive::new(
+            "test".to_string(),
+            Mode::USB.tuning(7_100_000.0, None, None),
+            WaterfallConfig {
+                dir: dir.0.clone(),
+                template: "{name}_%H%M%S".to_string(),
+                rotate,
+                ..WaterfallConfig::default()
+            },
+            EventBus::new(),
+        )
+    }
+
+    fn row(seconds: i64, start_frequency: f64) -> SpectrumRow {
+        SpectrumRow {
+            timestamp: Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap()
+                + chrono::Duration::seconds(seconds),
+            sequence: seconds as u32,
+            start_frequency,
+            bin_width: 100.0,
+            bins: vec![-100.0; 50],
+        }
+    }
+
+    fn rows(path: &Path) -> u64 {
+        NpyReader::open(path).unwrap().rows
+    }
+
+    #[test]
+    fn starts_a_new_file_when_the_band_moves() {
+        let dir = TempDir::new("archive-band");
+        let mut archive = archive(&dir, Duration::from_secs(3600));
+        let mut events = archive.events.subscribe();
+        for seconds in 0..3 {
+            archive.write(&row(seconds, 7.0e6)).unwrap();
+        }
+        archive.write(&row(3, 7.1e6)).unwrap();
+        // Only closed files are drawn
+        assert_eq!(dir.files("png").len(), 1);
+        archive.close().unwrap();
+
+        let npys = dir.files("npy");
+        assert_eq!(
+            npys.iter()
+                .map(|path| path.file_name().unwrap().to_str().unwrap())
+                .collect::<Vec<_>>(),
+            ["test_120000.npy", "test_120003.npy"]
+        );
+        assert_eq!(rows(&npys[0]), 3);
+        assert_eq!(rows(&npys[1]), 1);
+        assert_eq!(dir.files("png").len(), 2);
+
+        let mut opened = 0;
+        let mut closed = 0;
+        while let Ok(event) = events.try_recv() {
+            match event.kind {
+                EventKind::FileOpened { .. } => opened += 1,
+                EventKind::FileClosed { .. } => closed += 1,
+                _ => {}
+            }
+        }
+        // The NPY and PNG of each
+        assert_eq!((opened, closed), (2, 4));
+    }
+
+    #[test]
+    fn rotates_after_a_while() {
+        let dir = TempDir::new("archive-rotate");
+        let mut archive = archive(&dir, Duration::from_millis(50));
+        archive.write(&row(0, 7.0e6)).unwrap();
+        archive.write(&row(1, 7.0e6)).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        archive.write(&row(2, 7.0e6)).unwrap();
+        archive.write(&row(3, 7.0e6)).unwrap();
+        archive.close().unwrap();
+
+        let npys = dir.files("npy");
+        assert_eq!(npys.len(), 2);
+        assert_eq!(rows(&npys[0]), 3);
+        assert_eq!(rows(&npys[1]), 1);
+        assert_eq!(dir.files("png").len(), 2);
+    }
+}
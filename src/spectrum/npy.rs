@@ -0,0 +1,262 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{DateTime, Utc};
+
+use crate::sdr::kiwi::SpectrumRow;
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// Room kept for the header so it can be rewritten in place as the row count grows.
+const HEADER_SIZE: usize = 256;
+
+/// Rows written between header rewrites, so a file left behind by a killed scraper still loads.
+const FLUSH_ROWS: u64 = 60;
+
+/// A spectrum row as stored, with the power rounded to whole dB as the Kiwi sends it.
+pub struct Record {
+    pub timestamp: DateTime<Utc>,
+    pub start_frequency: f64,
+    pub bin_width: f64,
+    pub dbm: Vec<i16>,
+}
+
+/// Writes rows as a NumPy structured array, so `numpy.load` gives `time` (µs since the epoch),
+/// `start_hz`, `bin_hz` and `dbm` columns.
+pub struct NpyWriter {
+    writer: BufWriter<File>,
+    bins: usize,
+    rows: u64,
+}
+
+fn row_size(bins: usize) -> u64 {
+    8 + 8 + 8 + 2 * bins as u64
+}
+
+fn header(bins: usize, rows: u64) -> Vec<u8> {
+    let dict = format!(
+        "{{'descr': [('time', '<i8'), ('start_hz', '<f8'), ('bin_hz', '<f8'), ('dbm', '<i2', ({},))], 'fortran_order': False, 'shape': ({},), }}",
+        bins, rows
+    );
+    let mut header = MAGIC.to_vec();
+    header.extend([1, 0]);
+    header.extend(((HEADER_SIZE - 10) as u16).to_le_bytes());
+    header.extend(dict.as_bytes());
+    header.resize(HEADER_SIZE - 1, b' ');
+    header.push(b'\n');
+    header
+}
+
+impl NpyWriter {
+    pub fn create(path: &Path, bins: usize) -> anyhow::Result<NpyWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&header(bins, 0))?;
+        Ok(NpyWriter {
+            writer,
+            bins,
+            rows: 0,
+        })
+    }
+
+    pub fn bins(&self) -> usize {
+        self.bins
+    }
+
+    pub fn write(&mut self, row: &SpectrumRow) -> anyhow::Result<()> {
+        self.writer
+            .write_i64::<LittleEndian>(row.timestamp.timestamp_micros())?;
+        self.writer.write_f64::<LittleEndian>(row.start_frequency)?;
+        self.writer.write_f64::<LittleEndian>(row.bin_width)?;
+        for &dbm in &row.bins {
+            self.writer.write_i16::<LittleEndian>(dbm.round() as i16)?;
+        }
+        self.rows += 1;
+        if self.rows.is_multiple_of(FLUSH_ROWS) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the rows so far and fills in their count, without which NumPy reads an empty
+    /// array.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header(self.bins, self.rows))?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.flush()
+    }
+}
+
+/// Reads back the rows of a file from `NpyWriter`.
+pub struct NpyReader {
+    reader: BufReader<File>,
+    pub bins: usize,
+    /// Whole rows in the file, which can be more than its header says if the writer never
+    /// finished it.
+    pub rows: u64,
+}
+
+impl NpyReader {
+    pub fn open(path: &Path) -> anyhow::Result<NpyReader> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = vec![0; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        if !header.starts_with(MAGIC) {
+            anyhow::bail!("{} is not an NPY file", path.display());
+        }
+
+        let dict = String::from_utf8_lossy(&header[10..]);
+        let number_after = |key: &str| -> anyhow::Result<u64> {
+            let start = dict
+                .find(key)
+                .ok_or_else(|| anyhow::anyhow!("no {} in {}", key, path.display()))?
+                + key.len();
+            let digits = dict[start..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect::<String>();
+            Ok(digits.parse()?)
+        };
+        let bins = number_after("'<i2', (")? as usize;
+        let length = reader.get_ref().metadata()?.len();
+        Ok(NpyReader {
+            bins,
+            rows: length.saturating_sub(HEADER_SIZE as u64) / row_size(bins),
+            reader,
+        })
+    }
+
+    /// The next row, or `None` at the end of the file or of the last whole row.
+    pub fn next_record(&mut self) -> anyhow::Result<Option<Record>> {
+        let mut row = vec![0; row_size(self.bins) as usize];
+        match self.reader.read_exact(&mut row) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let mut row = row.as_slice();
+        let timestamp = row.read_i64::<LittleEndian>()?;
+        let start_frequency = row.read_f64::<LittleEndian>()?;
+        let bin_width = row.read_f64::<LittleEndian>()?;
+        let mut dbm = vec![0; self.bins];
+        row.read_i16_into::<LittleEndian>(&mut dbm)?;
+
+        Ok(Some(Record {
+            timestamp: DateTime::from_timestamp_micros(timestamp)
+                .ok_or_else(|| anyhow::anyhow!("bad timestamp {}", timestamp))?,
+            start_frequency,
+            bin_width,
+            dbm,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    struct TempNpy(std::path::PathBuf);
+
+    impl TempNpy {
+        fn new(name: &str) -> Self {
+            TempNpy(std::env::temp_dir().join(format!(
+                "sdr-scraper-{}-{}.npy",
+                name,
+                std::process::id()
+            )))
+        }
+    }
+
+    impl Drop for TempNpy {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn row(seconds: i64) -> SpectrumRow {
+        SpectrumRow {
+            timestamp: Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap()
+                + chrono::Duration::seconds(seconds),
+            sequence: seconds as u32,
+            start_frequency: 7.0e6,
+            bin_width: 1000.0,
+            bins: vec![-120.4, -80.6, seconds as f32],
+        }
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let file = TempNpy::new("npy-round-trip");
+        let mut writer = NpyWriter::create(&file.0, 3).unwrap();
+        for seconds in 0..3 {
+            writer.write(&row(seconds)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let header = std::fs::read(&file.0).unwrap()[..HEADER_SIZE].to_vec();
+        assert!(String::from_utf8_lossy(&header).contains("'shape': (3,)"));
+
+        let mut reader = NpyReader::open(&file.0).unwrap();
+        assert_eq!((reader.bins, reader.rows), (3, 3));
+        for seconds in 0..3 {
+            let record = reader.next_record().unwrap().unwrap();
+            assert_eq!(record.timestamp, row(seconds).timestamp);
+            assert_eq!(record.start_frequency, 7.0e6);
+            assert_eq!(record.bin_width, 1000.0);
+            assert_eq!(record.dbm, vec![-120, -81, seconds as i16]);
+        }
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_a_file_that_was_never_finished() {
+        let file = TempNpy::new("npy-unfinished");
+        let mut writer = NpyWriter::create(&file.0, 3).unwrap();
+        for seconds in 0..FLUSH_ROWS as i64 + 2 {
+            writer.write(&row(seconds)).unwrap();
+        }
+        writer.flush().unwrap();
+        // Killed halfway through a row
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&file.0)
+            .unwrap()
+            .write_all(&[0; 10])
+            .unwrap();
+        drop(writer);
+
+        let mut reader = NpyReader::open(&file.0).unwrap();
+        assert_eq!(reader.rows, FLUSH_ROWS + 2);
+        let mut records = 0;
+        while reader.next_record().unwrap().is_some() {
+            records += 1;
+        }
+        assert_eq!(records, FLUSH_ROWS + 2);
+    }
+
+    #[test]
+    fn rewrites_the_header_as_it_goes() {
+        let file = TempNpy::new("npy-flush");
+        let mut writer = NpyWriter::create(&file.0, 3).unwrap();
+        for seconds in 0..FLUSH_ROWS as i64 {
+            writer.write(&row(seconds)).unwrap();
+        }
+
+        let written = std::fs::read(&file.0).unwrap();
+        let shape = format!("'shape': ({},)", FLUSH_ROWS);
+        assert!(String::from_utf8_lossy(&written[..HEADER_SIZE]).contains(&shape));
+        assert_eq!(
+            written.len() as u64,
+            HEADER_SIZE as u64 + FLUSH_ROWS * row_size(3)
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use super::font;
+use super::npy::NpyReader;
+
+/// Taller files have several rows folded into each line, keeping the strongest signal per bin.
+const MAX_LINES: u64 = 3600;
+
+const LEFT: usize = 44;
+const RIGHT: usize = 8;
+const TOP: usize = 14;
+const BOTTOM: usize = 22;
+const TICK: usize = 4;
+
+const BACKGROUND: [u8; 3] = [16, 16, 16];
+const FOREGROUND: [u8; 3] = [220, 220, 220];
+
+/// Colors from weakest to strongest, blended linearly in between.
+const PALETTE: [[u8; 3]; 5] = [
+    [0, 0, 0],
+    [0, 0, 160],
+    [0, 200, 255],
+    [255, 255, 0],
+    [255, 0, 0],
+];
+
+fn color(dbm: i16, min_db: i32, max_db: i32) -> [u8; 3] {
+    let t = ((dbm as f64 - min_db as f64) / (max_db - min_db).max(1) as f64).clamp(0.0, 1.0);
+    let position = t * (PALETTE.len() - 1) as f64;
+    let index = (position as usize).min(PALETTE.len() - 2);
+    let fraction = position - index as f64;
+    let (from, to) = (PALETTE[index], PALETTE[index + 1]);
+    [0, 1, 2].map(|i| (from[i] as f64 + (to[i] as f64 - from[i] as f64) * fraction) as u8)
+}
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: BACKGROUND.repeat(width * height),
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let offset = (y * self.width + x) * 3;
+            self.pixels[offset..offset + 3].copy_from_slice(&color);
+        }
+    }
+
+    fn text(&mut self, text: &str, x: usize, y: usize) {
+        font::draw(text, x, y, |x, y| self.set(x, y, FOREGROUND));
+    }
+}
+
+/// The first of 1, 2 and 5 times a power of ten that splits `span` into at most `count` steps.
+fn nice_step(span: f64, count: f64) -> f64 {
+    let rough = span / count;
+    let magnitude = 10f64.powf(rough.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= rough)
+        .unwrap_or(10.0 * magnitude)
+}
+
+/// Draws the rows of an NPY file from `NpyWriter` as a spectrogram, frequency across and time
+/// down, labelled in kHz and UTC.
+pub fn render(npy: &Path, png: &Path, name: &str, min_db: i32, max_db: i32) -> anyhow::Result<()> {
+    let mut reader = NpyReader::open(npy)?;
+    if reader.rows == 0 {
+        anyhow::bail!("{} has no rows", npy.display());
+    }
+    let bins = reader.bins;
+    let group = reader.rows.div_ceil(MAX_LINES);
+    let lines = reader.rows.div_ceil(group) as usize;
+
+    let mut canvas = Canvas::new(LEFT + bins + RIGHT, TOP + lines + BOTTOM);
+    let mut times: Vec<DateTime<Utc>> = Vec::with_capacity(lines);
+    let mut axis = None;
+    for line in 0..lines {
+        let mut peak = vec![i16::MIN; bins];
+        for _ in 0..group {
+            let Some(record) = reader.next_record()? else {
+                break;
+            };
+            if times.len() == line {
+                times.push(record.timestamp);
+            }
+            axis.get_or_insert((record.start_frequency, record.bin_width));
+            for (peak, dbm) in peak.iter_mut().zip(record.dbm) {
+                *peak = (*peak).max(dbm);
+            }
+        }
+        for (x, &dbm) in peak.iter().enumerate() {
+            canvas.set(LEFT + x, TOP + line, color(dbm, min_db, max_db));
+        }
+    }
+    let (start, bin_width) = axis.unwrap_or_default();
+    let bottom = TOP + times.len();
+
+    // Time down the left, a tick wherever a line crosses into a new interval
+    let first = times[0];
+    let last = *times.last().unwrap();
+    let span = (last - first).num_seconds().max(1);
+    let interval = [60, 120, 300, 600, 900, 1800, 3600, 7200, 21600]
+        .into_iter()
+        .find(|interval| span / interval <= 12)
+        .unwrap_or(86400);
+    let mut last_label = None;
+    for (line, pair) in times.windows(2).enumerate() {
+        if pair[0].timestamp().div_euclid(interval) == pair[1].timestamp().div_euclid(interval) {
+            continue;
+        }
+        let y = TOP + line + 1;
+        for x in LEFT - TICK..LEFT {
+            canvas.set(x, y, FOREGROUND);
+        }
+        if last_label.is_none_or(|last| y >= last + font::HEIGHT + 2) {
+            let label = pair[1].format("%H:%M").to_string();
+            let x = LEFT - TICK - 2 - font::text_width(&label);
+            canvas.text(&label, x, y.saturating_sub(font::HEIGHT / 2));
+            last_label = Some(y);
+        }
+    }
+
+    // Frequency along the bottom
+    let step = nice_step(bins as f64 * bin_width, 8.0);
+    let decimals = if step >= 1000.0 {
+        0
+    } else {
+        3 - step.log10().floor() as usize
+    };
+    let mut frequency = (start / step).ceil() * step;
+    while frequency < start + bins as f64 * bin_width {
+        let x = LEFT + ((frequency - start) / bin_width) as usize;
+        for y in bottom..bottom + TICK {
+            canvas.set(x, y, FOREGROUND);
+        }
+        let label = format!("{:.*}", decimals, frequency / 1000.0);
+        let width = font::text_width(&label);
+        canvas.text(&label, x.saturating_sub(width / 2), bottom + TICK + 2);
+        frequency += step;
+    }
+    canvas.text("KHZ", 2, bottom + TICK + 2);
+
+    let title = format!(
+        "{} {} - {} UTC",
+        name,
+        first.format("%Y-%m-%d %H:%M"),
+        last.format("%H:%M")
+    );
+    canvas.text(&title, LEFT, 3);
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(png)?),
+        canvas.width as u32,
+        canvas.height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&canvas.pixels)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::sdr::kiwi::SpectrumRow;
+    use crate::spectrum::npy::NpyWriter;
+
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("sdr-scraper-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Writes `rows` rows a second apart, 100 bins at -110 dBm with bin 50 at -10 dBm on every
+    /// third row.
+    fn write_npy(path: &Path, rows: i64) {
+        let mut writer = NpyWriter::create(path, 100).unwrap();
+        for line in 0..rows {
+            let mut bins = vec![-110.0; 100];
+            if line % 3 == 0 {
+                bins[50] = -10.0;
+            }
+            writer
+                .write(&SpectrumRow {
+                    timestamp: Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap()
+                        + chrono::Duration::seconds(line),
+                    sequence: line as u32,
+                    start_frequency: 7.0e6,
+                    bin_width: 100.0,
+                    bins,
+                })
+                .unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn read_png(path: &Path) -> (usize, usize, Vec<u8>) {
+        let decoder = png::Decoder::new(File::open(path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        (info.width as usize, info.height as usize, pixels)
+    }
+
+    fn pixel(pixels: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+        let offset = (y * width + x) * 3;
+        pixels[offset..offset + 3].try_into().unwrap()
+    }
+
+    #[test]
+    fn draws_a_line_per_row() {
+        let dir = TempDir::new("render-lines");
+        let (npy, png) = (dir.0.join("test.npy"), dir.0.join("test.png"));
+        write_npy(&npy, 6);
+        render(&npy, &png, "test", -110, -10).unwrap();
+
+        let (width, height, pixels) = read_png(&png);
+        assert_eq!((width, height), (LEFT + 100 + RIGHT, TOP + 6 + BOTTOM));
+        for line in 0..6 {
+            let expected = if line % 3 == 0 {
+                PALETTE[4]
+            } else {
+                PALETTE[0]
+            };
+            assert_eq!(pixel(&pixels, width, LEFT + 50, TOP + line), expected);
+            assert_eq!(pixel(&pixels, width, LEFT + 49, TOP + line), PALETTE[0]);
+        }
+    }
+
+    #[test]
+    fn folds_long_files_keeping_the_peaks() {
+        let dir = TempDir::new("render-folds");
+        let (npy, png) = (dir.0.join("test.npy"), dir.0.join("test.png"));
+        // Three rows to a line, one of which has the carrier
+        write_npy(&npy, MAX_LINES as i64 * 3);
+        render(&npy, &png, "test", -110, -10).unwrap();
+
+        let (width, height, pixels) = read_png(&png);
+        assert_eq!(height, TOP + MAX_LINES as usize + BOTTOM);
+        for line in [0, 1, MAX_LINES as usize - 1] {
+            assert_eq!(pixel(&pixels, width, LEFT + 50, TOP + line), PALETTE[4]);
+        }
+    }
+
+    #[test]
+    fn fails_on_an_empty_file() {
+        let dir = TempDir::new("render-empty");
+        let (npy, png) = (dir.0.join("test.npy"), dir.0.join("test.png"));
+        write_npy(&npy, 0);
+        assert!(render(&npy, &png, "test", -110, -10).is_err());
+        assert!(!png.exists());
+    }
+}
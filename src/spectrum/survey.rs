@@ -0,0 +1,323 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::SpectrumSink;
+use crate::{config::SurveyConfig, sdr::kiwi::SpectrumRow};
+
+/// Where in a channel's sorted levels its noise floor is read off.
+const NOISE_PERCENTILE: f64 = 0.1;
+
+/// One channel over one interval of a survey.
+#[derive(Clone, Debug, Serialize)]
+pub struct SurveyRecord {
+    /// Start of the interval.
+    pub time: DateTime<Utc>,
+    /// Center of the channel, in Hz.
+    pub frequency: f64,
+    /// 10th percentile of the channel's level, in dBm.
+    pub noise_floor: f32,
+    /// Percentage of the lines the channel was occupied in.
+    pub occupancy: f32,
+    /// Number of lines the channel was measured in.
+    pub lines: u32,
+}
+
+/// Results of a survey, oldest first, shared with the HTTP API.
+#[derive(Clone, Default)]
+pub struct SurveyTable {
+    records: Arc<RwLock<VecDeque<SurveyRecord>>>,
+}
+
+impl SurveyTable {
+    /// Adds `records`, dropping those more than `retention` older than the newest.
+    fn append(&self, records: Vec<SurveyRecord>, retention: Duration) {
+        let mut table = self.records.write().unwrap();
+        table.extend(records);
+
+        let cutoff = table.back().and_then(|newest| {
+            newest
+                .time
+                .checked_sub_signed(chrono::Duration::from_std(retention).ok()?)
+        });
+        if let Some(cutoff) = cutoff {
+            while table.front().is_some_and(|record| record.time < cutoff) {
+                table.pop_front();
+            }
+        }
+    }
+
+    /// Records of intervals starting at `since` or later, for channels between `low` and `high` Hz.
+    pub fn query(
+        &self,
+        since: Option<DateTime<Utc>>,
+        low: Option<f64>,
+        high: Option<f64>,
+    ) -> Vec<SurveyRecord> {
+        self.records
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|record| {
+                since.is_none_or(|since| record.time >= since)
+                    && low.is_none_or(|low| record.frequency >= low)
+                    && high.is_none_or(|high| record.frequency <= high)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Clone, Default)]
+struct Channel {
+    /// The strongest bin of every line the channel was in.
+    levels: Vec<f32>,
+    /// Lines where that stood `threshold` above the noise.
+    occupied: u32,
+}
+
+/// Splits the surveyed range into channels and works out, for every interval, how often each
+/// was occupied and where its noise floor sits.
+///
+/// A channel is occupied in a line when its strongest bin is `threshold` dB above the line's
+/// median, most of any band being empty at any one time. Channels are counted in the line
+/// holding their center, so steps that meet mid-channel don't count it twice.
+pub struct Survey {
+    config: SurveyConfig,
+    table: SurveyTable,
+    /// The interval being summed up, as a count of intervals since the epoch.
+    interval: Option<i64>,
+    channels: Vec<Channel>,
+}
+
+impl Survey {
+    pub fn new(config: SurveyConfig, table: SurveyTable) -> Self {
+        // Nothing to split a range into without a width, checked before a survey starts
+        let count = if config.channel_width > 0.0 {
+            ((config.end - config.start) / config.channel_width)
+                .ceil()
+                .max(0.0) as usize
+        } else {
+            0
+        };
+        Survey {
+            config,
+            table,
+            interval: None,
+            channels: vec![Channel::default(); count],
+        }
+    }
+
+    fn interval_seconds(&self) -> i64 {
+        self.config.interval.as_secs().max(1) as i64
+    }
+
+    fn channel_center(&self, index: usize) -> f64 {
+        self.config.start + (index as f64 + 0.5) * self.config.channel_width
+    }
+
+    /// Sums up the interval so far into the table and starts over.
+    fn flush(&mut self) {
+        let Some(interval) = self.interval.take() else {
+            return;
+        };
+        let time =
+            DateTime::from_timestamp(interval * self.interval_seconds(), 0).unwrap_or_default();
+
+        let mut records = Vec::new();
+        for index in 0..self.channels.len() {
+            let mut channel = std::mem::take(&mut self.channels[index]);
+            if channel.levels.is_empty() {
+                continue;
+            }
+            channel.levels.sort_by(f32::total_cmp);
+            let lines = channel.levels.len();
+            records.push(SurveyRecord {
+                time,
+                frequency: self.channel_center(index),
+                noise_floor: channel.levels[((lines - 1) as f64 * NOISE_PERCENTILE) as usize],
+                occupancy: channel.occupied as f32 * 100.0 / lines as f32,
+                lines: lines as u32,
+            });
+        }
+        self.table.append(records, self.config.retention);
+    }
+}
+
+impl SpectrumSink for Survey {
+    fn write(&mut self, row: &SpectrumRow) -> anyhow::Result<()> {
+        let interval = row
+            .timestamp
+            .timestamp()
+            .div_euclid(self.interval_seconds());
+        if self.interval.is_some_and(|current| current != interval) {
+            self.flush();
+        }
+        self.interval = Some(interval);
+
+        let mut sorted = row.bins.clone();
+        sorted.sort_by(f32::total_cmp);
+        let Some(&noise) = sorted.get(sorted.len() / 2) else {
+            return Ok(());
+        };
+
+        // Strongest bin per channel, in order as the bins only go up in frequency
+        let mut peaks: Vec<(usize, f32)> = Vec::new();
+        for (index, &dbm) in row.bins.iter().enumerate() {
+            let offset = (row.frequency(index) - self.config.start) / self.config.channel_width;
+            if offset < 0.0 || offset as usize >= self.channels.len() {
+                continue;
+            }
+            match peaks.last_mut() {
+                Some((channel, peak)) if *channel == offset as usize => *peak = peak.max(dbm),
+                _ => peaks.push((offset as usize, dbm)),
+            }
+        }
+
+        let end = row.start_frequency + row.bins.len() as f64 * row.bin_width;
+        for (index, peak) in peaks {
+            let center = self.channel_center(index);
+            if center < row.start_frequency || center >= end {
+                continue;
+            }
+            let channel = &mut self.channels[index];
+            channel.levels.push(peak);
+            if peak >= noise + self.config.threshold {
+                channel.occupied += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.flush();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// Four 5 kHz channels from 7.000 to 7.020 MHz, summed up every minute.
+    fn survey() -> (Survey, SurveyTable) {
+        let table = SurveyTable::default();
+        let config = SurveyConfig {
+            start: 7.0e6,
+            end: 7.02e6,
+            channel_width: 5000.0,
+            threshold: 10.0,
+            interval: Duration::from_secs(60),
+            ..SurveyConfig::default()
+        };
+        (Survey::new(config, table.clone()), table)
+    }
+
+    fn row(seconds: i64, start_frequency: f64, bin_width: f64, bins: Vec<f32>) -> SpectrumRow {
+        SpectrumRow {
+            timestamp: Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap()
+                + chrono::Duration::seconds(seconds),
+            sequence: 0,
+            start_frequency,
+            bin_width,
+            bins,
+        }
+    }
+
+    #[test]
+    fn sums_up_noise_floor_and_occupancy() {
+        let (mut survey, table) = survey();
+        for line in 0..21 {
+            // 1 kHz bins at -120 dBm, with channel 0 rising a dB a line and a carrier in
+            // channel 1 for the first 7 lines
+            let mut bins = vec![-120.0; 20];
+            bins[2] = -120.0 + line as f32;
+            if line < 7 {
+                bins[7] = -90.0;
+            }
+            survey.write(&row(line, 7.0e6, 1000.0, bins)).unwrap();
+        }
+        assert!(table.query(None, None, None).is_empty());
+        survey.close().unwrap();
+
+        let records = table.query(None, None, None);
+        assert_eq!(records.len(), 4);
+        for (record, frequency) in records.iter().zip([7.0025e6, 7.0075e6, 7.0125e6, 7.0175e6]) {
+            assert_eq!(record.frequency, frequency);
+            assert_eq!(record.lines, 21);
+            assert_eq!(record.time, row(0, 0.0, 0.0, vec![]).timestamp);
+        }
+        // The third lowest of 21 lines, and the 11 lines at -110 dBm or above
+        assert_eq!(records[0].noise_floor, -118.0);
+        assert!((records[0].occupancy - 11.0 * 100.0 / 21.0).abs() < 1e-4);
+        assert_eq!(records[1].noise_floor, -120.0);
+        assert!((records[1].occupancy - 7.0 * 100.0 / 21.0).abs() < 1e-4);
+        assert_eq!(records[2].occupancy, 0.0);
+
+        // Nothing more to flush
+        survey.close().unwrap();
+        assert_eq!(table.query(None, None, None).len(), 4);
+    }
+
+    #[test]
+    fn flushes_when_the_interval_rolls_over() {
+        let (mut survey, table) = survey();
+        survey
+            .write(&row(0, 7.0e6, 1000.0, vec![-120.0; 20]))
+            .unwrap();
+        survey
+            .write(&row(59, 7.0e6, 1000.0, vec![-120.0; 20]))
+            .unwrap();
+        assert!(table.query(None, None, None).is_empty());
+
+        survey
+            .write(&row(60, 7.0e6, 1000.0, vec![-110.0; 20]))
+            .unwrap();
+        let first = table.query(None, None, None);
+        assert_eq!(first.len(), 4);
+        assert!(first.iter().all(|record| record.lines == 2));
+
+        survey.close().unwrap();
+        let second = table.query(Some(row(60, 0.0, 0.0, vec![]).timestamp), None, None);
+        assert_eq!(second.len(), 4);
+        assert!(second
+            .iter()
+            .all(|record| record.lines == 1 && record.noise_floor == -110.0));
+    }
+
+    #[test]
+    fn counts_each_channel_once_across_steps() {
+        let (mut survey, table) = survey();
+        // Two steps of 500 Hz bins meeting at 7.0095 MHz, inside channel 1. Its center is only in
+        // the first, so the carrier the second sees at its edge isn't counted.
+        survey
+            .write(&row(0, 7.0e6, 500.0, vec![-120.0; 19]))
+            .unwrap();
+        let mut bins = vec![-120.0; 19];
+        bins[0] = -80.0;
+        survey.write(&row(1, 7.0095e6, 500.0, bins)).unwrap();
+        survey.close().unwrap();
+
+        let records = table.query(None, None, None);
+        assert_eq!(records.len(), 4);
+        assert!(records.iter().all(|record| record.lines == 1));
+        assert_eq!(records[1].occupancy, 0.0);
+        assert_eq!(records[1].noise_floor, -120.0);
+    }
+
+    #[test]
+    fn skips_lines_off_the_range() {
+        let (mut survey, table) = survey();
+        survey.write(&row(0, 7.0e6, 1000.0, vec![])).unwrap();
+        survey
+            .write(&row(0, 14.0e6, 1000.0, vec![-120.0; 20]))
+            .unwrap();
+        survey.close().unwrap();
+        assert!(table.query(None, None, None).is_empty());
+    }
+}
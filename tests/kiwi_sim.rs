@@ -0,0 +1,141 @@
+//! Drives the Kiwi scraper against kiwi-sim.
+
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use sdr_scraper::audio::{OverflowPolicy, WriterPool};
+use sdr_scraper::events::{EventBus, EventKind, StationEvent, StationState};
+use sdr_scraper::sdr::kiwi::{KiwiSDRScraper, KiwiSDRScraperSettings};
+use sdr_scraper::sdr::{Mode, SDRScraper};
+use tokio::sync::broadcast;
+use url::Url;
+
+/// A running kiwi-sim, killed when dropped.
+struct Simulator(Child);
+
+impl Simulator {
+    async fn start(port: u16) -> Simulator {
+        let child = Command::new(env!("CARGO_BIN_EXE_kiwi-sim"))
+            .args(["--port", &port.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let simulator = Simulator(child);
+
+        let url = format!("http://127.0.0.1:{}/VER", port);
+        for _ in 0..100 {
+            if reqwest::get(&url).await.is_ok() {
+                return simulator;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("kiwi-sim never came up");
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Waits for an event `wanted` accepts, failing after `timeout`.
+async fn expect(
+    events: &mut broadcast::Receiver<StationEvent>,
+    timeout: Duration,
+    what: &str,
+    wanted: impl Fn(&EventKind) -> bool,
+) {
+    let found = tokio::time::timeout(timeout, async {
+        loop {
+            if wanted(&events.recv().await.unwrap().kind) {
+                return;
+            }
+        }
+    })
+    .await;
+    assert!(found.is_ok(), "no {} within {:?}", what, timeout);
+}
+
+fn ready(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::StateChanged {
+            state: StationState::Ready
+        }
+    )
+}
+
+#[tokio::test]
+async fn kiwi_scraper_keeps_reconnecting() {
+    let port = free_port();
+    let pool = WriterPool::new(1, 64, OverflowPolicy::Block, 4096);
+    let bus = EventBus::new();
+    let mut events = bus.subscribe();
+    let mut scraper = KiwiSDRScraper::new(
+        KiwiSDRScraperSettings {
+            name: "test".to_string(),
+            endpoint: Url::parse(&format!("ws://127.0.0.1:{}/", port)).unwrap(),
+            password: None,
+            station: Mode::USB.tuning(7_100_000.0, None, None),
+            agc: true,
+            location: "test".to_string(),
+            identity: "test".to_string(),
+            sinks: Vec::new(),
+            capture: None,
+            waterfall: None,
+        },
+        &pool,
+        &bus,
+    );
+    scraper.start().await.unwrap();
+
+    // Nothing is listening yet, so the first connect fails and is retried
+    expect(
+        &mut events,
+        Duration::from_secs(5),
+        "failed connect",
+        |kind| matches!(kind, EventKind::Disconnected { .. }),
+    )
+    .await;
+    let _simulator = Simulator::start(port).await;
+    expect(&mut events, Duration::from_secs(10), "reconnect", |kind| {
+        matches!(kind, EventKind::Reconnected)
+    })
+    .await;
+    expect(&mut events, Duration::from_secs(5), "audio", ready).await;
+
+    // Cut off without a close frame, then back
+    reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{}/sim/drop", port))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    expect(&mut events, Duration::from_secs(5), "drop", |kind| {
+        matches!(kind, EventKind::Disconnected { .. })
+    })
+    .await;
+    expect(&mut events, Duration::from_secs(10), "reconnect", |kind| {
+        matches!(kind, EventKind::Reconnected)
+    })
+    .await;
+    expect(&mut events, Duration::from_secs(5), "audio", ready).await;
+
+    scraper.stop().await.unwrap();
+    tokio::task::spawn_blocking(move || pool.shutdown())
+        .await
+        .unwrap();
+}